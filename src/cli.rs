@@ -0,0 +1,557 @@
+//! # Command-Line Interface
+//!
+//! Besides running the HTTP service (`svg2png serve`, the default), the binary
+//! converts local files with the same [`Renderer`] and the same options as the
+//! endpoints, so designers get exactly the output the service would produce:
+//!
+//! ```text
+//! svg2png convert logo.svg -o logo.png --dpi 300
+//! svg2png convert 'icons/*.svg' --out-dir build/icons --width 64 --format webp
+//! svg2png transparent scan.png -o scan-clean.png --fuzz 10 --seed corners
+//! cat logo.svg | svg2png convert - > logo.png
+//! ```
+//!
+//! Inputs are file paths, glob patterns or `-` for stdin. Without `--output`
+//! or `--out-dir`, each file is written next to its input (with the output
+//! format's extension for `convert`, and a `-transparent` suffix for
+//! `transparent`) and stdin input is written to stdout. With `--out-dir`,
+//! files matched by a pattern keep their path relative to the pattern's
+//! literal prefix (`icons/**/*.svg` writes `icons/a/x.svg` to `<dir>/a/x.png`),
+//! and inputs that would be written to the same file are rejected up front.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context};
+use clap::{Args, Parser, Subcommand};
+use resvg::tiny_skia::NonZeroRect;
+use svg2png::config::{FontConfig, Limits};
+use svg2png::encode::OutputFormat;
+use svg2png::sizing::{self, Alignment, FitMode, Padding};
+use svg2png::transparency::{KeyMode, KeyOptions, Seed};
+use svg2png::{EncodedImage, RenderError, RenderOptions, Renderer};
+
+use crate::server::params::{parse_color, parse_dimension, parse_feather, parse_fuzz, parse_quality};
+
+/// The argument standing for stdin (as input) or stdout (as output).
+const STDIO_ARG: &str = "-";
+/// File name suffix used by `transparent` when writing next to the input.
+const TRANSPARENT_SUFFIX: &str = "-transparent";
+
+/// Converts SVG images and removes PNG backgrounds, as a service or locally.
+#[derive(Debug, Parser)]
+#[command(name = "svg2png", version, about)]
+pub struct Cli {
+    /// The command to run. Defaults to `serve`.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// A `svg2png` subcommand.
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the HTTP service (the default). Configured via `SVG2PNG_*` environment variables.
+    Serve,
+    /// Convert SVG files to PNG, JPEG, WebP or AVIF, like `/svg-to-image`.
+    Convert(ConvertArgs),
+    /// Make the background of PNG files transparent, like `/png-to-transparent`.
+    Transparent(TransparentArgs),
+}
+
+/// Where inputs are read from and outputs are written to.
+#[derive(Debug, Args)]
+pub struct IoArgs {
+    /// Input files or glob patterns (quote them to bypass the shell), or `-` for stdin.
+    #[arg(default_value = STDIO_ARG)]
+    inputs: Vec<String>,
+    /// Output file, or `-` for stdout. Requires a single input.
+    #[arg(short, long, conflicts_with = "out_dir")]
+    output: Option<PathBuf>,
+    /// Directory to write the outputs to, named after their inputs (keeping the
+    /// directories below a pattern's literal prefix). Created if missing.
+    #[arg(short = 'd', long)]
+    out_dir: Option<PathBuf>,
+}
+
+/// Options of `svg2png convert`; see `/svg-to-image` for details.
+#[derive(Debug, Args)]
+pub struct ConvertArgs {
+    #[command(flatten)]
+    io: IoArgs,
+    /// Output DPI, used for scaling when no explicit size is given.
+    #[arg(long, default_value_t = svg2png::renderer::DEFAULT_DPI, value_parser = parse_dpi)]
+    dpi: f32,
+    /// Output width in pixels.
+    #[arg(long, value_parser = |v: &str| parse_dimension("width", v))]
+    width: Option<u32>,
+    /// Output height in pixels.
+    #[arg(long, value_parser = |v: &str| parse_dimension("height", v))]
+    height: Option<u32>,
+    /// How the SVG fits a `--width` x `--height` canvas: contain, cover, fill or scale-down.
+    #[arg(long, default_value = "contain")]
+    fit: FitMode,
+    /// Content alignment inside the canvas, e.g. center or top-left.
+    #[arg(long, default_value = "center")]
+    align: Alignment,
+    /// CSS color the canvas is filled with before rendering.
+    #[arg(long, value_parser = |v: &str| parse_color("background", v))]
+    background: Option<svgtypes::Color>,
+    /// Composite onto an opaque background and drop the alpha channel.
+    #[arg(long)]
+    flatten: bool,
+    /// Output format: png, jpeg, webp or avif. Defaults to the `--output` extension, or png.
+    #[arg(long)]
+    format: Option<OutputFormat>,
+    /// Lossy encoding quality (1-100).
+    #[arg(long, value_parser = |v: &str| parse_quality("quality", v))]
+    quality: Option<u8>,
+    /// Force lossless WebP encoding.
+    #[arg(long)]
+    lossless: bool,
+    /// Render only the element with this ID, cropped to its bounding box.
+    #[arg(long, conflicts_with_all = ["viewbox", "trim"])]
+    id: Option<String>,
+    /// Render the region `x,y,w,h` (in SVG user units) instead of the SVG's viewBox.
+    #[arg(long, value_parser = sizing::parse_view_box, conflicts_with = "trim")]
+    viewbox: Option<NonZeroRect>,
+    /// Crop to the bounding box of the drawn content.
+    #[arg(long)]
+    trim: bool,
+    /// Space around the rendered region in canvas pixels: `all` or `top,right,bottom,left`.
+    #[arg(long, default_value = "0")]
+    padding: Padding,
+    /// CSS file injected while parsing, e.g. a dark theme.
+    #[arg(long)]
+    css: Option<PathBuf>,
+    /// CSS color that `currentColor` resolves to.
+    #[arg(long, value_parser = |v: &str| parse_color("color", v))]
+    color: Option<svgtypes::Color>,
+}
+
+/// Options of `svg2png transparent`; see `/png-to-transparent` for details.
+#[derive(Debug, Args)]
+pub struct TransparentArgs {
+    #[command(flatten)]
+    io: IoArgs,
+    /// Color tolerance in percent, e.g. 10 or 10%.
+    #[arg(long, default_value = "5", value_parser = |v: &str| parse_fuzz("fuzz", v))]
+    fuzz: f32,
+    /// Seed point `x,y`, or `corners` for all four corners. May be repeated.
+    #[arg(long)]
+    seed: Vec<Seed>,
+    /// CSS color to key out instead of the color under each seed.
+    #[arg(long, value_parser = |v: &str| parse_color("color", v))]
+    color: Option<svgtypes::Color>,
+    /// flood (contiguous with a seed) or global (every matching pixel).
+    #[arg(long, default_value = "flood")]
+    mode: KeyMode,
+    /// Radius in pixels of edge refinement around the removed area.
+    #[arg(long, default_value_t = 0, value_parser = |v: &str| parse_feather("feather", v))]
+    feather: u32,
+}
+
+/// Parses a positive DPI value.
+fn parse_dpi(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(v) if v > 0.0 => Ok(v),
+        _ => Err(format!("'{}' must be a positive number", value)),
+    }
+}
+
+/// An input to process.
+#[derive(Debug, Clone, PartialEq)]
+enum Input {
+    /// Standard input.
+    Stdin,
+    /// A file.
+    File {
+        /// The file's path.
+        path: PathBuf,
+        /// The directory its `--out-dir` output path is relative to: the
+        /// literal prefix of the glob pattern it matched, or its parent.
+        base: PathBuf,
+    },
+}
+
+impl Input {
+    /// A file given by its path rather than matched by a pattern.
+    fn file(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let base = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Input::File { path, base }
+    }
+
+    /// Reads the whole input.
+    fn read(&self) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Input::Stdin => {
+                let mut data = Vec::new();
+                std::io::stdin().lock().read_to_end(&mut data).context("Failed to read stdin")?;
+                Ok(data)
+            }
+            Input::File { path, .. } => std::fs::read(path).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// A name for messages.
+    fn name(&self) -> String {
+        match self {
+            Input::Stdin => "<stdin>".to_string(),
+            Input::File { path, .. } => path.display().to_string(),
+        }
+    }
+}
+
+/// Where an output is written.
+#[derive(Debug, Clone, PartialEq)]
+enum Output {
+    /// Standard output.
+    Stdout,
+    /// A file.
+    File(PathBuf),
+}
+
+impl Output {
+    /// Writes `data`, creating the parent directory if needed.
+    fn write(&self, data: &[u8]) -> anyhow::Result<()> {
+        match self {
+            Output::Stdout => {
+                let mut stdout = std::io::stdout().lock();
+                stdout.write_all(data).and_then(|_| stdout.flush()).context("Failed to write stdout")
+            }
+            Output::File(path) => {
+                if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+                }
+                std::fs::write(path, data).with_context(|| format!("Failed to write {}", path.display()))
+            }
+        }
+    }
+}
+
+/// Expands the input arguments: `-` is stdin, patterns are globbed (sorted), anything else is a path.
+fn expand_inputs(args: &[String]) -> anyhow::Result<Vec<Input>> {
+    let mut inputs = Vec::new();
+    for arg in args {
+        if arg == STDIO_ARG {
+            inputs.push(Input::Stdin);
+        } else if arg.contains(['*', '?', '[']) {
+            let mut matches = glob::glob(arg)
+                .with_context(|| format!("Invalid glob pattern '{}'", arg))?
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("Failed to expand '{}'", arg))?;
+            if matches.is_empty() {
+                bail!("No files match '{}'", arg);
+            }
+            matches.sort();
+            let base = glob_base(arg);
+            inputs.extend(matches.into_iter().map(|path| Input::File { path, base: base.clone() }));
+        } else {
+            inputs.push(Input::file(arg));
+        }
+    }
+    Ok(inputs)
+}
+
+/// The literal directory prefix of a glob pattern, i.e. its components before
+/// the first one containing a wildcard.
+fn glob_base(pattern: &str) -> PathBuf {
+    let mut base = PathBuf::new();
+    let mut components = Path::new(pattern).components().peekable();
+    while let Some(component) = components.next() {
+        // The last component names the files, even without a wildcard.
+        if components.peek().is_none() || component.as_os_str().to_string_lossy().contains(['*', '?', '[']) {
+            break;
+        }
+        base.push(component);
+    }
+    base
+}
+
+/// Determines where the output for `input` goes.
+///
+/// # Arguments
+///
+/// * `io` - The `--output` / `--out-dir` arguments.
+/// * `input` - The input being processed.
+/// * `suffix` - Appended to the input's file stem when writing next to the input.
+/// * `extension` - The output file extension.
+fn output_for(io: &IoArgs, input: &Input, suffix: &str, extension: &str) -> anyhow::Result<Output> {
+    if let Some(output) = &io.output {
+        return Ok(if output.as_os_str() == STDIO_ARG { Output::Stdout } else { Output::File(output.clone()) });
+    }
+    let path = match (input, &io.out_dir) {
+        (Input::Stdin, None) => return Ok(Output::Stdout),
+        (Input::Stdin, Some(_)) => bail!("Reading from stdin requires --output"),
+        (Input::File { path, base }, Some(dir)) => {
+            let relative = path.strip_prefix(base).unwrap_or_else(|_| Path::new(path.file_name().unwrap_or_default()));
+            dir.join(relative).with_file_name(file_name(path, "", extension))
+        }
+        (Input::File { path, .. }, None) => path.with_file_name(file_name(path, suffix, extension)),
+    };
+    if same_file(&path, input) {
+        bail!("Refusing to overwrite input {}; use --output or --out-dir", path.display());
+    }
+    Ok(Output::File(path))
+}
+
+/// Builds `<stem><suffix>.<extension>` from an input path.
+fn file_name(path: &Path, suffix: &str, extension: &str) -> String {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    format!("{}{}.{}", stem, suffix, extension)
+}
+
+/// Whether `path` refers to the file `input` was read from.
+fn same_file(path: &Path, input: &Input) -> bool {
+    let Input::File { path: input, .. } = input else {
+        return false;
+    };
+    // Compare lexically first (`./a.png` is `a.png`), then resolve links for existing files.
+    let lexical = |p: &Path| p.components().filter(|c| *c != Component::CurDir).collect::<PathBuf>();
+    lexical(path) == lexical(input)
+        || matches!((path.canonicalize(), input.canonicalize()), (Ok(a), Ok(b)) if a == b)
+}
+
+/// Runs `process` on every input and writes each result, continuing past failures.
+///
+/// # Returns
+///
+/// * `Ok(())` - If every input was processed.
+/// * `Err(anyhow::Error)` - If the arguments are invalid or any input failed.
+///   Each failure is reported on stderr as it happens.
+fn run_batch(
+    io: &IoArgs,
+    suffix: &str,
+    extension: &str,
+    process: impl Fn(&[u8]) -> Result<EncodedImage, RenderError>,
+) -> anyhow::Result<()> {
+    let inputs = expand_inputs(&io.inputs)?;
+    if io.output.is_some() && inputs.len() > 1 {
+        bail!("--output requires a single input, got {}; use --out-dir instead", inputs.len());
+    }
+    if inputs.iter().filter(|input| **input == Input::Stdin).count() > 1 {
+        bail!("stdin ('-') can only be read once");
+    }
+    let outputs: Vec<_> = inputs.iter().map(|input| output_for(io, input, suffix, extension)).collect();
+    check_distinct_outputs(&inputs, &outputs)?;
+
+    let mut failed = 0;
+    for (input, output) in inputs.iter().zip(outputs) {
+        let result = output.and_then(|output| {
+            let data = input.read()?;
+            let image = process(&data).map_err(|e| anyhow!("{} ({})", e, e.code()))?;
+            output.write(&image.data)
+        });
+        if let Err(e) = result {
+            eprintln!("svg2png: {}: {:#}", input.name(), e);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        bail!("{} of {} inputs failed", failed, inputs.len());
+    }
+    Ok(())
+}
+
+/// Fails if two inputs would be written to the same file, before anything is written.
+fn check_distinct_outputs(inputs: &[Input], outputs: &[anyhow::Result<Output>]) -> anyhow::Result<()> {
+    let mut written_by = HashMap::new();
+    for (input, output) in inputs.iter().zip(outputs) {
+        let Ok(Output::File(path)) = output else {
+            continue;
+        };
+        if let Some(other) = written_by.insert(path, input) {
+            bail!(
+                "{} and {} would both be written to {}; use separate --out-dir runs",
+                other.name(),
+                input.name(),
+                path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Builds a renderer from the same environment variables as the service.
+fn renderer_from_env() -> anyhow::Result<Renderer> {
+    Ok(Renderer::new(FontConfig::from_env()?, Limits::from_env()?))
+}
+
+/// Runs `svg2png convert`.
+pub fn convert(args: ConvertArgs) -> anyhow::Result<()> {
+    // An explicit format wins; otherwise `-o out.webp` picks WebP.
+    let format = args
+        .format
+        .or_else(|| {
+            let extension = args.io.output.as_deref()?.extension()?.to_str()?;
+            extension.to_ascii_lowercase().parse().ok()
+        })
+        .unwrap_or_default();
+    let options = RenderOptions {
+        dpi: args.dpi,
+        width: args.width,
+        height: args.height,
+        fit: args.fit,
+        align: args.align,
+        background: args.background,
+        flatten: args.flatten,
+        format,
+        quality: args.quality,
+        lossless: args.lossless,
+        deadline: None,
+        element: args.id,
+        view_box: args.viewbox,
+        trim: args.trim,
+        padding: args.padding,
+        style_sheet: args
+            .css
+            .as_deref()
+            .map(|path| std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display())))
+            .transpose()?,
+        color: args.color,
+    };
+
+    let renderer = renderer_from_env()?;
+    run_batch(&args.io, "", format.extension(), |svg| renderer.render(svg, &options))
+}
+
+/// Runs `svg2png transparent`.
+pub fn transparent(args: TransparentArgs) -> anyhow::Result<()> {
+    let options = KeyOptions {
+        fuzz: args.fuzz,
+        seeds: args.seed,
+        color: args.color.map(|c| [c.red, c.green, c.blue, c.alpha]),
+        mode: args.mode,
+        feather: args.feather,
+        deadline: None,
+    };
+
+    let renderer = renderer_from_env()?;
+    run_batch(&args.io, TRANSPARENT_SUFFIX, OutputFormat::Png.extension(), |png| {
+        renderer.make_transparent(png, &options)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Command {
+        Cli::try_parse_from(args).unwrap().command.unwrap()
+    }
+
+    #[test]
+    fn test_convert_args_match_query_parameters() {
+        let Command::Convert(args) = parse(&[
+            "svg2png", "convert", "in.svg", "--width", "64", "--fit", "cover", "--background", "#ff0000", "--quality", "90",
+        ]) else {
+            panic!("expected convert");
+        };
+        assert_eq!(args.io.inputs, ["in.svg"]);
+        assert_eq!((args.width, args.height, args.fit), (Some(64), None, FitMode::Cover));
+        assert_eq!(args.background.map(|c| c.red), Some(255));
+        assert_eq!(args.dpi, svg2png::renderer::DEFAULT_DPI);
+
+        // Invalid values are rejected with the same rules as the endpoints.
+        for invalid in [["--width", "0"], ["--quality", "101"], ["--fit", "squash"], ["--dpi", "-1"]] {
+            assert!(Cli::try_parse_from(["svg2png", "convert", "in.svg", invalid[0], invalid[1]]).is_err());
+        }
+        assert!(Cli::try_parse_from(["svg2png", "transparent", "--feather", "65"]).is_err());
+        assert!(matches!(parse(&["svg2png", "serve"]), Command::Serve));
+    }
+
+    #[test]
+    fn test_output_paths() {
+        let io = |output: Option<&str>, out_dir: Option<&str>| IoArgs {
+            inputs: Vec::new(),
+            output: output.map(PathBuf::from),
+            out_dir: out_dir.map(PathBuf::from),
+        };
+        let input = Input::file("art/logo.svg");
+
+        let next_to_input = output_for(&io(None, None), &input, "", "png").unwrap();
+        assert_eq!(next_to_input, Output::File(PathBuf::from("art/logo.png")));
+        let in_dir = output_for(&io(None, Some("out")), &input, "-transparent", "webp").unwrap();
+        assert_eq!(in_dir, Output::File(PathBuf::from("out/logo.webp")));
+        assert_eq!(output_for(&io(Some("-"), None), &input, "", "png").unwrap(), Output::Stdout);
+        assert_eq!(output_for(&io(None, None), &Input::Stdin, "", "png").unwrap(), Output::Stdout);
+        assert!(output_for(&io(None, Some("out")), &Input::Stdin, "", "png").is_err());
+
+        // Never overwrite the input.
+        let png = Input::file("scan.png");
+        assert!(output_for(&io(None, Some(".")), &png, "-transparent", "png").is_err());
+        let beside = output_for(&io(None, None), &png, "-transparent", "png").unwrap();
+        assert_eq!(beside, Output::File(PathBuf::from("scan-transparent.png")));
+
+        // Pattern matches keep their path below the pattern's literal prefix.
+        assert_eq!(glob_base("icons/**/*.svg"), PathBuf::from("icons"));
+        assert_eq!(glob_base("*.svg"), PathBuf::new());
+        assert_eq!(glob_base("art/logo.svg"), PathBuf::from("art"));
+        let matched = Input::File { path: PathBuf::from("icons/a/x.svg"), base: PathBuf::from("icons") };
+        let nested = output_for(&io(None, Some("out")), &matched, "", "png").unwrap();
+        assert_eq!(nested, Output::File(PathBuf::from("out/a/x.png")));
+    }
+
+    #[test]
+    fn test_convert_glob_to_out_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="20"><rect width="10" height="20"/></svg>"#;
+        std::fs::write(dir.path().join("a.svg"), svg).unwrap();
+        std::fs::write(dir.path().join("b.svg"), svg).unwrap();
+        std::fs::write(dir.path().join("broken.svg"), "not svg").unwrap();
+        let out_dir = dir.path().join("out");
+
+        let pattern = dir.path().join("[ab].svg").display().to_string();
+        let Command::Convert(args) =
+            parse(&["svg2png", "convert", &pattern, "-d", out_dir.to_str().unwrap(), "--dpi", "192", "--format", "jpeg"])
+        else {
+            panic!("expected convert");
+        };
+        convert(args).unwrap();
+        for name in ["a.jpg", "b.jpg"] {
+            let image = image::open(out_dir.join(name)).unwrap();
+            assert_eq!((image.width(), image.height()), (20, 40));
+        }
+
+        // A failing input is reported, but does not stop the others.
+        let pattern = dir.path().join("*.svg").display().to_string();
+        let Command::Convert(args) = parse(&["svg2png", "convert", &pattern, "-d", out_dir.to_str().unwrap()]) else {
+            panic!("expected convert");
+        };
+        assert_eq!(convert(args).unwrap_err().to_string(), "1 of 3 inputs failed");
+        assert!(out_dir.join("b.png").is_file());
+    }
+
+    #[test]
+    fn test_out_dir_keeps_subdirectories_and_rejects_collisions() {
+        let dir = tempfile::tempdir().unwrap();
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="20"/>"#;
+        for sub in ["a", "b"] {
+            std::fs::create_dir(dir.path().join(sub)).unwrap();
+            std::fs::write(dir.path().join(sub).join("x.svg"), svg).unwrap();
+        }
+        let out_dir = dir.path().join("out");
+        let out = out_dir.to_str().unwrap();
+
+        let pattern = dir.path().join("**").join("x.svg").display().to_string();
+        let Command::Convert(args) = parse(&["svg2png", "convert", &pattern, "-d", out]) else {
+            panic!("expected convert");
+        };
+        convert(args).unwrap();
+        assert!(out_dir.join("a/x.png").is_file());
+        assert!(out_dir.join("b/x.png").is_file());
+
+        // Explicit paths are written by file name, so these two would collide.
+        let (a, b) = (dir.path().join("a/x.svg"), dir.path().join("b/x.svg"));
+        let collide = dir.path().join("collide");
+        let Command::Convert(args) =
+            parse(&["svg2png", "convert", a.to_str().unwrap(), b.to_str().unwrap(), "-d", collide.to_str().unwrap()])
+        else {
+            panic!("expected convert");
+        };
+        let err = convert(args).unwrap_err().to_string();
+        assert!(err.contains("would both be written to"), "{}", err);
+        assert!(!collide.exists());
+    }
+}
@@ -0,0 +1,270 @@
+//! # Configuration
+//!
+//! Settings of the library read from environment variables at startup.
+//! Settings specific to the HTTP service live in the binary.
+//!
+//! ## Fonts
+//!
+//! - `SVG2PNG_FONT_DIRS`: Extra font directories to load, separated like `PATH`
+//!   (`:` on Unix, `;` on Windows).
+//! - `SVG2PNG_LOAD_SYSTEM_FONTS`: Whether to load system fonts. Defaults to `true`.
+//! - `SVG2PNG_FONT_SERIF`, `SVG2PNG_FONT_SANS_SERIF`, `SVG2PNG_FONT_MONOSPACE`,
+//!   `SVG2PNG_FONT_CURSIVE`, `SVG2PNG_FONT_FANTASY`: Font family used for each
+//!   generic CSS family.
+//! - `SVG2PNG_DEFAULT_FONT_FAMILY`: Font family used when an element specifies none.
+//!
+//! ## Limits
+//!
+//! - `SVG2PNG_MAX_BODY_BYTES`: Maximum request body size. Defaults to 10 MiB.
+//! - `SVG2PNG_MAX_WIDTH`, `SVG2PNG_MAX_HEIGHT`: Maximum output width and height
+//!   in pixels. Default to 16384.
+//! - `SVG2PNG_MAX_PIXELS`: Maximum output (or decoded input) pixel count.
+//!   Defaults to 50 million (200 MB of RGBA).
+//! - `SVG2PNG_MAX_DPI`: Maximum `dpi` query parameter. Defaults to 2400.
+//! - `SVG2PNG_MAX_BATCH_FILES`: Maximum number of SVGs in a batch request.
+//!   Defaults to 1000.
+//! - `SVG2PNG_MAX_BATCH_BYTES`: Maximum total uncompressed size of the SVGs in a
+//!   batch request. Defaults to 100 MiB.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::error::RenderError;
+
+/// Environment variable name for extra font directories.
+const FONT_DIRS_ENV_VAR: &str = "SVG2PNG_FONT_DIRS";
+/// Environment variable name for toggling system font loading.
+const LOAD_SYSTEM_FONTS_ENV_VAR: &str = "SVG2PNG_LOAD_SYSTEM_FONTS";
+/// Environment variable name for the `serif` generic family.
+const FONT_SERIF_ENV_VAR: &str = "SVG2PNG_FONT_SERIF";
+/// Environment variable name for the `sans-serif` generic family.
+const FONT_SANS_SERIF_ENV_VAR: &str = "SVG2PNG_FONT_SANS_SERIF";
+/// Environment variable name for the `monospace` generic family.
+const FONT_MONOSPACE_ENV_VAR: &str = "SVG2PNG_FONT_MONOSPACE";
+/// Environment variable name for the `cursive` generic family.
+const FONT_CURSIVE_ENV_VAR: &str = "SVG2PNG_FONT_CURSIVE";
+/// Environment variable name for the `fantasy` generic family.
+const FONT_FANTASY_ENV_VAR: &str = "SVG2PNG_FONT_FANTASY";
+/// Environment variable name for the default font family.
+const DEFAULT_FONT_FAMILY_ENV_VAR: &str = "SVG2PNG_DEFAULT_FONT_FAMILY";
+
+/// Environment variable name for the maximum request body size in bytes.
+const MAX_BODY_BYTES_ENV_VAR: &str = "SVG2PNG_MAX_BODY_BYTES";
+/// Environment variable name for the maximum output width in pixels.
+const MAX_WIDTH_ENV_VAR: &str = "SVG2PNG_MAX_WIDTH";
+/// Environment variable name for the maximum output height in pixels.
+const MAX_HEIGHT_ENV_VAR: &str = "SVG2PNG_MAX_HEIGHT";
+/// Environment variable name for the maximum output pixel count.
+const MAX_PIXELS_ENV_VAR: &str = "SVG2PNG_MAX_PIXELS";
+/// Environment variable name for the maximum requested DPI.
+const MAX_DPI_ENV_VAR: &str = "SVG2PNG_MAX_DPI";
+/// Environment variable name for the maximum number of files in a batch.
+const MAX_BATCH_FILES_ENV_VAR: &str = "SVG2PNG_MAX_BATCH_FILES";
+/// Environment variable name for the maximum uncompressed size of a batch in bytes.
+const MAX_BATCH_BYTES_ENV_VAR: &str = "SVG2PNG_MAX_BATCH_BYTES";
+
+/// Default `serif` family; a common, likely available serif font.
+const DEFAULT_SERIF_FAMILY: &str = "Liberation Serif";
+
+/// An environment variable with an invalid value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    /// Name of the variable.
+    pub name: String,
+    /// The value that was rejected.
+    pub value: String,
+    /// What the value should have been, e.g. `a positive number`.
+    pub expected: &'static str,
+}
+
+impl ConfigError {
+    fn new(name: &str, value: &str, expected: &'static str) -> Self {
+        ConfigError { name: name.to_string(), value: value.to_string(), expected }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid {} value: {} (expected {})", self.name, self.value, self.expected)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Font loading and fallback settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FontConfig {
+    /// Extra directories scanned (recursively) for fonts.
+    pub font_dirs: Vec<PathBuf>,
+    /// Whether to load fonts installed on the system.
+    pub load_system_fonts: bool,
+    /// Family used for the generic `serif` family.
+    pub serif: String,
+    /// Family used for the generic `sans-serif` family. fontdb default if unset.
+    pub sans_serif: Option<String>,
+    /// Family used for the generic `monospace` family. fontdb default if unset.
+    pub monospace: Option<String>,
+    /// Family used for the generic `cursive` family. fontdb default if unset.
+    pub cursive: Option<String>,
+    /// Family used for the generic `fantasy` family. fontdb default if unset.
+    pub fantasy: Option<String>,
+    /// Family used when an element has no `font-family`. usvg default if unset.
+    pub default_family: Option<String>,
+}
+
+impl Default for FontConfig {
+    fn default() -> Self {
+        FontConfig {
+            font_dirs: Vec::new(),
+            load_system_fonts: true,
+            serif: DEFAULT_SERIF_FAMILY.to_string(),
+            sans_serif: None,
+            monospace: None,
+            cursive: None,
+            fantasy: None,
+            default_family: None,
+        }
+    }
+}
+
+impl FontConfig {
+    /// Reads the font configuration from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(FontConfig)` - The configuration, with defaults for unset variables.
+    /// * `Err(ConfigError)` - If a variable has an invalid value.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let defaults = FontConfig::default();
+        Ok(FontConfig {
+            font_dirs: std::env::var_os(FONT_DIRS_ENV_VAR)
+                .map(|dirs| std::env::split_paths(&dirs).filter(|p| !p.as_os_str().is_empty()).collect())
+                .unwrap_or_default(),
+            load_system_fonts: match env_non_empty(LOAD_SYSTEM_FONTS_ENV_VAR) {
+                Some(value) => parse_bool(LOAD_SYSTEM_FONTS_ENV_VAR, &value)?,
+                None => defaults.load_system_fonts,
+            },
+            serif: env_non_empty(FONT_SERIF_ENV_VAR).unwrap_or(defaults.serif),
+            sans_serif: env_non_empty(FONT_SANS_SERIF_ENV_VAR),
+            monospace: env_non_empty(FONT_MONOSPACE_ENV_VAR),
+            cursive: env_non_empty(FONT_CURSIVE_ENV_VAR),
+            fantasy: env_non_empty(FONT_FANTASY_ENV_VAR),
+            default_family: env_non_empty(DEFAULT_FONT_FAMILY_ENV_VAR),
+        })
+    }
+}
+
+/// Resource limits enforced on every request.
+///
+/// Checked before any large allocation so a tiny request (e.g. an SVG with
+/// `width="100000"` and `dpi=9600`) cannot exhaust memory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Maximum request body size in bytes.
+    pub max_body_bytes: usize,
+    /// Maximum output width in pixels.
+    pub max_width: u32,
+    /// Maximum output height in pixels.
+    pub max_height: u32,
+    /// Maximum number of pixels in an output image or decoded input PNG.
+    pub max_pixels: u64,
+    /// Maximum value accepted for the `dpi` query parameter.
+    pub max_dpi: f32,
+    /// Maximum number of SVGs in a batch request.
+    pub max_batch_files: usize,
+    /// Maximum total uncompressed size of the SVGs in a batch request, in bytes.
+    pub max_batch_bytes: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_body_bytes: 10 * 1024 * 1024,
+            max_width: 16_384,
+            max_height: 16_384,
+            max_pixels: 50_000_000,
+            max_dpi: 2400.0,
+            max_batch_files: 1000,
+            max_batch_bytes: 100 * 1024 * 1024,
+        }
+    }
+}
+
+impl Limits {
+    /// Reads the limits from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Limits)` - The limits, with defaults for unset variables.
+    /// * `Err(ConfigError)` - If a variable is not a positive number.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let defaults = Limits::default();
+        Ok(Limits {
+            max_body_bytes: parse_limit(MAX_BODY_BYTES_ENV_VAR, defaults.max_body_bytes)?,
+            max_width: parse_limit(MAX_WIDTH_ENV_VAR, defaults.max_width)?,
+            max_height: parse_limit(MAX_HEIGHT_ENV_VAR, defaults.max_height)?,
+            max_pixels: parse_limit(MAX_PIXELS_ENV_VAR, defaults.max_pixels)?,
+            max_dpi: parse_limit(MAX_DPI_ENV_VAR, defaults.max_dpi)?,
+            max_batch_files: parse_limit(MAX_BATCH_FILES_ENV_VAR, defaults.max_batch_files)?,
+            max_batch_bytes: parse_limit(MAX_BATCH_BYTES_ENV_VAR, defaults.max_batch_bytes)?,
+        })
+    }
+
+    /// Rejects a requested DPI above [`Limits::max_dpi`].
+    pub fn check_dpi(&self, dpi: f32) -> Result<(), RenderError> {
+        if dpi > self.max_dpi {
+            return Err(RenderError::LimitExceeded(format!(
+                "Requested DPI {} exceeds the maximum of {}",
+                dpi, self.max_dpi
+            )));
+        }
+        Ok(())
+    }
+
+    /// Rejects image dimensions above the width, height or pixel count limits.
+    pub fn check_dimensions(&self, width: u32, height: u32) -> Result<(), RenderError> {
+        if width > self.max_width || height > self.max_height {
+            return Err(RenderError::LimitExceeded(format!(
+                "Image size {}x{} exceeds the maximum of {}x{}",
+                width, height, self.max_width, self.max_height
+            )));
+        }
+        let pixels = width as u64 * height as u64;
+        if pixels > self.max_pixels {
+            return Err(RenderError::LimitExceeded(format!(
+                "Image size {}x{} ({} pixels) exceeds the maximum of {} pixels",
+                width, height, pixels, self.max_pixels
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Reads an environment variable, treating unset and empty values alike.
+fn env_non_empty(name: &str) -> Option<String> {
+    std::env::var(name).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+/// Parses a boolean environment variable value.
+fn parse_bool(name: &str, value: &str) -> Result<bool, ConfigError> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Ok(true),
+        "false" | "0" | "no" | "off" => Ok(false),
+        _ => Err(ConfigError::new(name, value, "true or false")),
+    }
+}
+
+/// Parses a positive numeric limit from an environment variable, or returns `default` if unset.
+fn parse_limit<T>(name: &str, default: T) -> Result<T, ConfigError>
+where
+    T: FromStr + PartialOrd + Default,
+{
+    let Some(value) = env_non_empty(name) else {
+        return Ok(default);
+    };
+    match value.parse::<T>() {
+        Ok(v) if v > T::default() => Ok(v),
+        _ => Err(ConfigError::new(name, &value, "a positive number")),
+    }
+}
@@ -0,0 +1,55 @@
+//! # Deadlines
+//!
+//! resvg cannot be interrupted mid-render, so long-running operations are
+//! bounded cooperatively: a [`Deadline`] is checked between phases (parsing,
+//! rendering, encoding) and inside the per-pixel loops of background removal,
+//! and the operation bails out with [`RenderError::Timeout`] once it has
+//! passed.
+
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+use crate::error::RenderError;
+
+/// The point in time by which an operation must have finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline {
+    /// When the deadline expires.
+    at: Instant,
+    /// The total time allowed, for error messages.
+    timeout: Duration,
+}
+
+impl Deadline {
+    /// Creates a deadline `timeout` from now.
+    pub fn after(timeout: Duration) -> Self {
+        Deadline { at: Instant::now() + timeout, timeout }
+    }
+
+    /// The total time allowed.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// The time left until the deadline, zero once it has passed.
+    pub fn remaining(&self) -> Duration {
+        self.at.saturating_duration_since(Instant::now())
+    }
+
+    /// Fails with [`RenderError::Timeout`] if the deadline has passed.
+    ///
+    /// `phase` names the step that just finished, for logging.
+    pub fn check(&self, phase: &str) -> Result<(), RenderError> {
+        if Instant::now() >= self.at {
+            warn!(phase, timeout_ms = self.timeout.as_millis() as u64, "Render deadline exceeded");
+            return Err(self.error());
+        }
+        Ok(())
+    }
+
+    /// The error reported when this deadline is exceeded.
+    pub fn error(&self) -> RenderError {
+        RenderError::Timeout { timeout_ms: self.timeout.as_millis() as u64 }
+    }
+}
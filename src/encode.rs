@@ -0,0 +1,486 @@
+//! # Image Encoding
+//!
+//! Encodes a rendered [`Pixmap`] into one of the supported output formats
+//! (PNG, JPEG, WebP or AVIF), embedding the effective resolution as metadata
+//! where the format supports it:
+//!
+//! - PNG: `pHYs` chunk (pixels per meter).
+//! - JPEG: JFIF density (dots per inch).
+//! - WebP: `EXIF` chunk with `XResolution`/`YResolution`.
+//! - AVIF: no resolution metadata is written.
+
+use std::str::FromStr;
+
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::{JpegEncoder, PixelDensity, PixelDensityUnit};
+use image::{ExtendedColorType, ImageEncoder};
+use resvg::tiny_skia::Pixmap;
+use tracing::{debug, error};
+
+use crate::error::RenderError;
+
+/// Default quality for lossy JPEG encoding.
+const DEFAULT_JPEG_QUALITY: u8 = 85;
+/// Default quality for lossy WebP encoding.
+const DEFAULT_WEBP_QUALITY: u8 = 80;
+/// Default quality for AVIF encoding.
+const DEFAULT_AVIF_QUALITY: u8 = 80;
+/// AVIF encoder speed (1 = slowest/best, 10 = fastest). Favors latency for a web service.
+const AVIF_SPEED: u8 = 6;
+
+/// A supported output image format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Portable Network Graphics (lossless, alpha).
+    #[default]
+    Png,
+    /// JPEG (lossy, no alpha; always flattened onto a background).
+    Jpeg,
+    /// WebP (lossless or lossy, alpha).
+    WebP,
+    /// AVIF (lossy, alpha).
+    Avif,
+}
+
+impl OutputFormat {
+    /// All formats, in server preference order for content negotiation.
+    pub const ALL: [OutputFormat; 4] = [
+        OutputFormat::Png,
+        OutputFormat::WebP,
+        OutputFormat::Avif,
+        OutputFormat::Jpeg,
+    ];
+
+    /// The HTTP `Content-Type` for this format.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::WebP => "image/webp",
+            OutputFormat::Avif => "image/avif",
+        }
+    }
+
+    /// The conventional file extension, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::WebP => "webp",
+            OutputFormat::Avif => "avif",
+        }
+    }
+
+    /// Whether the format can carry an alpha channel.
+    pub fn supports_alpha(self) -> bool {
+        !matches!(self, OutputFormat::Jpeg)
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "png" => Ok(OutputFormat::Png),
+            "jpeg" | "jpg" => Ok(OutputFormat::Jpeg),
+            "webp" => Ok(OutputFormat::WebP),
+            "avif" => Ok(OutputFormat::Avif),
+            other => Err(format!(
+                "Invalid format '{}': expected one of png, jpeg, webp, avif",
+                other
+            )),
+        }
+    }
+}
+
+/// Picks an output format from an HTTP `Accept` header value.
+///
+/// Each format's quality is the `q` parameter of the most specific media range
+/// matching it (`image/png` over `image/*` over `*/*`), so `image/png;q=0, */*`
+/// excludes PNG. The format with the highest quality wins; ties go to the
+/// format listed first, and formats only matched by a wildcard are taken in
+/// [`OutputFormat::ALL`] order, PNG first.
+///
+/// # Returns
+///
+/// * `Some(format)` - The best acceptable format.
+/// * `None` - If none of the supported formats is acceptable.
+pub fn negotiate(accept: &str) -> Option<OutputFormat> {
+    let ranges: Vec<(String, f32)> = accept
+        .split(',')
+        .map(|range| {
+            let mut parts = range.split(';').map(str::trim);
+            let media_type = parts.next().unwrap_or("").to_ascii_lowercase();
+            // Parameter names are case-insensitive and may have whitespace around `=`.
+            let q = parts
+                .filter_map(|p| p.split_once('='))
+                .filter(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                .find_map(|(_, q)| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (media_type, q)
+        })
+        .collect();
+
+    // (quality, position of an explicit range or usize::MAX for wildcards, format)
+    let mut best: Option<(f32, usize, OutputFormat)> = None;
+    for format in OutputFormat::ALL {
+        let specificity = |media_type: &str| match media_type {
+            t if t == format.content_type() => Some(2),
+            "image/*" => Some(1),
+            "*/*" => Some(0),
+            _ => None,
+        };
+        let Some((specificity, position, q)) = ranges
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (media_type, q))| Some((specificity(media_type)?, i, *q)))
+            .min_by_key(|&(specificity, i, _)| (std::cmp::Reverse(specificity), i))
+        else {
+            continue;
+        };
+        if q <= 0.0 {
+            continue;
+        }
+        let rank = if specificity == 2 { position } else { usize::MAX };
+        if best.is_none_or(|(best_q, best_rank, _)| q > best_q || (q == best_q && rank < best_rank)) {
+            best = Some((q, rank, format));
+        }
+    }
+    best.map(|(_, _, format)| format)
+}
+
+/// Options controlling how a pixmap is encoded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EncodeOptions {
+    /// The output format.
+    pub format: OutputFormat,
+    /// Lossy quality (1-100). `None` uses the format default; for WebP it selects lossless.
+    pub quality: Option<u8>,
+    /// Force lossless WebP even if a quality is given.
+    pub lossless: bool,
+    /// Whether the pixmap is fully opaque and should be written without alpha.
+    pub flatten: bool,
+    /// Effective horizontal resolution in DPI.
+    pub dpi_x: f32,
+    /// Effective vertical resolution in DPI.
+    pub dpi_y: f32,
+}
+
+/// Encodes a rendered pixmap according to `options`.
+///
+/// # Returns
+///
+/// * `Ok(Vec<u8>)` - The encoded image bytes.
+/// * `Err(RenderError::Encoder)` - If the encoder fails.
+pub fn encode(pixmap: &Pixmap, options: &EncodeOptions) -> Result<Vec<u8>, RenderError> {
+    let width = pixmap.width();
+    let height = pixmap.height();
+    // tiny-skia stores premultiplied RGBA; all encoders expect straight alpha.
+    let data = straight_pixels(pixmap, options.flatten || !options.format.supports_alpha());
+    let color = if options.flatten || !options.format.supports_alpha() {
+        ExtendedColorType::Rgb8
+    } else {
+        ExtendedColorType::Rgba8
+    };
+
+    debug!(format = ?options.format, width, height, ?color, "Encoding image");
+    match options.format {
+        OutputFormat::Png => encode_png(&data, width, height, color, options),
+        OutputFormat::Jpeg => encode_jpeg(&data, width, height, options),
+        OutputFormat::WebP => encode_webp(&data, width, height, color, options),
+        OutputFormat::Avif => encode_avif(&data, width, height, color, options),
+    }
+}
+
+/// Returns the pixmap pixels as straight (non-premultiplied) RGBA or RGB bytes.
+fn straight_pixels(pixmap: &Pixmap, rgb: bool) -> Vec<u8> {
+    let channels = if rgb { 3 } else { 4 };
+    let mut data = Vec::with_capacity(pixmap.pixels().len() * channels);
+    for pixel in pixmap.pixels() {
+        let c = pixel.demultiply();
+        data.extend_from_slice(&[c.red(), c.green(), c.blue()]);
+        if !rgb {
+            data.push(c.alpha());
+        }
+    }
+    data
+}
+
+/// Maps an encoder error into the handler error type, logging it.
+fn encoder_error(what: &str, e: impl std::fmt::Display) -> RenderError {
+    error!(error = %e, "Failed to {}", what);
+    RenderError::Encoder(format!("Failed to {}: {}", what, e))
+}
+
+/// Encodes PNG data with a `pHYs` chunk describing the effective resolution.
+fn encode_png(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    color: ExtendedColorType,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, RenderError> {
+    // Create a buffer to hold the resulting PNG data.
+    let mut buffer = Vec::new();
+    // Create a PNG encoder that will write to the buffer.
+    let mut encoder = png::Encoder::new(&mut buffer, width, height);
+    // Set standard PNG color type and bit depth (RGBA 8-bit, or RGB 8-bit when
+    // the image has been flattened onto an opaque background).
+    encoder.set_color(if color == ExtendedColorType::Rgb8 { png::ColorType::Rgb } else { png::ColorType::Rgba });
+    encoder.set_depth(png::BitDepth::Eight);
+
+    // Get a writer for the image data. This must be done *before* writing
+    // custom chunks like pHYs.
+    debug!("Writing PNG header");
+    let mut writer = encoder.write_header().map_err(|e| encoder_error("write PNG header", e))?;
+
+    // Calculate pixels per meter for the pHYs chunk (1 inch = 0.0254 meters).
+    let ppm_x = (options.dpi_x / 0.0254).round() as u32;
+    let ppm_y = (options.dpi_y / 0.0254).round() as u32;
+    debug!(ppm_x, ppm_y, "Calculated PPM for pHYs chunk");
+
+    // Manually construct and write the pHYs chunk (physical pixel dimensions).
+    // Format: 4 bytes X ppm (big-endian), 4 bytes Y ppm (big-endian), 1 byte unit specifier.
+    let mut phys_data = [0u8; 9];
+    phys_data[0..4].copy_from_slice(&ppm_x.to_be_bytes());
+    phys_data[4..8].copy_from_slice(&ppm_y.to_be_bytes());
+    phys_data[8] = 1; // Unit specifier: 1 means the unit is meters.
+    debug!("Writing pHYs chunk");
+    writer
+        .write_chunk(png::chunk::pHYs, &phys_data)
+        .map_err(|e| encoder_error("write pHYs chunk", e))?;
+
+    debug!("Writing PNG image data");
+    writer.write_image_data(data).map_err(|e| encoder_error("write PNG data", e))?;
+    // The `writer` must be dropped here to finalize the PNG stream correctly
+    // before the buffer is returned.
+    drop(writer);
+
+    Ok(buffer)
+}
+
+/// Encodes RGB data as JPEG with a JFIF density header.
+fn encode_jpeg(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, RenderError> {
+    let mut buffer = Vec::new();
+    let quality = options.quality.unwrap_or(DEFAULT_JPEG_QUALITY);
+    let mut encoder = JpegEncoder::new_with_quality(&mut buffer, quality);
+    encoder.set_pixel_density(PixelDensity {
+        density: (dpi_u16(options.dpi_x), dpi_u16(options.dpi_y)),
+        unit: PixelDensityUnit::Inches,
+    });
+    encoder
+        .encode(data, width, height, ExtendedColorType::Rgb8)
+        .map_err(|e| encoder_error("encode JPEG", e))?;
+    Ok(buffer)
+}
+
+/// Encodes WebP (lossless unless a quality is requested) with an EXIF resolution chunk.
+fn encode_webp(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    color: ExtendedColorType,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, RenderError> {
+    let encoder = if color == ExtendedColorType::Rgb8 {
+        webp::Encoder::from_rgb(data, width, height)
+    } else {
+        webp::Encoder::from_rgba(data, width, height)
+    };
+    let lossless = options.lossless || options.quality.is_none();
+    let quality = options.quality.unwrap_or(DEFAULT_WEBP_QUALITY);
+    let mut config = webp::WebPConfig::new().map_err(|_| encoder_error("configure WebP encoder", "invalid config"))?;
+    config.lossless = lossless as i32;
+    config.quality = if lossless { 75.0 } else { quality as f32 };
+    let encoded = encoder
+        .encode_advanced(&config)
+        .map_err(|e| encoder_error("encode WebP", format!("{:?}", e)))?;
+
+    let exif = exif_resolution(options.dpi_x, options.dpi_y);
+    add_webp_exif(&encoded, width, height, color == ExtendedColorType::Rgba8, &exif)
+        .ok_or_else(|| encoder_error("write WebP EXIF chunk", "unexpected WebP container layout"))
+}
+
+/// Encodes AVIF. AVIF has no standard resolution metadata, so none is written.
+fn encode_avif(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    color: ExtendedColorType,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, RenderError> {
+    let mut buffer = Vec::new();
+    let quality = options.quality.unwrap_or(DEFAULT_AVIF_QUALITY);
+    AvifEncoder::new_with_speed_quality(&mut buffer, AVIF_SPEED, quality)
+        .write_image(data, width, height, color)
+        .map_err(|e| encoder_error("encode AVIF", e))?;
+    Ok(buffer)
+}
+
+/// Converts a DPI value to the `u16` range used by JFIF.
+fn dpi_u16(dpi: f32) -> u16 {
+    dpi.round().clamp(1.0, u16::MAX as f32) as u16
+}
+
+/// Builds a minimal little-endian TIFF/EXIF block with `XResolution`,
+/// `YResolution` and `ResolutionUnit` (inches) in IFD0.
+fn exif_resolution(dpi_x: f32, dpi_y: f32) -> Vec<u8> {
+    // Resolutions are RATIONALs; keep two decimal places of precision.
+    const DENOMINATOR: u32 = 100;
+    let to_rational = |dpi: f32| ((dpi * DENOMINATOR as f32).round().max(1.0) as u32, DENOMINATOR);
+
+    let entry_count: u16 = 3;
+    // Header (8) + entry count (2) + entries (12 each) + next IFD offset (4).
+    let data_offset = 8 + 2 + 12 * entry_count as u32 + 4;
+
+    let mut exif = Vec::with_capacity(data_offset as usize + 16);
+    exif.extend_from_slice(b"II");
+    exif.extend_from_slice(&42u16.to_le_bytes());
+    exif.extend_from_slice(&8u32.to_le_bytes());
+    exif.extend_from_slice(&entry_count.to_le_bytes());
+    // Tag, type (5 = RATIONAL, 3 = SHORT), count, value/offset.
+    for (tag, offset) in [(0x011Au16, data_offset), (0x011B, data_offset + 8)] {
+        exif.extend_from_slice(&tag.to_le_bytes());
+        exif.extend_from_slice(&5u16.to_le_bytes());
+        exif.extend_from_slice(&1u32.to_le_bytes());
+        exif.extend_from_slice(&offset.to_le_bytes());
+    }
+    exif.extend_from_slice(&0x0128u16.to_le_bytes());
+    exif.extend_from_slice(&3u16.to_le_bytes());
+    exif.extend_from_slice(&1u32.to_le_bytes());
+    exif.extend_from_slice(&[2, 0, 0, 0]); // 2 = inches, left-justified SHORT.
+    exif.extend_from_slice(&0u32.to_le_bytes());
+    for dpi in [dpi_x, dpi_y] {
+        let (num, den) = to_rational(dpi);
+        exif.extend_from_slice(&num.to_le_bytes());
+        exif.extend_from_slice(&den.to_le_bytes());
+    }
+    exif
+}
+
+/// Appends an `EXIF` chunk to a WebP file, converting it to the extended
+/// (`VP8X`) container format if necessary.
+///
+/// Returns `None` if the input is not a well-formed RIFF/WebP file.
+fn add_webp_exif(webp: &[u8], width: u32, height: u32, has_alpha: bool, exif: &[u8]) -> Option<Vec<u8>> {
+    /// VP8X flag bit indicating EXIF metadata is present.
+    const EXIF_FLAG: u8 = 0x08;
+    /// VP8X flag bit indicating the image has alpha.
+    const ALPHA_FLAG: u8 = 0x10;
+
+    if webp.len() < 12 || &webp[0..4] != b"RIFF" || &webp[8..12] != b"WEBP" {
+        return None;
+    }
+    let chunks = &webp[12..];
+
+    let mut body = Vec::with_capacity(chunks.len() + exif.len() + 32);
+    body.extend_from_slice(b"WEBP");
+    if chunks.get(0..4)? == b"VP8X" {
+        // Already extended: set the EXIF flag in place.
+        body.extend_from_slice(chunks);
+        *body.get_mut(4 + 8)? |= EXIF_FLAG;
+    } else {
+        // Simple format: prepend a VP8X header describing the canvas.
+        let mut vp8x = [0u8; 10];
+        vp8x[0] = EXIF_FLAG | if has_alpha { ALPHA_FLAG } else { 0 };
+        vp8x[4..7].copy_from_slice(&(width - 1).to_le_bytes()[0..3]);
+        vp8x[7..10].copy_from_slice(&(height - 1).to_le_bytes()[0..3]);
+        write_riff_chunk(&mut body, b"VP8X", &vp8x);
+        body.extend_from_slice(chunks);
+    }
+    write_riff_chunk(&mut body, b"EXIF", exif);
+
+    let mut out = Vec::with_capacity(body.len() + 8);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(&body);
+    Some(out)
+}
+
+/// Writes a RIFF chunk (FourCC, little-endian size, payload, pad byte if odd).
+fn write_riff_chunk(out: &mut Vec<u8>, fourcc: &[u8; 4], payload: &[u8]) {
+    out.extend_from_slice(fourcc);
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(payload);
+    if payload.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_negotiate_prefers_highest_quality() {
+        assert_eq!(negotiate("image/webp;q=0.9, image/avif"), Some(OutputFormat::Avif));
+        assert_eq!(negotiate("image/jpeg, image/webp"), Some(OutputFormat::Jpeg));
+        assert_eq!(negotiate("text/html, */*;q=0.8"), Some(OutputFormat::Png));
+        assert_eq!(negotiate("text/html"), None);
+        assert_eq!(negotiate("image/png;q=0"), None);
+        assert_eq!(negotiate("image/png;Q=0"), None);
+        assert_eq!(negotiate("image/png ; q = 0"), None);
+        assert_eq!(negotiate("image/webp;level=1;q=0.5, image/jpeg; Q = 0.6"), Some(OutputFormat::Jpeg));
+
+        // An explicit q=0 wins over wildcards.
+        assert_eq!(negotiate("image/png;q=0, */*"), Some(OutputFormat::WebP));
+        assert_eq!(negotiate("image/*;q=0.5, image/png;q=0"), Some(OutputFormat::WebP));
+        assert_eq!(negotiate("image/*;q=0, image/jpeg"), Some(OutputFormat::Jpeg));
+        assert_eq!(negotiate("image/avif;q=0.5, image/*"), Some(OutputFormat::Png));
+    }
+
+    #[test]
+    fn test_webp_exif_is_readable() {
+        let mut pixmap = Pixmap::new(3, 2).unwrap();
+        pixmap.fill(resvg::tiny_skia::Color::from_rgba8(10, 20, 30, 255));
+        let options = EncodeOptions {
+            format: OutputFormat::WebP,
+            quality: Some(90),
+            lossless: false,
+            flatten: true,
+            dpi_x: 300.0,
+            dpi_y: 150.5,
+        };
+        let encoded = encode(&pixmap, &options).unwrap();
+        assert_eq!(&encoded[12..16], b"VP8X");
+
+        let img = image::load_from_memory_with_format(&encoded, image::ImageFormat::WebP).unwrap();
+        assert_eq!((img.width(), img.height()), (3, 2));
+
+        // Walk the RIFF chunks to the EXIF payload.
+        let u16_at = |data: &[u8], at: usize| u16::from_le_bytes(data[at..at + 2].try_into().unwrap());
+        let u32_at = |data: &[u8], at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+        let mut at = 12;
+        let exif = loop {
+            let size = u32_at(&encoded, at + 4) as usize;
+            if &encoded[at..at + 4] == b"EXIF" {
+                break &encoded[at + 8..at + 8 + size];
+            }
+            at += 8 + size + size % 2;
+        };
+
+        // Read the resolution tags from the first IFD of the little-endian TIFF.
+        assert_eq!(&exif[0..4], b"II*\0");
+        let ifd = u32_at(exif, 4) as usize;
+        let entries: HashMap<u16, (u16, u32)> = (0..u16_at(exif, ifd) as usize)
+            .map(|i| ifd + 2 + 12 * i)
+            .map(|entry| (u16_at(exif, entry), (u16_at(exif, entry + 2), u32_at(exif, entry + 8))))
+            .collect();
+        let rational = |tag: u16| {
+            let (kind, offset) = entries[&tag];
+            assert_eq!(kind, 5, "tag {tag:#06x} is not a RATIONAL");
+            let offset = offset as usize;
+            u32_at(exif, offset) as f64 / u32_at(exif, offset + 4) as f64
+        };
+        assert_eq!(rational(0x011A), 300.0);
+        assert_eq!(rational(0x011B), 150.5);
+        assert_eq!(entries[&0x0128], (3, 2), "ResolutionUnit should be inches");
+    }
+}
@@ -0,0 +1,103 @@
+//! # Render Errors
+//!
+//! [`RenderError`] is returned by every fallible library operation. Each
+//! variant has a stable, machine-readable [`code`](RenderError::code) that the
+//! HTTP service reports unchanged, so callers can match on it regardless of
+//! whether they use the library directly or the service.
+
+use std::fmt;
+
+/// An error produced while rendering, keying or encoding an image.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RenderError {
+    /// The input data was empty.
+    EmptyInput,
+    /// A render or keying option had an invalid value (e.g. a seed outside the image).
+    InvalidOption(String),
+    /// The input could not be parsed as SVG.
+    SvgParse(String),
+    /// The input could not be decoded as PNG.
+    PngDecode(String),
+    /// The SVG results in a zero-sized image after scaling.
+    ZeroSize,
+    /// The SVG has no renderable element with the requested ID.
+    ElementNotFound(String),
+    /// The requested output (or decoded input) exceeds a configured size or DPI limit.
+    LimitExceeded(String),
+    /// The pixmap for the output image could not be allocated.
+    PixmapAlloc {
+        /// Requested pixmap width.
+        width: u32,
+        /// Requested pixmap height.
+        height: u32,
+    },
+    /// usvg, resvg or tiny-skia panicked while handling the input.
+    Panic,
+    /// An image encoder failed.
+    Encoder(String),
+    /// The operation did not finish before its deadline.
+    Timeout {
+        /// The deadline that was exceeded, in milliseconds.
+        timeout_ms: u64,
+    },
+}
+
+impl RenderError {
+    /// The stable, machine-readable error code.
+    pub fn code(&self) -> &'static str {
+        match self {
+            RenderError::EmptyInput => "empty_body",
+            RenderError::InvalidOption(_) => "invalid_parameter",
+            RenderError::SvgParse(_) => "svg_parse_error",
+            RenderError::PngDecode(_) => "png_decode_error",
+            RenderError::ZeroSize => "zero_size",
+            RenderError::ElementNotFound(_) => "element_not_found",
+            RenderError::LimitExceeded(_) => "limit_exceeded",
+            RenderError::PixmapAlloc { .. } => "pixmap_alloc_failed",
+            RenderError::Panic => "render_panic",
+            RenderError::Encoder(_) => "encoder_error",
+            RenderError::Timeout { .. } => "render_timeout",
+        }
+    }
+
+    /// A short, human-readable summary of the error kind.
+    pub fn title(&self) -> &'static str {
+        match self {
+            RenderError::EmptyInput => "Empty request body",
+            RenderError::InvalidOption(_) => "Invalid query parameter",
+            RenderError::SvgParse(_) => "Invalid SVG",
+            RenderError::PngDecode(_) => "Invalid PNG",
+            RenderError::ZeroSize => "Zero-sized image",
+            RenderError::ElementNotFound(_) => "Element not found",
+            RenderError::LimitExceeded(_) => "Limit exceeded",
+            RenderError::PixmapAlloc { .. } => "Pixmap allocation failed",
+            RenderError::Panic => "Rendering failed",
+            RenderError::Encoder(_) => "Image encoding failed",
+            RenderError::Timeout { .. } => "Render timed out",
+        }
+    }
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::EmptyInput => write!(f, "Input data cannot be empty"),
+            RenderError::InvalidOption(msg) | RenderError::LimitExceeded(msg) | RenderError::Encoder(msg) => {
+                write!(f, "{}", msg)
+            }
+            RenderError::SvgParse(msg) => write!(f, "Invalid SVG: {}", msg),
+            RenderError::PngDecode(msg) => write!(f, "Invalid PNG: {}", msg),
+            RenderError::ZeroSize => write!(f, "SVG results in zero width or height after scaling"),
+            RenderError::ElementNotFound(id) => write!(f, "The SVG has no renderable element with id '{}'", id),
+            RenderError::PixmapAlloc { width, height } => {
+                write!(f, "Failed to create {}x{} pixmap", width, height)
+            }
+            RenderError::Panic => write!(f, "The input triggered an internal error in the renderer"),
+            RenderError::Timeout { timeout_ms } => {
+                write!(f, "Rendering did not finish within {} ms", timeout_ms)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
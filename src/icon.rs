@@ -0,0 +1,431 @@
+//! # Icon Export
+//!
+//! Renders one SVG at several pixel sizes and packages the PNGs as:
+//!
+//! - [`IconFormat::Ico`]: a multi-resolution Windows `.ico` (favicon) with
+//!   PNG-compressed entries, up to 256x256.
+//! - [`IconFormat::Icns`]: an Apple `.icns` with PNG-compressed entries from
+//!   16x16 to 1024x1024, including the `@2x` variants macOS looks for.
+//! - [`IconFormat::Android`]: a ZIP of launcher icons in the `res/mipmap-*dpi`
+//!   directories plus the 512x512 Play Store icon.
+//! - [`IconFormat::Ios`]: a ZIP of an `AppIcon.appiconset` with every iPhone,
+//!   iPad and App Store size and the matching `Contents.json`.
+//!
+//! The SVG is drawn into a square canvas for each size, so non-square artwork
+//! is placed according to the `fit` and `align` render options.
+
+use std::io::{Cursor, Write};
+use std::str::FromStr;
+
+use serde::Serialize;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::error::RenderError;
+use crate::renderer::{EncodedImage, RenderOptions};
+
+/// Default pixel sizes of an `.ico`.
+pub const DEFAULT_ICO_SIZES: [u32; 7] = [16, 24, 32, 48, 64, 128, 256];
+/// Default pixel sizes of an `.icns`: every size it can hold.
+pub const DEFAULT_ICNS_SIZES: [u32; 7] = [16, 32, 64, 128, 256, 512, 1024];
+/// Default file name (without extension) of Android launcher icons.
+pub const DEFAULT_ANDROID_NAME: &str = "ic_launcher";
+/// Largest entry an `.ico` can describe.
+const MAX_ICO_SIZE: u32 = 256;
+
+/// Android launcher icon densities and their pixel sizes (48dp at each density).
+const ANDROID_DENSITIES: [(&str, u32); 5] =
+    [("mdpi", 48), ("hdpi", 72), ("xhdpi", 96), ("xxhdpi", 144), ("xxxhdpi", 192)];
+/// Pixel size of the Google Play Store icon.
+const ANDROID_PLAY_STORE_SIZE: u32 = 512;
+
+/// A four-character ICNS element type.
+type OsType = [u8; 4];
+
+/// ICNS element types holding PNG data: pixel size, type, and the `@2x` type
+/// of the point size half as large (if macOS defines one).
+const ICNS_TYPES: [(u32, &OsType, Option<&OsType>); 7] = [
+    (16, b"icp4", None),
+    (32, b"icp5", Some(b"ic11")),
+    (64, b"icp6", Some(b"ic12")),
+    (128, b"ic07", None),
+    (256, b"ic08", Some(b"ic13")),
+    (512, b"ic09", Some(b"ic14")),
+    (1024, b"ic10", None),
+];
+
+/// Directory of the iOS icon set inside the ZIP.
+const IOS_ICONSET_DIR: &str = "AppIcon.appiconset";
+/// iOS app icon slots: idiom, size in points and scale.
+const IOS_ICONS: [(&str, f32, u32); 18] = [
+    ("iphone", 20.0, 2),
+    ("iphone", 20.0, 3),
+    ("iphone", 29.0, 2),
+    ("iphone", 29.0, 3),
+    ("iphone", 40.0, 2),
+    ("iphone", 40.0, 3),
+    ("iphone", 60.0, 2),
+    ("iphone", 60.0, 3),
+    ("ipad", 20.0, 1),
+    ("ipad", 20.0, 2),
+    ("ipad", 29.0, 1),
+    ("ipad", 29.0, 2),
+    ("ipad", 40.0, 1),
+    ("ipad", 40.0, 2),
+    ("ipad", 76.0, 1),
+    ("ipad", 76.0, 2),
+    ("ipad", 83.5, 2),
+    ("ios-marketing", 1024.0, 1),
+];
+
+/// A supported icon package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IconFormat {
+    /// Windows icon (`.ico`).
+    Ico,
+    /// Apple icon image (`.icns`).
+    Icns,
+    /// ZIP of Android `mipmap` launcher icons.
+    Android,
+    /// ZIP of an iOS `AppIcon.appiconset`.
+    Ios,
+}
+
+impl IconFormat {
+    /// The HTTP `Content-Type` of the package.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            IconFormat::Ico => "image/x-icon",
+            IconFormat::Icns => "image/icns",
+            IconFormat::Android | IconFormat::Ios => "application/zip",
+        }
+    }
+
+    /// A conventional file name for the package.
+    pub fn file_name(self) -> &'static str {
+        match self {
+            IconFormat::Ico => "favicon.ico",
+            IconFormat::Icns => "icon.icns",
+            IconFormat::Android => "android-icons.zip",
+            IconFormat::Ios => "AppIcon.appiconset.zip",
+        }
+    }
+
+    /// Whether every image must be opaque. The App Store rejects app icons with
+    /// an alpha channel.
+    pub fn requires_opaque(self) -> bool {
+        self == IconFormat::Ios
+    }
+}
+
+impl FromStr for IconFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ico" => Ok(IconFormat::Ico),
+            "icns" => Ok(IconFormat::Icns),
+            "android" => Ok(IconFormat::Android),
+            "ios" => Ok(IconFormat::Ios),
+            other => Err(format!(
+                "Invalid icon format '{}': expected one of ico, icns, android, ios",
+                other
+            )),
+        }
+    }
+}
+
+/// Options controlling icon export.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IconOptions {
+    /// The package to produce.
+    pub format: IconFormat,
+    /// Pixel sizes for `.ico` and `.icns`. The format's defaults if empty.
+    /// Android and iOS sizes are fixed by their conventions.
+    pub sizes: Vec<u32>,
+    /// File name of Android launcher icons. [`DEFAULT_ANDROID_NAME`] if unset.
+    pub name: Option<String>,
+    /// Render options applied to every size. `width`, `height` and `format`
+    /// are overridden; `fit`, `align`, `background`, `flatten` and `deadline` apply.
+    pub render: RenderOptions,
+}
+
+impl Default for IconOptions {
+    fn default() -> Self {
+        IconOptions { format: IconFormat::Ico, sizes: Vec::new(), name: None, render: RenderOptions::default() }
+    }
+}
+
+/// Validates the options and returns the pixel sizes to render, ascending and
+/// without duplicates.
+pub(crate) fn sizes(options: &IconOptions) -> Result<Vec<u32>, RenderError> {
+    let mut sizes = match options.format {
+        IconFormat::Ico | IconFormat::Icns if options.sizes.is_empty() => default_sizes(options.format).to_vec(),
+        IconFormat::Ico | IconFormat::Icns => options.sizes.clone(),
+        IconFormat::Android | IconFormat::Ios if !options.sizes.is_empty() => {
+            return Err(RenderError::InvalidOption(
+                "Icon sizes are fixed for android and ios and cannot be given".to_string(),
+            ))
+        }
+        IconFormat::Android => ANDROID_DENSITIES
+            .iter()
+            .map(|(_, size)| *size)
+            .chain([ANDROID_PLAY_STORE_SIZE])
+            .collect(),
+        IconFormat::Ios => IOS_ICONS.iter().map(|(_, points, scale)| ios_pixels(*points, *scale)).collect(),
+    };
+    sizes.sort_unstable();
+    sizes.dedup();
+
+    for &size in &sizes {
+        let valid = match options.format {
+            IconFormat::Ico => (1..=MAX_ICO_SIZE).contains(&size),
+            IconFormat::Icns => ICNS_TYPES.iter().any(|(s, _, _)| *s == size),
+            IconFormat::Android | IconFormat::Ios => true,
+        };
+        if !valid {
+            return Err(RenderError::InvalidOption(match options.format {
+                IconFormat::Icns => format!(
+                    "Invalid icns size {}: expected one of {}",
+                    size,
+                    DEFAULT_ICNS_SIZES.map(|s| s.to_string()).join(", ")
+                ),
+                _ => format!("Invalid ico size {}: expected 1 to {}", size, MAX_ICO_SIZE),
+            }));
+        }
+    }
+    if options.format == IconFormat::Android {
+        android_name(options)?;
+    }
+    Ok(sizes)
+}
+
+/// The default sizes of `.ico` and `.icns` packages.
+fn default_sizes(format: IconFormat) -> &'static [u32] {
+    match format {
+        IconFormat::Icns => &DEFAULT_ICNS_SIZES,
+        _ => &DEFAULT_ICO_SIZES,
+    }
+}
+
+/// The validated Android resource name.
+fn android_name(options: &IconOptions) -> Result<&str, RenderError> {
+    let name = options.name.as_deref().unwrap_or(DEFAULT_ANDROID_NAME);
+    let valid = name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if !valid {
+        return Err(RenderError::InvalidOption(format!(
+            "Invalid icon name '{}': Android resource names use lowercase letters, digits and underscores",
+            name
+        )));
+    }
+    Ok(name)
+}
+
+/// Pixel size of an iOS icon slot.
+fn ios_pixels(points: f32, scale: u32) -> u32 {
+    (points * scale as f32).round() as u32
+}
+
+/// Packages rendered PNGs.
+///
+/// # Arguments
+///
+/// * `options` - The icon options; must have passed [`sizes`].
+/// * `images` - One PNG per size returned by [`sizes`], in the same order.
+pub(crate) fn package(options: &IconOptions, images: &[EncodedImage]) -> Result<Vec<u8>, RenderError> {
+    let image = |size: u32| {
+        images
+            .iter()
+            .find(|image| image.width == size)
+            .ok_or_else(|| RenderError::Encoder(format!("Missing {}x{} icon image", size, size)))
+    };
+    match options.format {
+        IconFormat::Ico => Ok(encode_ico(images)),
+        IconFormat::Icns => encode_icns(images),
+        IconFormat::Android => {
+            let name = android_name(options)?;
+            let mut files = Vec::new();
+            for (density, size) in ANDROID_DENSITIES {
+                files.push((format!("res/mipmap-{}/{}.png", density, name), &image(size)?.data));
+            }
+            files.push((format!("{}-playstore.png", name), &image(ANDROID_PLAY_STORE_SIZE)?.data));
+            write_zip(files.iter().map(|(path, data)| (path.as_str(), data.as_slice())))
+        }
+        IconFormat::Ios => {
+            let mut contents = IosContents { images: Vec::new(), info: IosInfo { author: "svg2png", version: 1 } };
+            let mut files = Vec::new();
+            for (idiom, points, scale) in IOS_ICONS {
+                let pixels = ios_pixels(points, scale);
+                let filename = format!("icon-{}.png", pixels);
+                contents.images.push(IosImage {
+                    filename: filename.clone(),
+                    idiom,
+                    scale: format!("{}x", scale),
+                    size: format!("{}x{}", points, points),
+                });
+                if !files.iter().any(|(name, _)| *name == filename) {
+                    files.push((filename, image(pixels)?.data.clone()));
+                }
+            }
+            let contents = serde_json::to_vec_pretty(&contents)
+                .map_err(|e| RenderError::Encoder(format!("Failed to write Contents.json: {}", e)))?;
+            files.push(("Contents.json".to_string(), contents));
+            write_zip(files.iter().map(|(name, data)| (format!("{}/{}", IOS_ICONSET_DIR, name), data.as_slice())))
+        }
+    }
+}
+
+/// Writes an `.ico` with one PNG-compressed entry per image.
+fn encode_ico(images: &[EncodedImage]) -> Vec<u8> {
+    const HEADER_LEN: usize = 6;
+    const ENTRY_LEN: usize = 16;
+    let mut ico = Vec::new();
+    // ICONDIR: reserved, type (1 = icon), image count.
+    ico.extend_from_slice(&0u16.to_le_bytes());
+    ico.extend_from_slice(&1u16.to_le_bytes());
+    ico.extend_from_slice(&(images.len() as u16).to_le_bytes());
+
+    let mut offset = HEADER_LEN + ENTRY_LEN * images.len();
+    for image in images {
+        // ICONDIRENTRY: width and height (0 means 256), palette size, reserved,
+        // color planes, bits per pixel, data size and data offset.
+        ico.push(image.width as u8);
+        ico.push(image.height as u8);
+        ico.extend_from_slice(&[0, 0]);
+        ico.extend_from_slice(&1u16.to_le_bytes());
+        ico.extend_from_slice(&32u16.to_le_bytes());
+        ico.extend_from_slice(&(image.data.len() as u32).to_le_bytes());
+        ico.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += image.data.len();
+    }
+    for image in images {
+        ico.extend_from_slice(&image.data);
+    }
+    ico
+}
+
+/// Writes an `.icns` with one PNG element per image (and its `@2x` alias).
+fn encode_icns(images: &[EncodedImage]) -> Result<Vec<u8>, RenderError> {
+    let mut elements = Vec::new();
+    for image in images {
+        let (_, kind, retina_kind) = ICNS_TYPES
+            .iter()
+            .find(|(size, _, _)| *size == image.width)
+            .ok_or_else(|| RenderError::InvalidOption(format!("Invalid icns size {}", image.width)))?;
+        for kind in std::iter::once(*kind).chain(*retina_kind) {
+            elements.extend_from_slice(kind);
+            elements.extend_from_slice(&(8 + image.data.len() as u32).to_be_bytes());
+            elements.extend_from_slice(&image.data);
+        }
+    }
+    let mut icns = Vec::with_capacity(8 + elements.len());
+    icns.extend_from_slice(b"icns");
+    icns.extend_from_slice(&(8 + elements.len() as u32).to_be_bytes());
+    icns.extend_from_slice(&elements);
+    Ok(icns)
+}
+
+/// Writes a ZIP archive. PNGs are already compressed, so entries are stored.
+fn write_zip<'a, P: AsRef<str>>(files: impl Iterator<Item = (P, &'a [u8])>) -> Result<Vec<u8>, RenderError> {
+    let zip_error = |e: &dyn std::fmt::Display| RenderError::Encoder(format!("Failed to write ZIP archive: {}", e));
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    for (path, data) in files {
+        writer.start_file(path.as_ref(), options).map_err(|e| zip_error(&e))?;
+        writer.write_all(data).map_err(|e| zip_error(&e))?;
+    }
+    Ok(writer.finish().map_err(|e| zip_error(&e))?.into_inner())
+}
+
+/// The `Contents.json` of an `.appiconset`.
+#[derive(Debug, Serialize)]
+struct IosContents {
+    images: Vec<IosImage>,
+    info: IosInfo,
+}
+
+/// An image slot in `Contents.json`.
+#[derive(Debug, Serialize)]
+struct IosImage {
+    filename: String,
+    idiom: &'static str,
+    scale: String,
+    size: String,
+}
+
+/// The `info` block of `Contents.json`.
+#[derive(Debug, Serialize)]
+struct IosInfo {
+    author: &'static str,
+    version: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::OutputFormat;
+    use crate::renderer::PhaseTimings;
+
+    fn fake_png(size: u32) -> EncodedImage {
+        EncodedImage {
+            data: vec![size as u8; 10],
+            format: OutputFormat::Png,
+            width: size,
+            height: size,
+            timings: PhaseTimings::default(),
+        }
+    }
+
+    fn options(format: IconFormat, sizes: &[u32]) -> IconOptions {
+        IconOptions { format, sizes: sizes.to_vec(), ..IconOptions::default() }
+    }
+
+    #[test]
+    fn test_sizes_are_validated_and_deduplicated() {
+        assert_eq!(sizes(&options(IconFormat::Ico, &[])).unwrap(), DEFAULT_ICO_SIZES);
+        assert_eq!(sizes(&options(IconFormat::Ico, &[48, 16, 48])).unwrap(), [16, 48]);
+        assert!(sizes(&options(IconFormat::Ico, &[512])).is_err());
+        assert!(sizes(&options(IconFormat::Icns, &[48])).is_err());
+        assert!(sizes(&options(IconFormat::Ios, &[16])).is_err());
+        assert_eq!(sizes(&options(IconFormat::Android, &[])).unwrap(), [48, 72, 96, 144, 192, 512]);
+        let ios = sizes(&options(IconFormat::Ios, &[])).unwrap();
+        assert!(ios.contains(&167) && ios.contains(&1024));
+
+        let bad_name = IconOptions { name: Some("Launcher-Icon".to_string()), ..options(IconFormat::Android, &[]) };
+        assert!(sizes(&bad_name).is_err());
+    }
+
+    #[test]
+    fn test_ico_and_icns_layout() {
+        let images = [fake_png(16), fake_png(256)];
+        let ico = encode_ico(&images);
+        assert_eq!(ico[..6], [0, 0, 1, 0, 2, 0]);
+        // Second entry: 256 is stored as 0, data follows both entries and the first image.
+        assert_eq!((ico[22], ico[23]), (0, 0));
+        assert_eq!(u32::from_le_bytes(ico[34..38].try_into().unwrap()), 6 + 32 + 10);
+        assert_eq!(ico.len(), 6 + 32 + 20);
+
+        let icns = encode_icns(&[fake_png(16), fake_png(32)]).unwrap();
+        assert_eq!(&icns[..4], b"icns");
+        assert_eq!(u32::from_be_bytes(icns[4..8].try_into().unwrap()) as usize, icns.len());
+        assert_eq!(&icns[8..12], b"icp4");
+        assert_eq!(&icns[26..30], b"icp5");
+        assert_eq!(&icns[44..48], b"ic11");
+    }
+
+    #[test]
+    fn test_ios_contents_json() {
+        let options = options(IconFormat::Ios, &[]);
+        let images: Vec<_> = sizes(&options).unwrap().into_iter().map(fake_png).collect();
+        let archive = package(&options, &images).unwrap();
+        let mut archive = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+        let contents: serde_json::Value =
+            serde_json::from_reader(archive.by_name("AppIcon.appiconset/Contents.json").unwrap()).unwrap();
+        let images = contents["images"].as_array().unwrap();
+        assert_eq!(images.len(), IOS_ICONS.len());
+        let ipad_pro = images.iter().find(|i| i["size"] == "83.5x83.5").unwrap();
+        assert_eq!((&ipad_pro["idiom"], &ipad_pro["scale"]), (&"ipad".into(), &"2x".into()));
+        assert_eq!(ipad_pro["filename"], "icon-167.png");
+        assert!(archive.by_name("AppIcon.appiconset/icon-167.png").is_ok());
+    }
+}
@@ -0,0 +1,32 @@
+//! # svg2png
+//!
+//! Converts SVG images to PNG, JPEG, WebP and AVIF with resvg, and removes
+//! PNG backgrounds natively. The same [`Renderer`] backs the HTTP service
+//! shipped as the `svg2png` binary, so embedding it in another program gives
+//! identical output without running the service.
+//!
+//! ```no_run
+//! use svg2png::{Renderer, RenderOptions};
+//! use svg2png::config::{FontConfig, Limits};
+//!
+//! let renderer = Renderer::new(FontConfig::default(), Limits::default());
+//! let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"/>"#;
+//! let image = renderer.render_png(svg, &RenderOptions { dpi: 300.0, ..RenderOptions::default() })?;
+//! std::fs::write("out.png", &image.data)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod config;
+pub mod deadline;
+pub mod encode;
+pub mod error;
+pub mod icon;
+pub mod panic_guard;
+pub mod renderer;
+pub mod sizing;
+mod theme;
+pub mod transparency;
+
+pub use deadline::Deadline;
+pub use error::RenderError;
+pub use renderer::{EncodedImage, PhaseTimings, RenderOptions, Renderer};
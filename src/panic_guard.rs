@@ -0,0 +1,102 @@
+//! # Panic Isolation
+//!
+//! usvg, resvg and tiny-skia can panic on malformed or extreme input (e.g. an
+//! overflowing filter offset). [`catch`] runs a job under `catch_unwind` and
+//! turns a panic into [`RenderError::Panic`], so one bad SVG produces an
+//! error instead of taking down the caller's thread.
+//!
+//! The panic hook is process-wide, so the library never installs one on its
+//! own: without it, the panic message is still logged through `tracing`, and
+//! the standard hook prints it to stderr as usual. Programs that want the
+//! backtrace in the log instead call [`install`] once at startup. The hook
+//! records the backtrace of panics raised inside [`catch`] and passes panics
+//! elsewhere on to the previously installed hook unchanged.
+
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+
+use tracing::error;
+
+use crate::error::RenderError;
+
+thread_local! {
+    /// Whether the current thread is running a job inside [`catch`].
+    static GUARDED: Cell<bool> = const { Cell::new(false) };
+    /// Location and backtrace of the last guarded panic on this thread.
+    static CAPTURED: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Installs the backtrace-capturing panic hook.
+///
+/// Call it once from `main`, before rendering. Later calls do nothing.
+pub fn install() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !GUARDED.with(Cell::get) {
+                previous(info);
+                return;
+            }
+            let location = info.location().map(|l| l.to_string()).unwrap_or_default();
+            let report = format!("at {}\n{}", location, Backtrace::force_capture());
+            CAPTURED.with(|c| *c.borrow_mut() = Some(report));
+        }));
+    });
+}
+
+/// Runs `job`, converting a panic into [`RenderError::Panic`].
+///
+/// The panic message, and the backtrace if [`install`] was called, are logged
+/// but not returned to the client.
+pub(crate) fn catch<T>(job: impl FnOnce() -> Result<T, RenderError>) -> Result<T, RenderError> {
+    let was_guarded = GUARDED.with(|g| g.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(job));
+    GUARDED.with(|g| g.set(was_guarded));
+
+    result.unwrap_or_else(|payload| {
+        let message = panic_message(payload.as_ref());
+        let backtrace = CAPTURED.with(|c| c.borrow_mut().take()).unwrap_or_default();
+        error!(panic = %message, %backtrace, "Render panicked");
+        Err(RenderError::Panic)
+    })
+}
+
+/// Extracts the message from a panic payload.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hook_captures_guarded_panics_only() {
+        install();
+        let result: Result<(), RenderError> = catch(|| panic!("overflow"));
+        assert!(matches!(result, Err(RenderError::Panic)));
+        assert!(!GUARDED.with(Cell::get));
+        assert_eq!(catch(|| Ok(7)).unwrap(), 7);
+
+        // The hook records the location of a guarded panic for `catch` to log.
+        GUARDED.with(|g| g.set(true));
+        let _ = panic::catch_unwind(|| panic!("overflow"));
+        GUARDED.with(|g| g.set(false));
+        let report = CAPTURED.with(|c| c.borrow_mut().take()).unwrap();
+        assert!(report.starts_with("at src/panic_guard.rs:"), "{}", report);
+
+        // Unguarded panics go to the previous hook untouched.
+        let _ = panic::catch_unwind(|| panic!("elsewhere"));
+        assert!(CAPTURED.with(|c| c.borrow().is_none()));
+    }
+}
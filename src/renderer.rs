@@ -0,0 +1,757 @@
+//! # Renderer
+//!
+//! [`Renderer`] owns everything that is expensive to build and shared across
+//! conversions, most importantly the font database. Loading system fonts
+//! dominates the cost of a conversion, so it is done once (and on explicit
+//! reload) rather than per call. Each database comes with a
+//! [fingerprint](Renderer::font_fingerprint) of the fonts it holds, so callers
+//! caching rendered images can tell when the fonts changed, even across
+//! restarts.
+//!
+//! Every operation catches panics, so a panic inside usvg, resvg or tiny-skia
+//! is reported as [`RenderError::Panic`] instead of unwinding into the caller.
+//!
+//! Each phase runs in its own `info` span (`parse`, `render` and `encode`; or
+//! `decode`, `key` and `encode` for background removal), nested under the
+//! caller's current span, so traces show where the time of a conversion went.
+
+use std::borrow::Cow;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use resvg::tiny_skia::{NonZeroRect, Rect, Transform};
+use resvg::usvg::{self, fontdb, roxmltree, Node, Options, Size, Tree};
+use sha2::{Digest, Sha256};
+use tracing::{debug, error, info, info_span, warn};
+
+use crate::config::{FontConfig, Limits};
+use crate::deadline::Deadline;
+use crate::encode::{self, EncodeOptions, OutputFormat};
+use crate::error::RenderError;
+use crate::icon::{self, IconOptions};
+use crate::panic_guard;
+use crate::sizing::{self, Alignment, FitMode, Padding};
+use crate::theme;
+use crate::transparency::{self, KeyOptions};
+
+/// The DPI at which an SVG is rendered at its intrinsic size.
+pub const DEFAULT_DPI: f32 = 96.0;
+/// Background color used when flattening without an explicit `background`.
+const DEFAULT_FLATTEN_BACKGROUND: svgtypes::Color = svgtypes::Color { red: 255, green: 255, blue: 255, alpha: 255 };
+
+/// Options controlling how an SVG is rendered and encoded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderOptions {
+    /// Requested output DPI. Used for scaling when no explicit size is given.
+    pub dpi: f32,
+    /// Explicit output width in pixels.
+    pub width: Option<u32>,
+    /// Explicit output height in pixels.
+    pub height: Option<u32>,
+    /// Fit mode applied when both `width` and `height` are given.
+    pub fit: FitMode,
+    /// Content alignment inside the output canvas.
+    pub align: Alignment,
+    /// Color the canvas is filled with before rendering. Transparent if unset.
+    pub background: Option<svgtypes::Color>,
+    /// Whether to composite onto an opaque background and drop the alpha channel.
+    pub flatten: bool,
+    /// The output format.
+    pub format: OutputFormat,
+    /// Lossy encoding quality (1-100). Format default if unset.
+    pub quality: Option<u8>,
+    /// Whether to force lossless WebP encoding.
+    pub lossless: bool,
+    /// Deadline checked after parsing and after rendering. Unbounded if unset.
+    pub deadline: Option<Deadline>,
+    /// ID of the only element to render, cropped to its bounding box (including
+    /// stroke and filters). The whole canvas is rendered if unset.
+    pub element: Option<String>,
+    /// Region to render instead of the canvas, in the SVG's user coordinates
+    /// (those of its root `viewBox`), overriding the SVG's own `viewBox`.
+    pub view_box: Option<NonZeroRect>,
+    /// Whether to crop to the bounding box of the drawn content.
+    pub trim: bool,
+    /// Space added around the rendered region, in canvas pixels.
+    pub padding: Padding,
+    /// CSS injected while parsing. Like a user-agent stylesheet, it overrides
+    /// presentation attributes but not the SVG's own `<style>` or `style` rules.
+    pub style_sheet: Option<String>,
+    /// The CSS `color` of the root element, which `currentColor` resolves to.
+    pub color: Option<svgtypes::Color>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            dpi: DEFAULT_DPI,
+            width: None,
+            height: None,
+            fit: FitMode::default(),
+            align: Alignment::default(),
+            background: None,
+            flatten: false,
+            format: OutputFormat::default(),
+            quality: None,
+            lossless: false,
+            deadline: None,
+            element: None,
+            view_box: None,
+            trim: false,
+            padding: Padding::default(),
+            style_sheet: None,
+            color: None,
+        }
+    }
+}
+
+/// An encoded output image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodedImage {
+    /// The encoded image bytes.
+    pub data: Vec<u8>,
+    /// The format `data` is encoded in.
+    pub format: OutputFormat,
+    /// Width in pixels.
+    pub width: u32,
+    /// Height in pixels.
+    pub height: u32,
+    /// Time spent in each phase of producing the image.
+    pub timings: PhaseTimings,
+}
+
+/// Time spent in each phase of an operation.
+///
+/// For [`Renderer::make_transparent`], `parse` is PNG decoding and `render` is
+/// background removal.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PhaseTimings {
+    /// Parsing the input.
+    pub parse: Duration,
+    /// Rendering (or keying) into a pixmap.
+    pub render: Duration,
+    /// Encoding the output.
+    pub encode: Duration,
+}
+
+/// Renders SVGs and removes PNG backgrounds with a shared font database.
+///
+/// `Renderer` is `Send + Sync`; share it behind an `Arc` to use it from
+/// several threads.
+pub struct Renderer {
+    /// The current usvg options, including the pre-built font database, and
+    /// the fingerprint of that database.
+    ///
+    /// The options are wrapped in an `Arc` inside the lock so callers can take
+    /// a snapshot without holding the lock while parsing, and a reload can
+    /// swap in a new database without affecting in-flight renders.
+    fonts: RwLock<(Arc<Options<'static>>, [u8; 32])>,
+    /// Font settings used to (re)build the font database.
+    font_config: FontConfig,
+    /// Resource limits enforced on every operation.
+    limits: Limits,
+}
+
+impl Renderer {
+    /// Creates a renderer, loading the font database.
+    ///
+    /// This is blocking (it reads every font file).
+    ///
+    /// # Arguments
+    ///
+    /// * `font_config` - Which font directories to load and the fallback families.
+    /// * `limits` - Resource limits enforced on every operation.
+    pub fn new(font_config: FontConfig, limits: Limits) -> Self {
+        Renderer {
+            fonts: RwLock::new(load_fonts(&font_config)),
+            font_config,
+            limits,
+        }
+    }
+
+    /// Returns a snapshot of the current usvg options.
+    pub fn options(&self) -> Arc<Options<'static>> {
+        self.fonts.read().unwrap_or_else(|e| e.into_inner()).0.clone()
+    }
+
+    /// Returns the SHA-256 fingerprint of the current fonts.
+    ///
+    /// It covers the font settings and every loaded face (family names, file,
+    /// index within the file, style, weight and stretch), so it changes
+    /// whenever a reload or a restart picks up different fonts.
+    pub fn font_fingerprint(&self) -> [u8; 32] {
+        self.fonts.read().unwrap_or_else(|e| e.into_inner()).1
+    }
+
+    /// Returns the current font database.
+    pub fn fontdb(&self) -> Arc<fontdb::Database> {
+        self.options().fontdb.clone()
+    }
+
+    /// Returns the resource limits.
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Rescans the font directories and swaps in the new font database.
+    ///
+    /// This is blocking (it reads every font file) and should be run off the
+    /// async runtime, e.g. via `tokio::task::spawn_blocking`.
+    ///
+    /// # Returns
+    ///
+    /// The number of font faces in the new database.
+    pub fn reload_fonts(&self) -> usize {
+        let fonts = load_fonts(&self.font_config);
+        let faces = fonts.0.fontdb.len();
+        *self.fonts.write().unwrap_or_else(|e| e.into_inner()) = fonts;
+        info!(faces, "Font database reloaded");
+        faces
+    }
+
+    /// Renders an SVG and encodes it as PNG, ignoring `options.format`.
+    pub fn render_png(&self, svg: &[u8], options: &RenderOptions) -> Result<EncodedImage, RenderError> {
+        self.render(svg, &RenderOptions { format: OutputFormat::Png, ..options.clone() })
+    }
+
+    /// Parses, renders and encodes an SVG according to `options`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(EncodedImage)` - The encoded image.
+    /// * `Err(RenderError)` - If the SVG is empty, invalid or zero-sized, has no
+    ///   element with `options.element` as its ID, exceeds the limits, panics the
+    ///   renderer, fails to encode or misses its deadline.
+    pub fn render(&self, svg: &[u8], options: &RenderOptions) -> Result<EncodedImage, RenderError> {
+        let usvg_options = self.options();
+        panic_guard::catch(|| render_svg(&usvg_options, &self.limits, options, svg))
+    }
+
+    /// Renders an SVG at several square sizes and packages them as an icon.
+    ///
+    /// The SVG is parsed once and rendered as PNG at each size the format
+    /// calls for (see [`icon`](crate::icon)).
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<u8>)` - The `.ico`, `.icns` or ZIP archive.
+    /// * `Err(RenderError)` - If the sizes or name are invalid, or any size fails
+    ///   to render for the reasons [`render`](Self::render) gives.
+    pub fn render_icon(&self, svg: &[u8], options: &IconOptions) -> Result<Vec<u8>, RenderError> {
+        let sizes = icon::sizes(options)?;
+        let usvg_options = self.options();
+        let render = RenderOptions {
+            dpi: DEFAULT_DPI,
+            format: OutputFormat::Png,
+            flatten: options.render.flatten || options.format.requires_opaque(),
+            ..options.render.clone()
+        };
+        panic_guard::catch(|| {
+            let document = parse_svg(&usvg_options, svg, &render)?;
+            let images = sizes
+                .iter()
+                .map(|&size| {
+                    render_tree(&document, &self.limits, &RenderOptions { width: Some(size), height: Some(size), ..render.clone() })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            debug!(format = ?options.format, sizes = images.len(), "Rendered icon sizes");
+            icon::package(options, &images)
+        })
+    }
+
+    /// Makes the background of a PNG transparent.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(EncodedImage)` - An 8-bit RGBA PNG, preserving the input's `pHYs` chunk.
+    /// * `Err(RenderError)` - If the PNG is empty, invalid or exceeds the limits,
+    ///   a seed lies outside the image, encoding fails or the deadline passes.
+    pub fn make_transparent(&self, png: &[u8], options: &KeyOptions) -> Result<EncodedImage, RenderError> {
+        if png.is_empty() {
+            return Err(RenderError::EmptyInput);
+        }
+        panic_guard::catch(|| {
+            let started = Instant::now();
+            let span = info_span!("decode", bytes = png.len()).entered();
+            let mut bitmap = transparency::decode_png(png, &self.limits)?;
+            check_deadline(options.deadline, "decode")?;
+            drop(span);
+            let decoded = Instant::now();
+
+            // Sample the seed pixels (top-left by default) and key out the background.
+            let span = info_span!("key", width = bitmap.width, height = bitmap.height).entered();
+            let cleared = transparency::remove_background(&mut bitmap, options)?;
+            debug!(cleared, width = bitmap.width, height = bitmap.height, "Removed background");
+            check_deadline(options.deadline, "remove background")?;
+            drop(span);
+            let keyed = Instant::now();
+
+            let _span = info_span!("encode", format = ?OutputFormat::Png).entered();
+            let data = transparency::encode_png(&bitmap)?;
+            debug!(bytes = data.len(), "PNG encoding complete");
+            let timings = PhaseTimings {
+                parse: decoded - started,
+                render: keyed - decoded,
+                encode: keyed.elapsed(),
+            };
+            Ok(EncodedImage { data, format: OutputFormat::Png, width: bitmap.width, height: bitmap.height, timings })
+        })
+    }
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Self::new(FontConfig::default(), Limits::default())
+    }
+}
+
+/// Checks an optional deadline.
+fn check_deadline(deadline: Option<Deadline>, phase: &str) -> Result<(), RenderError> {
+    deadline.map_or(Ok(()), |d| d.check(phase))
+}
+
+/// Builds the usvg options and computes the fingerprint of their fonts.
+fn load_fonts(config: &FontConfig) -> (Arc<Options<'static>>, [u8; 32]) {
+    let options = build_options(config);
+    let fingerprint = font_fingerprint(config, &options.fontdb);
+    (Arc::new(options), fingerprint)
+}
+
+/// Hashes the font settings and the loaded faces.
+///
+/// Faces are sorted first, so the order in which directories were scanned
+/// does not matter.
+fn font_fingerprint(config: &FontConfig, db: &fontdb::Database) -> [u8; 32] {
+    /// Bumped whenever the hashed fields or their layout change.
+    const VERSION: u8 = 1;
+
+    // Length prefixes keep the boundaries between the parts unambiguous.
+    fn part(out: &mut Vec<u8>, bytes: &[u8]) {
+        out.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+        out.extend_from_slice(bytes);
+    }
+
+    let mut faces: Vec<Vec<u8>> = db
+        .faces()
+        .map(|face| {
+            let mut out = Vec::new();
+            let path = match &face.source {
+                fontdb::Source::File(path) | fontdb::Source::SharedFile(path, _) => path.to_string_lossy(),
+                fontdb::Source::Binary(_) => Cow::Borrowed(""),
+            };
+            part(&mut out, path.as_bytes());
+            part(&mut out, &face.index.to_le_bytes());
+            for (family, _) in &face.families {
+                part(&mut out, family.as_bytes());
+            }
+            part(&mut out, face.post_script_name.as_bytes());
+            let style: u8 = match face.style {
+                fontdb::Style::Normal => 0,
+                fontdb::Style::Italic => 1,
+                fontdb::Style::Oblique => 2,
+            };
+            part(&mut out, &[style]);
+            part(&mut out, &face.weight.0.to_le_bytes());
+            part(&mut out, &face.stretch.to_number().to_le_bytes());
+            out
+        })
+        .collect();
+    faces.sort();
+
+    let FontConfig { font_dirs, load_system_fonts, serif, sans_serif, monospace, cursive, fantasy, default_family } =
+        config;
+    let mut settings = vec![VERSION, u8::from(*load_system_fonts)];
+    for dir in font_dirs {
+        part(&mut settings, dir.to_string_lossy().as_bytes());
+    }
+    part(&mut settings, serif.as_bytes());
+    for family in [sans_serif, monospace, cursive, fantasy, default_family] {
+        // An unset family and an empty one are different settings.
+        match family {
+            Some(family) => part(&mut settings, &[&[1], family.as_bytes()].concat()),
+            None => part(&mut settings, &[0]),
+        }
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(&settings);
+    for face in &faces {
+        hasher.update((face.len() as u64).to_le_bytes());
+        hasher.update(face);
+    }
+    hasher.finalize().into()
+}
+
+/// Builds usvg options with an explicitly loaded font database.
+///
+/// System fonts (if enabled) are loaded first, then each configured font
+/// directory, so brand fonts shipped alongside the service are always available.
+fn build_options(config: &FontConfig) -> Options<'static> {
+    // Note: `usvg::Options::dpi` is not used directly as its effect on scaling wasn't
+    // clear from documentation at the time of writing. Manual scaling via `resvg::render`
+    // transform is used instead for explicit control.
+    let mut db = fontdb::Database::new();
+    if config.load_system_fonts {
+        // Load fonts installed on the system (e.g., via apt in Docker).
+        db.load_system_fonts();
+    }
+    for dir in &config.font_dirs {
+        if !dir.is_dir() {
+            warn!(dir = %dir.display(), "Configured font directory does not exist");
+            continue;
+        }
+        debug!(dir = %dir.display(), "Loading fonts from directory");
+        db.load_fonts_dir(dir);
+    }
+
+    // Map the generic CSS families to concrete fonts.
+    db.set_serif_family(config.serif.as_str());
+    if let Some(family) = &config.sans_serif {
+        db.set_sans_serif_family(family.as_str());
+    }
+    if let Some(family) = &config.monospace {
+        db.set_monospace_family(family.as_str());
+    }
+    if let Some(family) = &config.cursive {
+        db.set_cursive_family(family.as_str());
+    }
+    if let Some(family) = &config.fantasy {
+        db.set_fantasy_family(family.as_str());
+    }
+
+    let mut options = Options {
+        // Explicitly wrap the database in an Arc for the Options struct.
+        fontdb: Arc::new(db),
+        ..Options::default()
+    };
+    // Used for text elements that do not specify a `font-family` at all.
+    if let Some(family) = &config.default_family {
+        options.font_family = family.clone();
+    }
+    debug!(faces = options.fontdb.len(), "Loaded font database");
+    options
+}
+
+/// Parses, renders and encodes an SVG.
+///
+/// # Arguments
+///
+/// * `usvg_options` - The usvg options (including the shared font database) to parse with.
+/// * `limits` - The resource limits checked before the pixmap is allocated.
+/// * `options` - The render options.
+/// * `svg` - The raw SVG data.
+fn render_svg(
+    usvg_options: &Options<'static>,
+    limits: &Limits,
+    options: &RenderOptions,
+    svg: &[u8],
+) -> Result<EncodedImage, RenderError> {
+    limits.check_dpi(options.dpi).inspect_err(|e| error!(error = %e, "DPI limit exceeded"))?;
+    let started = Instant::now();
+    let document = parse_svg(usvg_options, svg, options)?;
+    let parse = started.elapsed();
+    let mut image = render_tree(&document, limits, options)?;
+    image.timings.parse = parse;
+    Ok(image)
+}
+
+/// A parsed SVG.
+struct Document {
+    /// The usvg render tree.
+    tree: Tree,
+    /// Maps the SVG's user coordinates (those of its root `viewBox`) to the canvas.
+    user_transform: Transform,
+}
+
+/// Parses an SVG with the shared font database and the options' stylesheet.
+///
+/// The XML is parsed here rather than by `Tree::from_data` so the root
+/// `viewBox` stays available to [`RenderOptions::view_box`].
+fn parse_svg(usvg_options: &Options<'static>, svg: &[u8], options: &RenderOptions) -> Result<Document, RenderError> {
+    let _span = info_span!("parse", bytes = svg.len()).entered();
+    if svg.is_empty() {
+        error!("Received empty SVG data");
+        return Err(RenderError::EmptyInput);
+    }
+
+    debug!(font_faces = usvg_options.fontdb.len(), "Parsing SVG data with shared font database");
+    let parse_error = |e: usvg::Error| {
+        error!(error = %e, "Invalid SVG data received");
+        RenderError::SvgParse(e.to_string())
+    };
+    let data = if svg.starts_with(&[0x1f, 0x8b]) {
+        Cow::Owned(usvg::decompress_svgz(svg).map_err(parse_error)?)
+    } else {
+        Cow::Borrowed(svg)
+    };
+    let text = std::str::from_utf8(&data).map_err(|_| parse_error(usvg::Error::NotAnUtf8Str))?;
+    let xml_options = roxmltree::ParsingOptions { allow_dtd: true, ..roxmltree::ParsingOptions::default() };
+    let xml = roxmltree::Document::parse_with_options(text, xml_options)
+        .map_err(|e| parse_error(usvg::Error::ParsingFailed(e)))?;
+    let tree = match theme::style_sheet(options) {
+        Some(style_sheet) => {
+            debug!(bytes = style_sheet.len(), "Injecting stylesheet");
+            Tree::from_xmltree(&xml, &theme::with_style_sheet(usvg_options, style_sheet))
+        }
+        None => Tree::from_xmltree(&xml, usvg_options),
+    }
+    .map_err(parse_error)?;
+    check_deadline(options.deadline, "parse")?;
+
+    let root = xml.root_element();
+    let user_transform = match root.attribute("viewBox").and_then(|v| sizing::parse_view_box(v).ok()) {
+        Some(view_box) => {
+            let aspect = root.attribute("preserveAspectRatio").and_then(|v| v.parse().ok()).unwrap_or_default();
+            sizing::view_box_transform(view_box, aspect, tree.size())
+        }
+        None => Transform::identity(),
+    };
+    Ok(Document { tree, user_transform })
+}
+
+/// Renders and encodes a parsed SVG.
+fn render_tree(document: &Document, limits: &Limits, options: &RenderOptions) -> Result<EncodedImage, RenderError> {
+    // Calculate the scale factor based on the requested DPI relative to the default.
+    let scale = options.dpi / DEFAULT_DPI;
+
+    let (node, region) = select_region(document, options)?;
+    let base_size = Size::from_wh(region.width(), region.height()).ok_or_else(|| {
+        let err = RenderError::ZeroSize;
+        error!(%err, ?region, "Render region is empty");
+        err
+    })?;
+    debug!(?base_size, ?region, "Got base SVG size");
+    let base_width = base_size.width();
+    let base_height = base_size.height();
+
+    // Calculate the target pixmap dimensions and transform. The canvas size is
+    // rounded up so the scaled image is never clipped.
+    let layout = sizing::compute_layout(base_size, scale, options.width, options.height, options.fit, options.align);
+    let target_width = layout.width;
+    let target_height = layout.height;
+    debug!(target_width, target_height, ?layout, "Calculated target pixmap dimensions");
+
+    if target_width == 0 || target_height == 0 {
+        let err = RenderError::ZeroSize;
+        error!(%err, base_width, base_height, scale);
+        return Err(err);
+    }
+    limits
+        .check_dimensions(target_width, target_height)
+        .inspect_err(|e| error!(error = %e, base_width, base_height, scale, "Output size limit exceeded"))?;
+
+    let render_started = Instant::now();
+    let span = info_span!("render", width = target_width, height = target_height).entered();
+    debug!(target_width, target_height, "Creating pixmap");
+    let mut pixmap = resvg::tiny_skia::Pixmap::new(target_width, target_height).ok_or_else(|| {
+        let err = RenderError::PixmapAlloc { width: target_width, height: target_height };
+        error!(%err, target_width, target_height);
+        err
+    })?;
+
+    // Formats without an alpha channel are always flattened.
+    let format = options.format;
+    let flatten = options.flatten || !format.supports_alpha();
+    if let Some(fill) = canvas_fill(options.background, flatten) {
+        debug!(?fill, "Filling pixmap background");
+        pixmap.fill(fill);
+    }
+
+    // Move the top-left corner of the region to the origin of the layout.
+    let transform = layout.transform.pre_translate(-region.x(), -region.y());
+
+    debug!(?transform, "Rendering SVG to pixmap");
+    // Render the SVG tree (or the selected element) to the pixmap using the
+    // calculated scaling transform. `resvg::render` can panic on extreme input;
+    // the caller catches it.
+    match node {
+        None => resvg::render(&document.tree, transform, &mut pixmap.as_mut()),
+        Some(node) => render_element(node, transform, &mut pixmap)?,
+    }
+    debug!("SVG rendering complete");
+    check_deadline(options.deadline, "render")?;
+    drop(span);
+
+    // The effective DPI reflects the scale actually applied, which differs from
+    // the requested DPI when an explicit width/height is given (and may differ
+    // per axis for `fit=fill`).
+    let encode_options = EncodeOptions {
+        format,
+        quality: options.quality,
+        lossless: options.lossless,
+        flatten,
+        dpi_x: layout.scale_x * DEFAULT_DPI,
+        dpi_y: layout.scale_y * DEFAULT_DPI,
+    };
+    let encode_started = Instant::now();
+    let _span = info_span!("encode", ?format).entered();
+    let data = encode::encode(&pixmap, &encode_options)?;
+    debug!(?format, bytes = data.len(), "Encoding complete");
+
+    let timings = PhaseTimings {
+        parse: Duration::ZERO,
+        render: encode_started - render_started,
+        encode: encode_started.elapsed(),
+    };
+    Ok(EncodedImage { data, format, width: target_width, height: target_height, timings })
+}
+
+/// Selects the part of the canvas to render, in canvas user units.
+///
+/// # Returns
+///
+/// The element to render on its own (if `options.element` is set) and the
+/// region to crop to: the element's bounding box, the `view_box`, the drawn
+/// content (`trim`) or the whole canvas, grown by the padding.
+fn select_region<'a>(document: &'a Document, options: &RenderOptions) -> Result<(Option<&'a Node>, Rect), RenderError> {
+    let tree = &document.tree;
+    let canvas = tree.size().to_non_zero_rect(0.0, 0.0).to_rect();
+    let selectors = [options.element.is_some(), options.view_box.is_some(), options.trim];
+    if selectors.iter().filter(|selected| **selected).count() > 1 {
+        return Err(RenderError::InvalidOption(
+            "Only one of an element, a view box and trimming can select the region to render".to_string(),
+        ));
+    }
+
+    let mut node = None;
+    let region = if let Some(id) = options.element.as_deref() {
+        let element = tree.node_by_id(id).ok_or_else(|| {
+            let err = RenderError::ElementNotFound(id.to_string());
+            error!(%err);
+            err
+        })?;
+        node = Some(element);
+        // Groups report their layer bounds, which include filter regions; other
+        // nodes are cropped to their stroke so outlines are not clipped.
+        match element {
+            Node::Group(group) => group.abs_layer_bounding_box().to_rect(),
+            _ => element.abs_stroke_bounding_box(),
+        }
+    } else if let Some(view_box) = options.view_box {
+        view_box.transform(document.user_transform).ok_or(RenderError::ZeroSize)?.to_rect()
+    } else if options.trim {
+        if !tree.root().has_children() {
+            return Err(RenderError::ZeroSize);
+        }
+        // Content outside the canvas is never drawn, so it does not count.
+        tree.root().abs_layer_bounding_box().to_rect().intersect(&canvas).ok_or(RenderError::ZeroSize)?
+    } else {
+        canvas
+    };
+    let padded = options.padding.expand(region).ok_or(RenderError::ZeroSize)?;
+    debug!(element = options.element, ?region, ?padded, "Selected render region");
+    Ok((node, padded))
+}
+
+/// Renders a single element.
+///
+/// `transform` maps canvas coordinates to the pixmap. `resvg::render_node`
+/// neither applies the transforms of the element's ancestors nor expects
+/// canvas coordinates, so both are adjusted for here.
+fn render_element(node: &Node, transform: Transform, pixmap: &mut resvg::tiny_skia::Pixmap) -> Result<(), RenderError> {
+    // A group applies its own transform while rendering; other nodes live in
+    // their parent's coordinate system.
+    let parent = match node {
+        Node::Group(group) => group.abs_transform().pre_concat(group.transform().invert().unwrap_or_default()),
+        _ => node.abs_transform(),
+    };
+    // `render_node` translates by the layer bounds before applying `transform`.
+    let bounds = node.abs_layer_bounding_box().ok_or(RenderError::ZeroSize)?;
+    let transform = transform.pre_concat(parent).pre_translate(bounds.x(), bounds.y());
+    resvg::render_node(node, transform, &mut pixmap.as_mut()).ok_or(RenderError::ZeroSize)
+}
+
+/// Determines the color the pixmap is filled with before rendering.
+///
+/// When flattening, the background (defaulting to white) is composited onto
+/// opaque white so the canvas ends up fully opaque; otherwise the requested
+/// background is used as-is, including its alpha.
+fn canvas_fill(background: Option<svgtypes::Color>, flatten: bool) -> Option<resvg::tiny_skia::Color> {
+    let color = match (background, flatten) {
+        (None, false) => return None,
+        (Some(bg), false) => return Some(resvg::tiny_skia::Color::from_rgba8(bg.red, bg.green, bg.blue, bg.alpha)),
+        (bg, true) => bg.unwrap_or(DEFAULT_FLATTEN_BACKGROUND),
+    };
+    let alpha = color.alpha as u32;
+    let over_white = |c: u8| ((c as u32 * alpha + 255 * (255 - alpha) + 127) / 255) as u8;
+    Some(resvg::tiny_skia::Color::from_rgba8(
+        over_white(color.red),
+        over_white(color.green),
+        over_white(color.blue),
+        255,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_font_config_applied() {
+        let dir = tempfile::tempdir().unwrap();
+        let renderer = Renderer::new(
+            FontConfig {
+                font_dirs: vec![dir.path().to_path_buf()],
+                load_system_fonts: false,
+                monospace: Some("Brand Mono".to_string()),
+                default_family: Some("Brand Sans".to_string()),
+                ..FontConfig::default()
+            },
+            Limits::default(),
+        );
+
+        let options = renderer.options();
+        assert_eq!(options.fontdb.len(), 0);
+        assert_eq!(options.font_family, "Brand Sans");
+        assert_eq!(options.fontdb.family_name(&fontdb::Family::Monospace), "Brand Mono");
+        assert_eq!(options.fontdb.family_name(&fontdb::Family::Serif), "Liberation Serif");
+    }
+
+    #[test]
+    fn test_font_fingerprint_tracks_settings_and_faces() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = FontConfig {
+            font_dirs: vec![dir.path().to_path_buf()],
+            load_system_fonts: false,
+            ..FontConfig::default()
+        };
+        let renderer = Renderer::new(config.clone(), Limits::default());
+        let empty = renderer.font_fingerprint();
+        // The same fonts give the same fingerprint after a restart.
+        assert_eq!(Renderer::new(config.clone(), Limits::default()).font_fingerprint(), empty);
+        let remapped = FontConfig { monospace: Some("Brand Mono".to_string()), ..config };
+        assert_ne!(Renderer::new(remapped, Limits::default()).font_fingerprint(), empty);
+
+        let system = Renderer::default().fontdb();
+        let font = system
+            .faces()
+            .find_map(|face| match &face.source {
+                fontdb::Source::File(path) | fontdb::Source::SharedFile(path, _) => Some(path.clone()),
+                fontdb::Source::Binary(_) => None,
+            })
+            .expect("No system font file to copy");
+        std::fs::copy(&font, dir.path().join(font.file_name().unwrap())).unwrap();
+        renderer.reload_fonts();
+        assert_ne!(renderer.font_fingerprint(), empty);
+    }
+
+    #[test]
+    fn test_render_png_without_http() {
+        let renderer = Renderer::new(
+            FontConfig { load_system_fonts: false, ..FontConfig::default() },
+            Limits::default(),
+        );
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="50"><rect width="100" height="50" fill="blue"/></svg>"#;
+
+        let options = RenderOptions { dpi: 192.0, format: OutputFormat::Jpeg, ..RenderOptions::default() };
+        let image = renderer.render_png(svg, &options).unwrap();
+        assert_eq!((image.format, image.width, image.height), (OutputFormat::Png, 200, 100));
+        assert!(image.data.starts_with(b"\x89PNG"));
+
+        let keyed = renderer.make_transparent(&image.data, &KeyOptions::default()).unwrap();
+        let bitmap = transparency::decode_png(&keyed.data, renderer.limits()).unwrap();
+        assert_eq!(bitmap.pixel(0, 0)[3], 0);
+
+        assert_eq!(renderer.render(b"", &options), Err(RenderError::EmptyInput));
+    }
+}
@@ -0,0 +1,63 @@
+//! # Admin Endpoints
+//!
+//! `/admin/*` endpoints change the state of the running service, so they are
+//! only served to requests carrying `Authorization: Bearer <token>` with the
+//! token configured in `SVG2PNG_ADMIN_TOKEN`. Without a configured token they
+//! are disabled and every call fails with `401 Unauthorized`.
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use tracing::warn;
+
+use super::error::AppError;
+use super::state::AppState;
+
+/// Middleware that rejects requests without the configured admin token.
+pub async fn require_token(State(state): State<AppState>, request: Request, next: Next) -> Result<Response, AppError> {
+    let Some(token) = state.admin().token.as_deref() else {
+        warn!("Admin endpoint called, but no admin token is configured");
+        return Err(AppError::Unauthorized(
+            "Admin endpoints are disabled; set SVG2PNG_ADMIN_TOKEN to enable them".to_string(),
+        ));
+    };
+    if !token_matches(token, request.headers().get(header::AUTHORIZATION)) {
+        warn!("Admin endpoint called without a valid token");
+        return Err(AppError::Unauthorized("Missing or invalid admin token".to_string()));
+    }
+    Ok(next.run(request).await)
+}
+
+/// Whether an `Authorization` header carries `Bearer <token>`.
+///
+/// The token is compared in constant time, so response timing does not reveal
+/// how much of a guess was right.
+fn token_matches(token: &str, authorization: Option<&HeaderValue>) -> bool {
+    let Some(given) = authorization
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+    let (given, token) = (given.trim().as_bytes(), token.as_bytes());
+    given.len() == token.len() && given.iter().zip(token).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_matches() {
+        let matches = |value: &str| token_matches("s3cret", Some(&HeaderValue::from_str(value).unwrap()));
+        assert!(matches("Bearer s3cret"));
+        assert!(!matches("Bearer s3cre"));
+        assert!(!matches("Bearer s3cret2"));
+        assert!(!matches("Basic s3cret"));
+        assert!(!matches("s3cret"));
+        assert!(!token_matches("s3cret", None));
+    }
+}
@@ -0,0 +1,370 @@
+//! # Batch Conversion
+//!
+//! `/batch/svg-to-png` converts many SVGs in one request. The SVGs arrive as a
+//! ZIP archive or as the file fields of a `multipart/form-data` body; the
+//! response is a ZIP archive holding one PNG per SVG at the same relative path
+//! (`icons/a.svg` becomes `icons/a.png`) plus a [`MANIFEST_NAME`] file:
+//!
+//! ```json
+//! {
+//!   "succeeded": 1,
+//!   "failed": 1,
+//!   "files": [
+//!     { "path": "icons/a.svg", "status": "ok", "output": "icons/a.png", "width": 64, "height": 64 },
+//!     { "path": "icons/b.svg", "status": "error",
+//!       "error": { "code": "svg_parse_error", "detail": "Invalid SVG: ..." } }
+//!   ]
+//! }
+//! ```
+//!
+//! A failing SVG does not fail the batch; its error is recorded in the
+//! manifest with the same `code` the single-file endpoints would report.
+//!
+//! Archives are untrusted: entry paths that are absolute or contain `..` are
+//! rejected, and the number of SVGs and their total uncompressed size are
+//! bounded by [`Limits::max_batch_files`] and [`Limits::max_batch_bytes`].
+
+use std::collections::HashSet;
+use std::io::{Cursor, Read, Write};
+
+use axum::{body::Bytes, http::HeaderValue};
+use serde::Serialize;
+use svg2png::config::Limits;
+use svg2png::{EncodedImage, RenderError};
+use tracing::debug;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use super::error::AppError;
+use super::form;
+
+/// Name of the manifest inside the response archive.
+pub const MANIFEST_NAME: &str = "manifest.json";
+/// File extension of SVG inputs.
+const SVG_EXTENSION: &str = ".svg";
+/// Directory macOS adds to ZIP archives for resource forks.
+const MACOS_METADATA_DIR: &str = "__MACOSX/";
+
+/// An SVG taken from a batch request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchFile {
+    /// Normalized relative path, using `/` as the separator.
+    pub path: String,
+    /// The raw SVG data.
+    pub data: Bytes,
+}
+
+/// Extracts the SVGs from a ZIP archive.
+///
+/// Directories, files without an `.svg` extension and macOS metadata are skipped.
+///
+/// # Returns
+///
+/// * `Ok(Vec<BatchFile>)` - The SVGs, in archive order.
+/// * `Err(AppError)` - `400 Bad Request` (`invalid_archive`) if the archive is
+///   invalid or has unsafe or duplicate paths; `422 Unprocessable Entity`
+///   (`limit_exceeded`) if it exceeds the batch limits.
+pub fn read_zip(data: &[u8], limits: &Limits) -> Result<Vec<BatchFile>, AppError> {
+    let mut archive = ZipArchive::new(Cursor::new(data))
+        .map_err(|e| AppError::InvalidArchive(format!("Invalid ZIP archive: {}", e)))?;
+    let mut collector = Collector::new(limits);
+    for index in 0..archive.len() {
+        let entry = archive
+            .by_index(index)
+            .map_err(|e| AppError::InvalidArchive(format!("Invalid ZIP entry {}: {}", index, e)))?;
+        let name = entry.name().to_string();
+        if entry.is_dir() || name.starts_with(MACOS_METADATA_DIR) || !has_svg_extension(&name) {
+            debug!(name, "Skipping ZIP entry");
+            continue;
+        }
+
+        // Read at most one byte more than the remaining budget, so a ZIP bomb is
+        // detected without inflating it completely.
+        let mut data = Vec::new();
+        entry
+            .take(collector.remaining_bytes().saturating_add(1))
+            .read_to_end(&mut data)
+            .map_err(|e| AppError::InvalidArchive(format!("Failed to extract '{}': {}", name, e)))?;
+        collector.push(&name, data.into())?;
+    }
+    Ok(collector.files)
+}
+
+/// Extracts the SVGs from the file fields of a `multipart/form-data` body.
+///
+/// Every field with a file name is treated as an SVG; other fields are ignored.
+///
+/// # Returns
+///
+/// * `Ok(Vec<BatchFile>)` - The SVGs, in field order.
+/// * `Err(AppError)` - As for [`read_zip`].
+pub async fn read_multipart(
+    content_type: &HeaderValue,
+    body: Bytes,
+    limits: &Limits,
+) -> Result<Vec<BatchFile>, AppError> {
+    let mut multipart = form::multipart(content_type, body).await.map_err(AppError::InvalidArchive)?;
+
+    let mut collector = Collector::new(limits);
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| AppError::InvalidArchive(e.body_text()))?
+    {
+        let Some(name) = field.file_name().map(str::to_string) else {
+            debug!(field = field.name(), "Skipping multipart field without a file name");
+            continue;
+        };
+        let data = field
+            .bytes()
+            .await
+            .map_err(|e| AppError::InvalidArchive(e.body_text()))?;
+        collector.push(&name, data)?;
+    }
+    Ok(collector.files)
+}
+
+/// Collects batch files while enforcing the batch limits and path rules.
+struct Collector<'a> {
+    /// The limits to enforce.
+    limits: &'a Limits,
+    /// The files collected so far.
+    files: Vec<BatchFile>,
+    /// The output paths of the files collected so far, to reject inputs that
+    /// would overwrite each other's PNG (e.g. `a.svg` and `a.SVG`).
+    output_paths: HashSet<String>,
+    /// The total size of the files collected so far.
+    total_bytes: u64,
+}
+
+impl<'a> Collector<'a> {
+    /// Creates an empty collector.
+    fn new(limits: &'a Limits) -> Self {
+        Collector { limits, files: Vec::new(), output_paths: HashSet::new(), total_bytes: 0 }
+    }
+
+    /// Bytes that may still be added before [`Limits::max_batch_bytes`] is exceeded.
+    fn remaining_bytes(&self) -> u64 {
+        self.limits.max_batch_bytes.saturating_sub(self.total_bytes)
+    }
+
+    /// Adds a file after normalizing its path and checking the limits.
+    fn push(&mut self, name: &str, data: Bytes) -> Result<(), AppError> {
+        let path = relative_path(name)
+            .ok_or_else(|| AppError::InvalidArchive(format!("Unsafe or empty path '{}'", name)))?;
+        if self.files.len() >= self.limits.max_batch_files {
+            return Err(RenderError::LimitExceeded(format!(
+                "Batch contains more than the maximum of {} files",
+                self.limits.max_batch_files
+            ))
+            .into());
+        }
+        self.total_bytes += data.len() as u64;
+        if self.total_bytes > self.limits.max_batch_bytes {
+            return Err(RenderError::LimitExceeded(format!(
+                "Batch exceeds the maximum uncompressed size of {} bytes",
+                self.limits.max_batch_bytes
+            ))
+            .into());
+        }
+        let output = output_path(&path);
+        if !self.output_paths.insert(output.clone()) {
+            return Err(AppError::InvalidArchive(format!(
+                "Duplicate path '{}': another file is also converted to '{}'",
+                path, output
+            )));
+        }
+        self.files.push(BatchFile { path, data });
+        Ok(())
+    }
+}
+
+/// Normalizes an archive path to a relative `/`-separated path.
+///
+/// Returns `None` for absolute paths, paths containing `..` or a drive prefix,
+/// and paths without any file name.
+fn relative_path(name: &str) -> Option<String> {
+    let name = name.replace('\\', "/");
+    if name.starts_with('/') || name.ends_with('/') {
+        return None;
+    }
+    let mut parts = Vec::new();
+    for part in name.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => return None,
+            part if part.contains(':') => return None,
+            part => parts.push(part),
+        }
+    }
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+/// Whether a path ends in `.svg` (case-insensitive).
+fn has_svg_extension(path: &str) -> bool {
+    path.len() >= SVG_EXTENSION.len()
+        && path.is_char_boundary(path.len() - SVG_EXTENSION.len())
+        && path[path.len() - SVG_EXTENSION.len()..].eq_ignore_ascii_case(SVG_EXTENSION)
+}
+
+/// The path of the PNG produced for an input path: `.svg` is replaced by `.png`.
+fn output_path(path: &str) -> String {
+    let stem = if has_svg_extension(path) { &path[..path.len() - SVG_EXTENSION.len()] } else { path };
+    format!("{}.png", stem)
+}
+
+/// The manifest written next to the converted images.
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    /// Number of SVGs converted successfully.
+    pub succeeded: usize,
+    /// Number of SVGs that failed.
+    pub failed: usize,
+    /// One entry per input SVG, in input order.
+    pub files: Vec<ManifestEntry>,
+}
+
+/// The outcome for a single SVG.
+#[derive(Debug, Serialize)]
+pub struct ManifestEntry {
+    /// Relative path of the input SVG.
+    pub path: String,
+    /// `ok` or `error`.
+    pub status: &'static str,
+    /// Relative path of the PNG in the archive, if converted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// Width of the PNG in pixels, if converted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    /// Height of the PNG in pixels, if converted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// Why the conversion failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ManifestError>,
+}
+
+/// A failed conversion, reported like an error response.
+#[derive(Debug, Serialize)]
+pub struct ManifestError {
+    /// The stable, machine-readable error code.
+    pub code: &'static str,
+    /// The human-readable error message.
+    pub detail: String,
+}
+
+/// Builds the response archive from the conversion results.
+///
+/// # Arguments
+///
+/// * `results` - The input path and conversion result of every SVG, in input order.
+///
+/// # Returns
+///
+/// * `Ok(Vec<u8>)` - The ZIP archive with the PNGs and [`MANIFEST_NAME`].
+/// * `Err(AppError)` - `500 Internal Server Error` if writing the archive fails.
+pub fn write_zip(results: Vec<(String, Result<EncodedImage, AppError>)>) -> Result<Vec<u8>, AppError> {
+    let zip_error = |e: &dyn std::fmt::Display| AppError::Internal(format!("Failed to write ZIP archive: {}", e));
+    // PNGs are already compressed; only the manifest benefits from deflate.
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let mut manifest = Manifest { succeeded: 0, failed: 0, files: Vec::with_capacity(results.len()) };
+    for (path, result) in results {
+        let entry = match result {
+            Ok(image) => {
+                let output = output_path(&path);
+                writer.start_file(output.as_str(), stored).map_err(|e| zip_error(&e))?;
+                writer.write_all(&image.data).map_err(|e| zip_error(&e))?;
+                manifest.succeeded += 1;
+                ManifestEntry {
+                    path,
+                    status: "ok",
+                    output: Some(output),
+                    width: Some(image.width),
+                    height: Some(image.height),
+                    error: None,
+                }
+            }
+            Err(e) => {
+                manifest.failed += 1;
+                ManifestEntry {
+                    path,
+                    status: "error",
+                    output: None,
+                    width: None,
+                    height: None,
+                    error: Some(ManifestError { code: e.code(), detail: e.to_string() }),
+                }
+            }
+        };
+        manifest.files.push(entry);
+    }
+
+    let manifest = serde_json::to_vec_pretty(&manifest).map_err(|e| zip_error(&e))?;
+    writer.start_file(MANIFEST_NAME, deflated).map_err(|e| zip_error(&e))?;
+    writer.write_all(&manifest).map_err(|e| zip_error(&e))?;
+    Ok(writer.finish().map_err(|e| zip_error(&e))?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zip_of(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_relative_paths() {
+        assert_eq!(relative_path("icons/./a.svg").as_deref(), Some("icons/a.svg"));
+        assert_eq!(relative_path("icons\\b.svg").as_deref(), Some("icons/b.svg"));
+        for unsafe_path in ["../a.svg", "icons/../../a.svg", "/etc/a.svg", "C:/a.svg", "icons/"] {
+            assert_eq!(relative_path(unsafe_path), None, "{}", unsafe_path);
+        }
+        assert_eq!(output_path("icons/A.SVG"), "icons/A.png");
+        assert_eq!(output_path("logo"), "logo.png");
+    }
+
+    #[test]
+    fn test_read_zip_filters_and_limits() {
+        let archive = zip_of(&[
+            ("icons/a.svg", b"<svg/>"),
+            ("icons/readme.txt", b"text"),
+            ("__MACOSX/icons/._a.svg", b"fork"),
+            ("b.SVG", b"<svg/>"),
+        ]);
+        let files = read_zip(&archive, &Limits::default()).unwrap();
+        let paths: Vec<_> = files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["icons/a.svg", "b.SVG"]);
+
+        let too_many = Limits { max_batch_files: 1, ..Limits::default() };
+        assert_eq!(read_zip(&archive, &too_many).unwrap_err().code(), "limit_exceeded");
+        let too_big = Limits { max_batch_bytes: 10, ..Limits::default() };
+        assert_eq!(read_zip(&archive, &too_big).unwrap_err().code(), "limit_exceeded");
+
+        let traversal = zip_of(&[("../evil.svg", b"<svg/>")]);
+        assert_eq!(read_zip(&traversal, &Limits::default()).unwrap_err().code(), "invalid_archive");
+        assert_eq!(read_zip(b"not a zip", &Limits::default()).unwrap_err().code(), "invalid_archive");
+    }
+
+    #[test]
+    fn test_rejects_inputs_with_the_same_output_path() {
+        let archive = zip_of(&[("icons/a.svg", b"<svg/>"), ("icons/a.SVG", b"<svg/>")]);
+        let err = read_zip(&archive, &Limits::default()).unwrap_err();
+        assert_eq!(err.code(), "invalid_archive");
+        assert!(err.to_string().contains("'icons/a.png'"), "{}", err);
+
+        // Multipart file names need not end in `.svg`.
+        let limits = Limits::default();
+        let mut collector = Collector::new(&limits);
+        collector.push("logo", Bytes::from_static(b"<svg/>")).unwrap();
+        assert_eq!(collector.push("logo.svg", Bytes::from_static(b"<svg/>")).unwrap_err().code(), "invalid_archive");
+    }
+}
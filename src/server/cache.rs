@@ -0,0 +1,549 @@
+//! # Render Cache
+//!
+//! Dashboards request the same SVGs with the same options over and over, so
+//! `/svg-to-png` keeps recently rendered PNGs in a content-addressed cache. The
+//! key is a SHA-256 hash of the SVG bytes, every render option and the
+//! fingerprint of the loaded fonts (see [`key`]): identical requests share an
+//! entry no matter who sends them, and any difference in input, options or
+//! fonts misses it.
+//!
+//! The cache has two tiers, both evicting the least recently used entries once
+//! they exceed their size limit (see [`CacheConfig`]):
+//!
+//! - **Memory**: encoded PNGs held in process, shared with responses without copying.
+//! - **Disk** (optional): one `<key>.png` file per entry in `SVG2PNG_CACHE_DIR`,
+//!   surviving restarts. Disk hits are promoted to memory.
+//!
+//! The key doubles as a strong `ETag`, so clients holding a response can
+//! revalidate with `If-None-Match` and get `304 Not Modified` without a render
+//! or even a cache lookup.
+//!
+//! Since the font fingerprint is part of every key, entries rendered with other
+//! fonts are never served, whether the fonts changed through a reload or
+//! between restarts sharing a disk tier. Both tiers are also cleared on reload
+//! to free the space held by entries that can no longer be hit.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use axum::{
+    body::Bytes,
+    http::{header, HeaderMap},
+};
+use sha2::{Digest, Sha256};
+use svg2png::encode::OutputFormat;
+use svg2png::sizing::FitMode;
+use svg2png::RenderOptions;
+use tracing::{debug, warn};
+
+use super::config::CacheConfig;
+
+/// File extension of disk tier entries.
+const DISK_EXTENSION: &str = "png";
+/// File extension of disk tier entries that are still being written.
+const PARTIAL_EXTENSION: &str = "partial";
+/// Version of the key layout (see [`options_bytes`]). Bump it whenever the
+/// layout changes, so disk entries keyed the old way are never hit.
+const KEY_VERSION: u8 = 1;
+
+/// Identifies a rendered image by the SHA-256 of everything that determines it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CacheKey([u8; 32]);
+
+impl CacheKey {
+    /// The strong entity tag of the rendered image, including the quotes.
+    pub fn etag(&self) -> String {
+        format!("\"{}\"", self)
+    }
+
+    /// Parses the file stem of a disk tier entry.
+    fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() != 64 {
+            return None;
+        }
+        let mut key = [0; 32];
+        for (byte, pair) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+        }
+        Some(CacheKey(key))
+    }
+}
+
+impl fmt::Display for CacheKey {
+    /// Formats the key as 64 lowercase hex digits.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+/// Computes the cache key of rendering `svg` with `options` and the fonts
+/// with fingerprint `fonts` (see [`Renderer::font_fingerprint`](svg2png::Renderer::font_fingerprint)).
+///
+/// Compute it before rendering, with the fingerprint at that time. The crate
+/// version is hashed too, so disk entries written by an older renderer are
+/// never served.
+pub fn key(svg: &[u8], options: &RenderOptions, fonts: &[u8; 32]) -> CacheKey {
+    let mut hasher = Sha256::new();
+    for part in [env!("CARGO_PKG_VERSION").as_bytes(), fonts, &options_bytes(options)] {
+        // Length prefixes keep the boundaries between the parts unambiguous.
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    hasher.update(svg);
+    CacheKey(hasher.finalize().into())
+}
+
+/// Serializes the options that affect the rendered image, starting with [`KEY_VERSION`].
+///
+/// The options are destructured without `..`, so adding a field fails to
+/// compile until it is either serialized here or explicitly ignored. Fields
+/// are written in a fixed order with fixed-size numbers, length-prefixed
+/// strings and a presence byte for optional values.
+fn options_bytes(options: &RenderOptions) -> Vec<u8> {
+    let RenderOptions {
+        dpi,
+        width,
+        height,
+        fit,
+        align,
+        background,
+        flatten,
+        format,
+        quality,
+        lossless,
+        deadline: _,
+        element,
+        view_box,
+        trim,
+        padding,
+        style_sheet,
+        color,
+    } = options;
+
+    let mut out = vec![KEY_VERSION];
+    let optional = |out: &mut Vec<u8>, value: Option<&[u8]>| match value {
+        Some(bytes) => {
+            out.push(1);
+            out.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+            out.extend_from_slice(bytes);
+        }
+        None => out.push(0),
+    };
+    let rgba = |color: &svgtypes::Color| [color.red, color.green, color.blue, color.alpha];
+
+    out.extend_from_slice(&dpi.to_le_bytes());
+    optional(&mut out, width.map(u32::to_le_bytes).as_ref().map(|b| &b[..]));
+    optional(&mut out, height.map(u32::to_le_bytes).as_ref().map(|b| &b[..]));
+    out.push(match fit {
+        FitMode::Contain => 0,
+        FitMode::Cover => 1,
+        FitMode::Fill => 2,
+        FitMode::ScaleDown => 3,
+    });
+    out.extend_from_slice(&align.x.to_le_bytes());
+    out.extend_from_slice(&align.y.to_le_bytes());
+    optional(&mut out, background.as_ref().map(rgba).as_ref().map(|b| &b[..]));
+    out.push(u8::from(*flatten));
+    out.push(match format {
+        OutputFormat::Png => 0,
+        OutputFormat::Jpeg => 1,
+        OutputFormat::WebP => 2,
+        OutputFormat::Avif => 3,
+    });
+    optional(&mut out, quality.as_ref().map(std::slice::from_ref));
+    out.push(u8::from(*lossless));
+    optional(&mut out, element.as_deref().map(str::as_bytes));
+    let view_box = view_box.map(|rect| [rect.x(), rect.y(), rect.width(), rect.height()].map(f32::to_le_bytes).concat());
+    optional(&mut out, view_box.as_deref());
+    out.push(u8::from(*trim));
+    for side in [padding.top, padding.right, padding.bottom, padding.left] {
+        out.extend_from_slice(&side.to_le_bytes());
+    }
+    optional(&mut out, style_sheet.as_deref().map(str::as_bytes));
+    optional(&mut out, color.as_ref().map(rgba).as_ref().map(|b| &b[..]));
+    out
+}
+
+/// Whether an `If-None-Match` header matches `etag`, i.e. the client's copy is current.
+///
+/// Uses the weak comparison required for `If-None-Match`, so `W/"…"` matches too.
+/// `*` is ignored: it asks whether the target resource exists at all, which is
+/// meaningless for a conversion and would skip rendering (and validating) the
+/// request body.
+pub fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+/// Where a cached image was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tier {
+    /// The in-process tier.
+    Memory,
+    /// The on-disk tier.
+    Disk,
+}
+
+/// The current size of each tier, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Usage {
+    /// Total size of the images cached in memory.
+    pub memory_bytes: u64,
+    /// Total size of the images cached on disk.
+    pub disk_bytes: u64,
+}
+
+/// The two-tier render cache.
+pub struct RenderCache {
+    /// The in-process tier.
+    memory: Mutex<Lru<Bytes>>,
+    /// The on-disk tier, if configured.
+    disk: Option<Arc<Disk>>,
+    /// The `Cache-Control` header value of cacheable responses.
+    cache_control: String,
+}
+
+impl RenderCache {
+    /// Opens the cache, indexing the entries already in the disk tier.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(RenderCache)` - The cache. Existing disk entries are kept up to the size limit.
+    /// * `Err(io::Error)` - If the disk tier directory cannot be created or read.
+    pub fn open(config: &CacheConfig) -> io::Result<Self> {
+        let disk = match &config.dir {
+            Some(dir) => Some(Arc::new(Disk::open(dir, config.max_disk_bytes)?)),
+            None => None,
+        };
+        Ok(Self::with_disk(config, disk))
+    }
+
+    /// Creates the cache around an opened disk tier.
+    fn with_disk(config: &CacheConfig, disk: Option<Arc<Disk>>) -> Self {
+        RenderCache {
+            memory: Mutex::new(Lru::new(config.max_memory_bytes)),
+            disk,
+            cache_control: format!("public, max-age={}", config.max_age.as_secs()),
+        }
+    }
+
+    /// The `Cache-Control` header value of cacheable responses.
+    pub fn cache_control(&self) -> &str {
+        &self.cache_control
+    }
+
+    /// Looks up an image, trying memory first, then disk.
+    pub async fn get(&self, key: &CacheKey) -> Option<(Bytes, Tier)> {
+        if let Some(data) = self.memory().get(key) {
+            return Some((data.clone(), Tier::Memory));
+        }
+        let disk = self.disk.clone()?;
+        let key = *key;
+        let data = tokio::task::spawn_blocking(move || disk.read(&key)).await.ok()??;
+        self.memory().insert(key, data.clone(), data.len() as u64);
+        Some((data, Tier::Disk))
+    }
+
+    /// Stores an image in both tiers.
+    ///
+    /// Disk write failures are logged and otherwise ignored: the cache is only
+    /// an optimization.
+    pub async fn insert(&self, key: CacheKey, data: Bytes) {
+        self.memory().insert(key, data.clone(), data.len() as u64);
+        if let Some(disk) = self.disk.clone() {
+            let _ = tokio::task::spawn_blocking(move || disk.write(key, &data)).await;
+        }
+    }
+
+    /// Removes all entries from both tiers. Blocks on disk I/O.
+    pub fn clear(&self) {
+        self.memory().clear();
+        if let Some(disk) = &self.disk {
+            disk.clear();
+        }
+    }
+
+    /// Returns the current size of each tier.
+    pub fn usage(&self) -> Usage {
+        Usage {
+            memory_bytes: self.memory().bytes,
+            disk_bytes: self.disk.as_ref().map_or(0, |disk| disk.index().bytes),
+        }
+    }
+
+    /// Locks the memory tier, ignoring poisoning: a panic cannot leave it inconsistent.
+    fn memory(&self) -> MutexGuard<'_, Lru<Bytes>> {
+        self.memory.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for RenderCache {
+    /// A memory-only cache with the default size.
+    fn default() -> Self {
+        Self::with_disk(&CacheConfig::default(), None)
+    }
+}
+
+/// The on-disk tier.
+struct Disk {
+    /// The directory holding one file per entry.
+    dir: PathBuf,
+    /// Which entries exist, in least recently used order. Values are unused.
+    index: Mutex<Lru<()>>,
+}
+
+impl Disk {
+    /// Creates the directory if needed and indexes its entries, oldest first.
+    fn open(dir: &Path, max_bytes: u64) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let extension = path.extension().and_then(|e| e.to_str());
+            if extension == Some(PARTIAL_EXTENSION) {
+                // Left behind by a crash during a write.
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            let key = path.file_stem().and_then(|s| s.to_str()).and_then(CacheKey::from_hex);
+            let (Some(key), Some(DISK_EXTENSION)) = (key, extension) else {
+                continue;
+            };
+            let metadata = fs::metadata(&path)?;
+            entries.push((metadata.modified().ok(), key, metadata.len()));
+        }
+        entries.sort_by_key(|(modified, _, _)| *modified);
+
+        let disk = Disk { dir: dir.to_path_buf(), index: Mutex::new(Lru::new(max_bytes)) };
+        for (_, key, size) in entries {
+            let evicted = disk.index().insert(key, (), size);
+            disk.remove_files(&evicted);
+        }
+        debug!(dir = %dir.display(), bytes = disk.index().bytes, "Opened render cache directory");
+        Ok(disk)
+    }
+
+    /// Reads an entry, dropping it from the index if its file is gone.
+    fn read(&self, key: &CacheKey) -> Option<Bytes> {
+        self.index().get(key)?;
+        match fs::read(self.path(key)) {
+            Ok(data) => Some(Bytes::from(data)),
+            Err(e) => {
+                warn!(%key, error = %e, "Failed to read cached image");
+                self.index().remove(key);
+                None
+            }
+        }
+    }
+
+    /// Writes an entry atomically and evicts old ones if over the limit.
+    fn write(&self, key: CacheKey, data: &[u8]) {
+        let path = self.path(&key);
+        let partial = path.with_extension(PARTIAL_EXTENSION);
+        if let Err(e) = fs::write(&partial, data).and_then(|()| fs::rename(&partial, &path)) {
+            warn!(%key, error = %e, "Failed to write cached image");
+            let _ = fs::remove_file(&partial);
+            return;
+        }
+        let evicted = self.index().insert(key, (), data.len() as u64);
+        self.remove_files(&evicted);
+    }
+
+    /// Deletes all entries.
+    fn clear(&self) {
+        let keys: Vec<CacheKey> = {
+            let mut index = self.index();
+            let keys = index.entries.keys().copied().collect();
+            index.clear();
+            keys
+        };
+        self.remove_files(&keys);
+    }
+
+    /// Deletes the files of evicted entries.
+    fn remove_files(&self, keys: &[CacheKey]) {
+        for key in keys {
+            if let Err(e) = fs::remove_file(self.path(key)) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warn!(%key, error = %e, "Failed to remove cached image");
+                }
+            }
+        }
+    }
+
+    /// The file of an entry.
+    fn path(&self, key: &CacheKey) -> PathBuf {
+        self.dir.join(format!("{}.{}", key, DISK_EXTENSION))
+    }
+
+    /// Locks the index, ignoring poisoning: a panic cannot leave it inconsistent.
+    fn index(&self) -> MutexGuard<'_, Lru<()>> {
+        self.index.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A least recently used map bounded by the total size of its values.
+struct Lru<V> {
+    /// The entries with their size and last use.
+    entries: HashMap<CacheKey, LruEntry<V>>,
+    /// Keys by last use, least recent first.
+    recency: BTreeMap<u64, CacheKey>,
+    /// Incremented on every use, so uses are totally ordered.
+    clock: u64,
+    /// Total size of all entries.
+    bytes: u64,
+    /// Maximum total size of all entries.
+    max_bytes: u64,
+}
+
+/// An entry of an [`Lru`].
+struct LruEntry<V> {
+    value: V,
+    size: u64,
+    last_used: u64,
+}
+
+impl<V> Lru<V> {
+    /// Creates an empty map holding at most `max_bytes`.
+    fn new(max_bytes: u64) -> Self {
+        Lru { entries: HashMap::new(), recency: BTreeMap::new(), clock: 0, bytes: 0, max_bytes }
+    }
+
+    /// Returns an entry and marks it as most recently used.
+    fn get(&mut self, key: &CacheKey) -> Option<&V> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(key)?;
+        self.recency.remove(&entry.last_used);
+        self.recency.insert(self.clock, *key);
+        entry.last_used = self.clock;
+        Some(&entry.value)
+    }
+
+    /// Inserts or replaces an entry, evicting the least recently used entries
+    /// until the total fits.
+    ///
+    /// # Returns
+    ///
+    /// The keys of the evicted entries. An entry larger than the whole map is
+    /// not stored and returned itself.
+    fn insert(&mut self, key: CacheKey, value: V, size: u64) -> Vec<CacheKey> {
+        self.remove(&key);
+        if size > self.max_bytes {
+            return vec![key];
+        }
+        let mut evicted = Vec::new();
+        while self.bytes + size > self.max_bytes {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.bytes -= entry.size;
+            }
+            evicted.push(oldest);
+        }
+        self.clock += 1;
+        self.recency.insert(self.clock, key);
+        self.entries.insert(key, LruEntry { value, size, last_used: self.clock });
+        self.bytes += size;
+        evicted
+    }
+
+    /// Removes an entry if present.
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.last_used);
+            self.bytes -= entry.size;
+        }
+    }
+
+    /// Removes all entries.
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+        self.bytes = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use svg2png::deadline::Deadline;
+
+    #[test]
+    fn test_key_covers_svg_and_options_but_not_deadline() {
+        let svg = b"<svg/>";
+        let options = RenderOptions::default();
+        let fonts = [0; 32];
+        let base = key(svg, &options, &fonts);
+        let deadline = Some(Deadline::after(Duration::from_secs(1)));
+        assert_eq!(base, key(svg, &RenderOptions { deadline, ..options.clone() }, &fonts));
+        assert_ne!(base, key(b"<svg />", &options, &fonts));
+        assert_ne!(base, key(svg, &options, &[1; 32]));
+        assert_ne!(base, key(svg, &RenderOptions { dpi: 192.0, ..options.clone() }, &fonts));
+        assert_ne!(base, key(svg, &RenderOptions { style_sheet: Some("*{}".to_string()), ..options.clone() }, &fonts));
+        // Unset and empty values are told apart, and values do not shift between fields.
+        assert_ne!(base, key(svg, &RenderOptions { element: Some(String::new()), ..options.clone() }, &fonts));
+        assert_ne!(
+            key(svg, &RenderOptions { element: Some("a".to_string()), ..options.clone() }, &fonts),
+            key(svg, &RenderOptions { style_sheet: Some("a".to_string()), ..options }, &fonts)
+        );
+
+        assert_eq!(CacheKey::from_hex(&base.to_string()), Some(base));
+        assert_eq!(base.etag().len(), 66);
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        let etag = "\"abc\"";
+        let headers = |value: &str| HeaderMap::from_iter([(header::IF_NONE_MATCH, value.parse().unwrap())]);
+        assert!(if_none_match(&headers("\"abc\""), etag));
+        assert!(if_none_match(&headers("\"x\", W/\"abc\""), etag));
+        assert!(!if_none_match(&headers("*"), etag));
+        assert!(!if_none_match(&headers("\"abcd\""), etag));
+        assert!(!if_none_match(&HeaderMap::new(), etag));
+    }
+
+    #[test]
+    fn test_lru_evicts_least_recently_used() {
+        let keys: Vec<CacheKey> = (0..4u8).map(|i| CacheKey([i; 32])).collect();
+        let mut lru = Lru::new(10);
+        assert!(lru.insert(keys[0], 'a', 4).is_empty());
+        assert!(lru.insert(keys[1], 'b', 4).is_empty());
+        // Using the first entry makes the second one the least recently used.
+        assert_eq!(lru.get(&keys[0]), Some(&'a'));
+        assert_eq!(lru.insert(keys[2], 'c', 4), vec![keys[1]]);
+        assert_eq!(lru.bytes, 8);
+        // Too large to cache at all.
+        assert_eq!(lru.insert(keys[3], 'd', 11), vec![keys[3]]);
+        assert_eq!(lru.get(&keys[1]), None);
+        assert_eq!(lru.get(&keys[3]), None);
+    }
+
+    #[tokio::test]
+    async fn test_disk_tier_survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = CacheConfig { max_memory_bytes: 0, dir: Some(dir.path().to_path_buf()), ..CacheConfig::default() };
+        let key = key(b"<svg/>", &RenderOptions::default(), &[0; 32]);
+
+        let cache = RenderCache::open(&config).unwrap();
+        assert_eq!(cache.get(&key).await, None);
+        cache.insert(key, Bytes::from_static(b"png")).await;
+        assert_eq!(cache.usage(), Usage { memory_bytes: 0, disk_bytes: 3 });
+
+        let cache = RenderCache::open(&config).unwrap();
+        assert_eq!(cache.get(&key).await, Some((Bytes::from_static(b"png"), Tier::Disk)));
+        cache.clear();
+        assert_eq!(cache.get(&key).await, None);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,206 @@
+//! # Service Configuration
+//!
+//! Settings of the HTTP service read from environment variables at startup.
+//! Font and limit settings shared with the library live in [`svg2png::config`].
+//!
+//! ## Render Pool
+//!
+//! - `SVG2PNG_RENDER_CONCURRENCY`: Maximum number of renders running at once.
+//!   Defaults to the number of available CPUs.
+//! - `SVG2PNG_RENDER_QUEUE_DEPTH`: Maximum number of renders waiting for a
+//!   worker. Requests beyond that are rejected with `503`. Defaults to 64.
+//! - `SVG2PNG_RENDER_TIMEOUT_MS`: Maximum time a render may take, including
+//!   waiting for a worker, before the request fails with `504`. Defaults to 30000.
+//! - `SVG2PNG_BATCH_TIMEOUT_MS`: Maximum time a batch request may spend
+//!   converting its files. Files not converted by then are reported as timed
+//!   out. Defaults to 300000.
+//!
+//! ## Render Cache
+//!
+//! - `SVG2PNG_CACHE_MAX_BYTES`: Maximum total size of the images cached in
+//!   memory. `0` disables the memory tier. Defaults to 64 MiB.
+//! - `SVG2PNG_CACHE_DIR`: Directory of the on-disk tier. Unset by default,
+//!   which disables it.
+//! - `SVG2PNG_CACHE_DISK_MAX_BYTES`: Maximum total size of the images cached on
+//!   disk. Defaults to 1 GiB.
+//! - `SVG2PNG_CACHE_MAX_AGE_SECS`: `max-age` of the `Cache-Control` header on
+//!   cacheable responses. Defaults to 86400 (one day).
+//!
+//! ## Admin
+//!
+//! - `SVG2PNG_ADMIN_TOKEN`: Bearer token required by the `/admin/*` endpoints.
+//!   Unset by default, which disables them.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+
+/// Environment variable name for the maximum number of concurrent renders.
+const RENDER_CONCURRENCY_ENV_VAR: &str = "SVG2PNG_RENDER_CONCURRENCY";
+/// Environment variable name for the maximum number of queued renders.
+const RENDER_QUEUE_DEPTH_ENV_VAR: &str = "SVG2PNG_RENDER_QUEUE_DEPTH";
+/// Environment variable name for the render timeout in milliseconds.
+const RENDER_TIMEOUT_MS_ENV_VAR: &str = "SVG2PNG_RENDER_TIMEOUT_MS";
+/// Environment variable name for the batch timeout in milliseconds.
+const BATCH_TIMEOUT_MS_ENV_VAR: &str = "SVG2PNG_BATCH_TIMEOUT_MS";
+/// Environment variable name for the memory tier size of the render cache.
+const CACHE_MAX_BYTES_ENV_VAR: &str = "SVG2PNG_CACHE_MAX_BYTES";
+/// Environment variable name for the directory of the render cache's disk tier.
+const CACHE_DIR_ENV_VAR: &str = "SVG2PNG_CACHE_DIR";
+/// Environment variable name for the disk tier size of the render cache.
+const CACHE_DISK_MAX_BYTES_ENV_VAR: &str = "SVG2PNG_CACHE_DISK_MAX_BYTES";
+/// Environment variable name for the `max-age` of cacheable responses.
+const CACHE_MAX_AGE_SECS_ENV_VAR: &str = "SVG2PNG_CACHE_MAX_AGE_SECS";
+/// Environment variable name for the token of the admin endpoints.
+const ADMIN_TOKEN_ENV_VAR: &str = "SVG2PNG_ADMIN_TOKEN";
+
+/// Sizing of the blocking pool that renders and encodes images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// Maximum number of renders running at once.
+    pub concurrency: usize,
+    /// Maximum number of renders waiting for a free worker.
+    pub queue_depth: usize,
+    /// Maximum time a render may take. Requests may only lower it.
+    pub timeout: Duration,
+    /// Maximum time all renders of a batch request may take together.
+    pub batch_timeout: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            concurrency: std::thread::available_parallelism().map_or(4, |n| n.get()),
+            queue_depth: 64,
+            timeout: Duration::from_secs(30),
+            batch_timeout: Duration::from_secs(300),
+        }
+    }
+}
+
+impl PoolConfig {
+    /// Reads the pool sizing from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(PoolConfig)` - The configuration, with defaults for unset variables.
+    /// * `Err(anyhow::Error)` - If the concurrency or a timeout is not positive
+    ///   or the queue depth is not a number.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let defaults = PoolConfig::default();
+        Ok(PoolConfig {
+            concurrency: parse_limit(RENDER_CONCURRENCY_ENV_VAR, defaults.concurrency)?,
+            queue_depth: match env_non_empty(RENDER_QUEUE_DEPTH_ENV_VAR) {
+                Some(value) => value
+                    .parse()
+                    .with_context(|| format!("Invalid {} value: {}", RENDER_QUEUE_DEPTH_ENV_VAR, value))?,
+                None => defaults.queue_depth,
+            },
+            timeout: Duration::from_millis(parse_limit(
+                RENDER_TIMEOUT_MS_ENV_VAR,
+                defaults.timeout.as_millis() as u64,
+            )?),
+            batch_timeout: Duration::from_millis(parse_limit(
+                BATCH_TIMEOUT_MS_ENV_VAR,
+                defaults.batch_timeout.as_millis() as u64,
+            )?),
+        })
+    }
+}
+
+/// Sizing of the render cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Maximum total size of the images cached in memory, in bytes. `0` disables the memory tier.
+    pub max_memory_bytes: u64,
+    /// Directory of the on-disk tier, or `None` to keep the cache in memory only.
+    pub dir: Option<PathBuf>,
+    /// Maximum total size of the images cached on disk, in bytes.
+    pub max_disk_bytes: u64,
+    /// How long clients may reuse a response without revalidating it.
+    pub max_age: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            max_memory_bytes: 64 * 1024 * 1024,
+            dir: None,
+            max_disk_bytes: 1024 * 1024 * 1024,
+            max_age: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Reads the cache configuration from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(CacheConfig)` - The configuration, with defaults for unset variables.
+    /// * `Err(anyhow::Error)` - If the disk size is not positive or another size is not a number.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let defaults = CacheConfig::default();
+        Ok(CacheConfig {
+            max_memory_bytes: parse_number(CACHE_MAX_BYTES_ENV_VAR, defaults.max_memory_bytes)?,
+            dir: env_non_empty(CACHE_DIR_ENV_VAR).map(PathBuf::from),
+            max_disk_bytes: parse_limit(CACHE_DISK_MAX_BYTES_ENV_VAR, defaults.max_disk_bytes)?,
+            max_age: Duration::from_secs(parse_number(CACHE_MAX_AGE_SECS_ENV_VAR, defaults.max_age.as_secs())?),
+        })
+    }
+}
+
+/// Access to the admin endpoints.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct AdminConfig {
+    /// Bearer token the admin endpoints require, or `None` to disable them.
+    pub token: Option<String>,
+}
+
+impl AdminConfig {
+    /// Reads the admin token from the environment.
+    pub fn from_env() -> Self {
+        AdminConfig { token: env_non_empty(ADMIN_TOKEN_ENV_VAR) }
+    }
+}
+
+impl fmt::Debug for AdminConfig {
+    /// Reports whether a token is set without revealing it.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let token = self.token.as_ref().map(|_| "<redacted>");
+        f.debug_struct("AdminConfig").field("token", &token).finish()
+    }
+}
+
+/// Reads an environment variable, treating unset and empty values alike.
+fn env_non_empty(name: &str) -> Option<String> {
+    std::env::var(name).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+/// Parses a non-negative number from an environment variable, or returns `default` if unset.
+fn parse_number<T: FromStr>(name: &str, default: T) -> anyhow::Result<T> {
+    let Some(value) = env_non_empty(name) else {
+        return Ok(default);
+    };
+    match value.parse::<T>() {
+        Ok(v) => Ok(v),
+        Err(_) => bail!("Invalid {} value: {} (expected a non-negative number)", name, value),
+    }
+}
+
+/// Parses a positive numeric limit from an environment variable, or returns `default` if unset.
+fn parse_limit<T>(name: &str, default: T) -> anyhow::Result<T>
+where
+    T: FromStr + PartialOrd + Default,
+{
+    let Some(value) = env_non_empty(name) else {
+        return Ok(default);
+    };
+    match value.parse::<T>() {
+        Ok(v) if v > T::default() => Ok(v),
+        _ => bail!("Invalid {} value: {} (expected a positive number)", name, value),
+    }
+}
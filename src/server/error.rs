@@ -0,0 +1,206 @@
+//! # Error Responses
+//!
+//! All handlers fail with an [`AppError`], which is rendered as an RFC 9457
+//! `application/problem+json` body carrying a stable, machine-readable `code`
+//! alongside the human-readable `detail` and the request ID:
+//!
+//! ```json
+//! {
+//!   "type": "urn:svg2png:error:svg_parse_error",
+//!   "title": "Invalid SVG",
+//!   "status": 400,
+//!   "code": "svg_parse_error",
+//!   "detail": "SVG data parsing failed cause unknown token at 1:1",
+//!   "request_id": "6f1c0c3e-4f7a-4a51-9d0e-6a4f5f0e2b9d"
+//! }
+//! ```
+//!
+//! Clients should match on `code`; `detail` wording may change.
+
+use std::fmt;
+
+use axum::{
+    extract::rejection::BytesRejection,
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use svg2png::RenderError;
+
+use super::request_id;
+
+/// HTTP Content-Type value for problem details.
+const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// An error returned by a handler.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppError {
+    /// Rendering, keying or encoding failed (see [`RenderError::code`]).
+    Render(RenderError),
+    /// The request body was empty.
+    EmptyBody,
+    /// A query parameter had an invalid value.
+    InvalidParameter(String),
+    /// A batch request body is not a valid ZIP or multipart archive of SVGs.
+    InvalidArchive(String),
+    /// A `multipart/form-data` body for a single SVG is malformed.
+    InvalidForm(String),
+    /// The request body exceeds the configured size limit.
+    PayloadTooLarge {
+        /// The configured limit in bytes.
+        limit: usize,
+    },
+    /// All render workers are busy and the queue is full.
+    Overloaded {
+        /// Seconds the client should wait before retrying.
+        retry_after_secs: u64,
+    },
+    /// None of the supported output formats satisfies the `Accept` header.
+    NotAcceptable(String),
+    /// An admin endpoint was called without a valid token, or is disabled.
+    Unauthorized(String),
+    /// Any other internal failure.
+    Internal(String),
+}
+
+impl AppError {
+    /// The stable, machine-readable error code.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Render(e) => e.code(),
+            AppError::EmptyBody => "empty_body",
+            AppError::InvalidParameter(_) => "invalid_parameter",
+            AppError::InvalidArchive(_) => "invalid_archive",
+            AppError::InvalidForm(_) => "invalid_form",
+            AppError::PayloadTooLarge { .. } => "payload_too_large",
+            AppError::Overloaded { .. } => "overloaded",
+            AppError::NotAcceptable(_) => "not_acceptable",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// The HTTP status code for this error.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::Render(e) => match e {
+                RenderError::EmptyInput
+                | RenderError::InvalidOption(_)
+                | RenderError::SvgParse(_)
+                | RenderError::PngDecode(_)
+                | RenderError::ZeroSize
+                | RenderError::ElementNotFound(_) => StatusCode::BAD_REQUEST,
+                RenderError::LimitExceeded(_) => StatusCode::UNPROCESSABLE_ENTITY,
+                RenderError::Timeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+                RenderError::PixmapAlloc { .. } | RenderError::Panic | RenderError::Encoder(_) => {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }
+            },
+            AppError::EmptyBody
+            | AppError::InvalidParameter(_)
+            | AppError::InvalidArchive(_)
+            | AppError::InvalidForm(_) => StatusCode::BAD_REQUEST,
+            AppError::PayloadTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::Overloaded { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// A short, human-readable summary of the error kind.
+    fn title(&self) -> &'static str {
+        match self {
+            AppError::Render(e) => e.title(),
+            AppError::EmptyBody => "Empty request body",
+            AppError::InvalidParameter(_) => "Invalid query parameter",
+            AppError::InvalidArchive(_) => "Invalid archive",
+            AppError::InvalidForm(_) => "Invalid form",
+            AppError::PayloadTooLarge { .. } => "Payload too large",
+            AppError::Overloaded { .. } => "Service overloaded",
+            AppError::NotAcceptable(_) => "Not acceptable",
+            AppError::Unauthorized(_) => "Unauthorized",
+            AppError::Internal(_) => "Internal error",
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Render(e) => write!(f, "{}", e),
+            AppError::EmptyBody => write!(f, "Request body cannot be empty"),
+            AppError::InvalidParameter(msg)
+            | AppError::InvalidArchive(msg)
+            | AppError::InvalidForm(msg)
+            | AppError::NotAcceptable(msg)
+            | AppError::Unauthorized(msg)
+            | AppError::Internal(msg) => {
+                write!(f, "{}", msg)
+            }
+            AppError::Overloaded { .. } => write!(f, "Too many concurrent requests, retry later"),
+            AppError::PayloadTooLarge { limit } => {
+                write!(f, "Request body exceeds the maximum of {} bytes", limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<RenderError> for AppError {
+    fn from(e: RenderError) -> Self {
+        AppError::Render(e)
+    }
+}
+
+impl AppError {
+    /// Maps a request body rejection, reporting `limit` if the body was too large.
+    pub fn from_body_rejection(rejection: BytesRejection, limit: usize) -> Self {
+        if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            AppError::PayloadTooLarge { limit }
+        } else {
+            AppError::InvalidParameter(rejection.body_text())
+        }
+    }
+}
+
+/// The JSON body of a problem details response.
+#[derive(Debug, Serialize)]
+struct ProblemDetails<'a> {
+    #[serde(rename = "type")]
+    kind: String,
+    title: &'a str,
+    status: u16,
+    code: &'a str,
+    detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let problem = ProblemDetails {
+            kind: format!("urn:svg2png:error:{}", self.code()),
+            title: self.title(),
+            status: status.as_u16(),
+            code: self.code(),
+            detail: self.to_string(),
+            request_id: request_id::current(),
+        };
+        // Serializing a struct of strings and integers cannot fail.
+        let body = serde_json::to_vec(&problem).unwrap_or_default();
+        let mut response = (status, [(header::CONTENT_TYPE, PROBLEM_CONTENT_TYPE)], body).into_response();
+        match self {
+            AppError::Overloaded { retry_after_secs } => {
+                response.headers_mut().insert(header::RETRY_AFTER, retry_after_secs.into());
+            }
+            AppError::Unauthorized(_) => {
+                response.headers_mut().insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+            _ => {}
+        }
+        response
+    }
+}
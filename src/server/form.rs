@@ -0,0 +1,84 @@
+//! # Form Uploads
+//!
+//! Besides a raw SVG body, the single-image endpoints accept a
+//! `multipart/form-data` body so a stylesheet can be uploaded next to the SVG:
+//!
+//! - [`SVG_FIELD`] (required): the SVG.
+//! - [`CSS_FIELD`] (optional): CSS injected while parsing, appended to any `css`
+//!   query parameter.
+//!
+//! Other fields are ignored.
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{FromRequest, Multipart, Request},
+    http::{header, HeaderValue},
+};
+use tracing::debug;
+
+use super::error::AppError;
+
+/// Name of the form field holding the SVG.
+pub const SVG_FIELD: &str = "svg";
+/// Name of the form field holding the stylesheet.
+pub const CSS_FIELD: &str = "css";
+
+/// An SVG and optional stylesheet taken from a form upload.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SvgForm {
+    /// The raw SVG data.
+    pub svg: Bytes,
+    /// The uploaded stylesheet, if any.
+    pub style_sheet: Option<String>,
+}
+
+/// Whether a `Content-Type` is `multipart/form-data`.
+pub fn is_multipart(content_type: &HeaderValue) -> bool {
+    content_type
+        .to_str()
+        .is_ok_and(|v| v.to_ascii_lowercase().starts_with("multipart/form-data"))
+}
+
+/// Starts reading a buffered `multipart/form-data` body.
+///
+/// # Returns
+///
+/// * `Ok(Multipart)` - The field reader.
+/// * `Err(String)` - Why the body is not valid multipart, for the caller's error.
+pub async fn multipart(content_type: &HeaderValue, body: Bytes) -> Result<Multipart, String> {
+    let request = Request::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Body::from(body))
+        .map_err(|e| format!("Failed to rebuild multipart request: {}", e))?;
+    Multipart::from_request(request, &()).await.map_err(|e| e.body_text())
+}
+
+/// Reads the SVG and stylesheet fields of a form upload.
+///
+/// # Returns
+///
+/// * `Ok(SvgForm)` - The SVG and stylesheet.
+/// * `Err(AppError)` - `400 Bad Request` if the body is not valid multipart, the
+///   stylesheet is not UTF-8 (`invalid_form`) or the SVG field is missing or
+///   empty (`empty_body`).
+pub async fn read_svg_form(content_type: &HeaderValue, body: Bytes) -> Result<SvgForm, AppError> {
+    let mut multipart = multipart(content_type, body).await.map_err(AppError::InvalidForm)?;
+    let mut svg = None;
+    let mut style_sheet = None;
+    while let Some(field) = multipart.next_field().await.map_err(|e| AppError::InvalidForm(e.body_text()))? {
+        let name = field.name().unwrap_or_default().to_string();
+        match name.as_str() {
+            SVG_FIELD => svg = Some(field.bytes().await.map_err(|e| AppError::InvalidForm(e.body_text()))?),
+            CSS_FIELD => {
+                let css = field.text().await.map_err(|e| AppError::InvalidForm(e.body_text()))?;
+                style_sheet = Some(css);
+            }
+            _ => debug!(field = name, "Skipping unknown form field"),
+        }
+    }
+
+    match svg {
+        Some(svg) if !svg.is_empty() => Ok(SvgForm { svg, style_sheet }),
+        _ => Err(AppError::EmptyBody),
+    }
+}
@@ -0,0 +1,351 @@
+//! # Metrics
+//!
+//! Prometheus metrics, served by `GET /metrics` in the text exposition format.
+//! The service exposes only a handful of series, so they are kept in a small
+//! in-process registry instead of pulling in a metrics client library.
+//!
+//! | Metric | Type | Labels | Meaning |
+//! | --- | --- | --- | --- |
+//! | `svg2png_http_requests_total` | counter | `route`, `status` | Requests handled, by route template and status code. |
+//! | `svg2png_render_phase_seconds` | histogram | `phase` | Time spent parsing, rendering and encoding each SVG. |
+//! | `svg2png_transparency_seconds` | histogram | | Time spent decoding, keying and encoding each `/png-to-transparent` image. |
+//! | `svg2png_icon_seconds` | histogram | | Time spent rendering and packaging each `/svg-to-icon` icon, all sizes included. |
+//! | `svg2png_output_pixels` | histogram | | Pixel count of each output image. |
+//! | `svg2png_output_bytes` | histogram | | Encoded size of each output image or icon. |
+//! | `svg2png_cache_lookups_total` | counter | `result` | Render cache lookups by `/svg-to-png`: `memory_hit`, `disk_hit`, `miss`, or `not_modified` for `If-None-Match` revalidations. |
+//! | `svg2png_cache_bytes` | gauge | `tier` | Size of the images in each render cache tier (`memory`, `disk`). |
+//! | `svg2png_font_faces` | gauge | | Font faces in the current font database. |
+//!
+//! Durations exclude time spent waiting for a render worker.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use svg2png::EncodedImage;
+
+use super::cache::{self, Tier};
+use super::state::AppState;
+
+/// HTTP Content-Type of the Prometheus text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+/// Route label for requests that did not match a route.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Upper bounds of the duration buckets, in seconds.
+const SECONDS_BUCKETS: [f64; 13] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+/// Upper bounds of the pixel count buckets (32x32 up to 8192x8192).
+const PIXELS_BUCKETS: [f64; 9] = [1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0, 16777216.0, 67108864.0];
+/// Upper bounds of the output size buckets, in bytes.
+const BYTES_BUCKETS: [f64; 8] = [1e3, 1e4, 5e4, 1e5, 5e5, 1e6, 1e7, 1e8];
+/// Names of the render phases, in the order of [`Registry::phases`].
+const PHASES: [&str; 3] = ["parse", "render", "encode"];
+
+/// The metrics registry shared by all requests.
+pub struct Metrics {
+    /// All series, behind one lock: updates are a few additions per request.
+    registry: Mutex<Registry>,
+}
+
+/// The recorded series.
+struct Registry {
+    /// Request counts keyed by route template and status code.
+    requests: BTreeMap<(String, u16), u64>,
+    /// Durations of the parse, render and encode phases.
+    phases: [Histogram; 3],
+    /// Durations of background removals.
+    transparency: Histogram,
+    /// Durations of icon renders.
+    icon: Histogram,
+    /// Pixel counts of output images.
+    output_pixels: Histogram,
+    /// Encoded sizes of output images.
+    output_bytes: Histogram,
+    /// Render cache lookups keyed by result.
+    cache_lookups: BTreeMap<&'static str, u64>,
+}
+
+/// The result of a render cache lookup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheResult {
+    /// The image was found in a tier.
+    Hit(Tier),
+    /// The image had to be rendered.
+    Miss,
+    /// The client's copy is current, so nothing was looked up.
+    NotModified,
+}
+
+impl CacheResult {
+    /// The `result` label value.
+    fn label(self) -> &'static str {
+        match self {
+            CacheResult::Hit(Tier::Memory) => "memory_hit",
+            CacheResult::Hit(Tier::Disk) => "disk_hit",
+            CacheResult::Miss => "miss",
+            CacheResult::NotModified => "not_modified",
+        }
+    }
+}
+
+/// A Prometheus histogram with fixed buckets.
+struct Histogram {
+    /// Upper bounds of the buckets, ascending. `+Inf` is implied.
+    bounds: &'static [f64],
+    /// Observations per bucket (not cumulative).
+    counts: Vec<u64>,
+    /// Sum of all observations.
+    sum: f64,
+    /// Number of observations, including those above the last bound.
+    count: u64,
+}
+
+impl Histogram {
+    /// Creates an empty histogram.
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram { bounds, counts: vec![0; bounds.len()], sum: 0.0, count: 0 }
+    }
+
+    /// Records one observation.
+    fn observe(&mut self, value: f64) {
+        if let Some(bucket) = self.bounds.iter().position(|bound| value <= *bound) {
+            self.counts[bucket] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// Writes the `_bucket`, `_sum` and `_count` samples.
+    ///
+    /// `labels` is prepended to the `le` label, e.g. `phase="parse",`.
+    fn write(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{{}le=\"{}\"}} {}", name, labels, bound, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{{}le=\"+Inf\"}} {}", name, labels, self.count);
+        let labels = labels.trim_end_matches(',');
+        let labels = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, self.count);
+    }
+}
+
+impl Metrics {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Metrics {
+            registry: Mutex::new(Registry {
+                requests: BTreeMap::new(),
+                phases: PHASES.map(|_| Histogram::new(&SECONDS_BUCKETS)),
+                transparency: Histogram::new(&SECONDS_BUCKETS),
+                icon: Histogram::new(&SECONDS_BUCKETS),
+                output_pixels: Histogram::new(&PIXELS_BUCKETS),
+                output_bytes: Histogram::new(&BYTES_BUCKETS),
+                cache_lookups: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Locks the registry, ignoring poisoning: a panic cannot leave it inconsistent.
+    fn registry(&self) -> std::sync::MutexGuard<'_, Registry> {
+        self.registry.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Counts a handled request.
+    pub fn record_request(&self, route: &str, status: u16) {
+        *self.registry().requests.entry((route.to_string(), status)).or_default() += 1;
+    }
+
+    /// Records the phase durations and output size of a rendered SVG.
+    pub fn record_render(&self, image: &EncodedImage) {
+        let timings = image.timings;
+        let mut registry = self.registry();
+        for (histogram, duration) in registry.phases.iter_mut().zip([timings.parse, timings.render, timings.encode]) {
+            histogram.observe(duration.as_secs_f64());
+        }
+        registry.record_output(image);
+    }
+
+    /// Counts a render cache lookup.
+    pub fn record_cache(&self, result: CacheResult) {
+        *self.registry().cache_lookups.entry(result.label()).or_default() += 1;
+    }
+
+    /// Records the duration and output size of a background removal.
+    pub fn record_transparency(&self, image: &EncodedImage) {
+        let timings = image.timings;
+        let total: Duration = timings.parse + timings.render + timings.encode;
+        let mut registry = self.registry();
+        registry.transparency.observe(total.as_secs_f64());
+        registry.record_output(image);
+    }
+
+    /// Records the duration and size of an icon render.
+    ///
+    /// Icons are kept out of the per-phase histograms: one icon parses once but
+    /// renders and encodes every size, so its phases are not comparable to a
+    /// single image's.
+    pub fn record_icon(&self, duration: Duration, bytes: usize) {
+        let mut registry = self.registry();
+        registry.icon.observe(duration.as_secs_f64());
+        registry.output_bytes.observe(bytes as f64);
+    }
+
+    /// Renders all series in the Prometheus text exposition format.
+    ///
+    /// # Arguments
+    ///
+    /// * `font_faces` - The current size of the font database, read at scrape time.
+    /// * `cache` - The current size of the render cache tiers, read at scrape time.
+    pub fn render(&self, font_faces: usize, cache: cache::Usage) -> String {
+        let registry = self.registry();
+        let mut out = String::new();
+
+        header(&mut out, "svg2png_http_requests_total", "counter", "HTTP requests handled, by route and status.");
+        for ((route, status), count) in &registry.requests {
+            let _ = writeln!(
+                out,
+                "svg2png_http_requests_total{{route=\"{}\",status=\"{}\"}} {}",
+                escape_label(route),
+                status,
+                count
+            );
+        }
+
+        let name = "svg2png_render_phase_seconds";
+        header(&mut out, name, "histogram", "Time spent parsing, rendering and encoding SVGs.");
+        for (phase, histogram) in PHASES.iter().zip(&registry.phases) {
+            histogram.write(&mut out, name, &format!("phase=\"{}\",", phase));
+        }
+
+        let name = "svg2png_transparency_seconds";
+        header(&mut out, name, "histogram", "Time spent removing PNG backgrounds.");
+        registry.transparency.write(&mut out, name, "");
+
+        let name = "svg2png_icon_seconds";
+        header(&mut out, name, "histogram", "Time spent rendering and packaging icons.");
+        registry.icon.write(&mut out, name, "");
+
+        let name = "svg2png_output_pixels";
+        header(&mut out, name, "histogram", "Pixel count of output images.");
+        registry.output_pixels.write(&mut out, name, "");
+
+        let name = "svg2png_output_bytes";
+        header(&mut out, name, "histogram", "Encoded size of output images and icons in bytes.");
+        registry.output_bytes.write(&mut out, name, "");
+
+        header(&mut out, "svg2png_cache_lookups_total", "counter", "Render cache lookups, by result.");
+        for (result, count) in &registry.cache_lookups {
+            let _ = writeln!(out, "svg2png_cache_lookups_total{{result=\"{}\"}} {}", result, count);
+        }
+
+        header(&mut out, "svg2png_cache_bytes", "gauge", "Size of the images in each render cache tier.");
+        let _ = writeln!(out, "svg2png_cache_bytes{{tier=\"memory\"}} {}", cache.memory_bytes);
+        let _ = writeln!(out, "svg2png_cache_bytes{{tier=\"disk\"}} {}", cache.disk_bytes);
+
+        header(&mut out, "svg2png_font_faces", "gauge", "Font faces in the current font database.");
+        let _ = writeln!(out, "svg2png_font_faces {}", font_faces);
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Registry {
+    /// Records the size of an output image.
+    fn record_output(&mut self, image: &EncodedImage) {
+        self.output_pixels.observe(image.width as f64 * image.height as f64);
+        self.output_bytes.observe(image.data.len() as f64);
+    }
+}
+
+/// Writes the `# HELP` and `# TYPE` lines of a metric.
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escapes a label value for the text exposition format.
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Middleware that counts every request by its route template and response status.
+///
+/// Installed with `Router::layer`, which wraps each route and the fallback, so
+/// [`MatchedPath`] is available for matched requests; the template (e.g.
+/// `/svg-to-icon/{format}`) keeps the label cardinality bounded. Requests that
+/// reach the fallback have no matched path and are labeled `unmatched`.
+pub async fn track_requests(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| UNMATCHED_ROUTE.to_string(), |path| path.as_str().to_string());
+    let response = next.run(request).await;
+    state.metrics().record_request(&route, response.status().as_u16());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use svg2png::encode::OutputFormat;
+    use svg2png::PhaseTimings;
+
+    #[test]
+    fn test_exposition_format() {
+        let metrics = Metrics::new();
+        metrics.record_request("/svg-to-png", 200);
+        metrics.record_request("/svg-to-png", 200);
+        metrics.record_request("/svg-to-png", 400);
+        metrics.record_render(&EncodedImage {
+            data: vec![0; 2000],
+            format: OutputFormat::Png,
+            width: 100,
+            height: 50,
+            timings: PhaseTimings {
+                parse: Duration::from_millis(2),
+                render: Duration::from_millis(20),
+                encode: Duration::from_secs(20),
+            },
+        });
+
+        metrics.record_icon(Duration::from_millis(30), 500);
+
+        metrics.record_cache(CacheResult::Hit(Tier::Disk));
+        metrics.record_cache(CacheResult::Miss);
+        metrics.record_cache(CacheResult::Miss);
+
+        let text = metrics.render(42, cache::Usage { memory_bytes: 7, disk_bytes: 0 });
+        assert!(text.contains("svg2png_http_requests_total{route=\"/svg-to-png\",status=\"200\"} 2\n"));
+        assert!(text.contains("svg2png_http_requests_total{route=\"/svg-to-png\",status=\"400\"} 1\n"));
+        assert!(text.contains("# TYPE svg2png_render_phase_seconds histogram\n"));
+        // Buckets are cumulative; observations above the last bound only count in +Inf.
+        assert!(text.contains("svg2png_render_phase_seconds_bucket{phase=\"parse\",le=\"0.001\"} 0\n"));
+        assert!(text.contains("svg2png_render_phase_seconds_bucket{phase=\"parse\",le=\"0.0025\"} 1\n"));
+        assert!(text.contains("svg2png_render_phase_seconds_bucket{phase=\"encode\",le=\"10\"} 0\n"));
+        assert!(text.contains("svg2png_render_phase_seconds_bucket{phase=\"encode\",le=\"+Inf\"} 1\n"));
+        assert!(text.contains("svg2png_render_phase_seconds_count{phase=\"render\"} 1\n"));
+        assert!(text.contains("svg2png_output_pixels_bucket{le=\"16384\"} 1\n"));
+        assert!(text.contains("svg2png_output_bytes_sum 2500\n"));
+        assert!(text.contains("svg2png_output_pixels_count 1\n"));
+        assert!(text.contains("svg2png_icon_seconds_bucket{le=\"0.05\"} 1\n"));
+        assert!(text.contains("svg2png_transparency_seconds_count 0\n"));
+        assert!(text.contains("svg2png_cache_lookups_total{result=\"disk_hit\"} 1\n"));
+        assert!(text.contains("svg2png_cache_lookups_total{result=\"miss\"} 2\n"));
+        assert!(text.contains("svg2png_cache_bytes{tier=\"memory\"} 7\n"));
+        assert!(text.contains("svg2png_font_faces 42\n"));
+    }
+}
@@ -0,0 +1,22 @@
+//! # HTTP Service
+//!
+//! Everything specific to serving the [`svg2png`] library over HTTP: the
+//! routes and their query parameters, service configuration, error responses,
+//! admin authentication, batch archives, form uploads, the render cache,
+//! metrics, tracing, the bounded render pool, readiness checks, request IDs
+//! and shared state.
+
+pub mod admin;
+pub mod batch;
+pub mod cache;
+pub mod config;
+pub mod error;
+pub mod form;
+pub mod metrics;
+pub mod params;
+pub mod pool;
+pub mod readiness;
+pub mod request_id;
+pub mod routes;
+pub mod state;
+pub mod telemetry;
@@ -0,0 +1,303 @@
+//! # Query Parameters
+//!
+//! Parses the query strings of the conversion endpoints into library options.
+//! Invalid values are rejected with `400 Bad Request` (`invalid_parameter`).
+//! The single-value parsers are shared with the command line (see [`crate::cli`]).
+
+use std::time::Duration;
+
+use tracing::debug;
+
+use svg2png::encode::OutputFormat;
+use svg2png::icon::{IconFormat, IconOptions};
+use svg2png::sizing;
+use svg2png::transparency::{self, KeyOptions};
+use svg2png::RenderOptions;
+
+use super::error::AppError;
+
+/// Query parameter name for the output DPI.
+const DPI_QUERY_PARAM: &str = "dpi";
+/// Query parameter name for an explicit output width in pixels.
+const WIDTH_QUERY_PARAM: &str = "width";
+/// Query parameter name for an explicit output height in pixels.
+const HEIGHT_QUERY_PARAM: &str = "height";
+/// Query parameter name for the fit mode used when both width and height are given.
+const FIT_QUERY_PARAM: &str = "fit";
+/// Query parameter name for the content alignment inside the output canvas.
+const ALIGN_QUERY_PARAM: &str = "align";
+/// Query parameter name for the background color (CSS color syntax).
+const BACKGROUND_QUERY_PARAM: &str = "background";
+/// Query parameter name for flattening alpha and emitting an RGB PNG.
+const FLATTEN_QUERY_PARAM: &str = "flatten";
+/// Query parameter name for the output format of `/svg-to-image`.
+pub const FORMAT_QUERY_PARAM: &str = "format";
+/// Query parameter name for the lossy encoding quality (1-100).
+const QUALITY_QUERY_PARAM: &str = "quality";
+/// Query parameter name for forcing lossless WebP encoding.
+const LOSSLESS_QUERY_PARAM: &str = "lossless";
+/// Query parameter name for the ID of the only element to render.
+const ID_QUERY_PARAM: &str = "id";
+/// Query parameter name for overriding the region to render (`x,y,w,h`).
+const VIEWBOX_QUERY_PARAM: &str = "viewbox";
+/// Query parameter name for cropping to the drawn content.
+const TRIM_QUERY_PARAM: &str = "trim";
+/// Query parameter name for the space around the rendered region, in canvas pixels.
+const PADDING_QUERY_PARAM: &str = "padding";
+/// Query parameter name for CSS injected while parsing.
+const CSS_QUERY_PARAM: &str = "css";
+/// Query parameter name for the color `currentColor` resolves to.
+const COLOR_QUERY_PARAM: &str = "color";
+/// Query parameter name for lowering the render timeout, in milliseconds.
+const TIMEOUT_QUERY_PARAM: &str = "timeout_ms";
+/// Query parameter name for the comma-separated pixel sizes of `/svg-to-icon`.
+const SIZES_QUERY_PARAM: &str = "sizes";
+/// Query parameter name for the Android launcher icon name of `/svg-to-icon`.
+const NAME_QUERY_PARAM: &str = "name";
+/// Query parameter name for the color tolerance of `/png-to-transparent`.
+const FUZZ_QUERY_PARAM: &str = "fuzz";
+/// Query parameter name for a seed point (`x,y` or `corners`); may be repeated.
+const SEED_QUERY_PARAM: &str = "seed";
+/// Query parameter name for an explicit color to key out (CSS color syntax).
+const KEY_COLOR_QUERY_PARAM: &str = "color";
+/// Query parameter name for the keying mode (`flood` or `global`).
+const MODE_QUERY_PARAM: &str = "mode";
+/// Query parameter name for the edge feathering radius in pixels.
+const FEATHER_QUERY_PARAM: &str = "feather";
+
+/// Rendering parameters parsed from the `/svg-to-png` and `/svg-to-image` query string.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RenderParams {
+    /// Options passed to the renderer. `options.format` is set by the handler.
+    pub options: RenderOptions,
+    /// Explicit output format (`/svg-to-image` only). Negotiated if unset.
+    pub format: Option<OutputFormat>,
+}
+
+/// Parses a positive pixel dimension from a query parameter value.
+pub fn parse_dimension(key: &str, value: &str) -> Result<u32, AppError> {
+    match value.parse::<u32>() {
+        Ok(v) if v > 0 => Ok(v),
+        _ => Err(AppError::InvalidParameter(format!(
+            "Invalid {}: '{}' must be a positive integer",
+            key, value
+        ))),
+    }
+}
+
+/// Parses a CSS color (hex, `rgb()`/`rgba()`, `hsl()` or a named color).
+pub fn parse_color(key: &str, value: &str) -> Result<svgtypes::Color, AppError> {
+    value.parse::<svgtypes::Color>().map_err(|e| {
+        AppError::InvalidParameter(format!("Invalid {}: '{}' is not a valid CSS color ({})", key, value, e))
+    })
+}
+
+/// Parses a boolean flag (`true`/`false`, `1`/`0`, `yes`/`no`).
+fn parse_bool(key: &str, value: &str) -> Result<bool, AppError> {
+    match value {
+        "true" | "1" | "yes" => Ok(true),
+        "false" | "0" | "no" => Ok(false),
+        _ => Err(AppError::InvalidParameter(format!(
+            "Invalid {}: '{}' must be true or false",
+            key, value
+        ))),
+    }
+}
+
+/// Parses an encoding quality in the range 1-100.
+pub fn parse_quality(key: &str, value: &str) -> Result<u8, AppError> {
+    match value.parse::<u8>() {
+        Ok(v) if (1..=100).contains(&v) => Ok(v),
+        _ => Err(AppError::InvalidParameter(format!(
+            "Invalid {}: '{}' must be an integer between 1 and 100",
+            key, value
+        ))),
+    }
+}
+
+/// Parses the `/svg-to-png` and `/svg-to-image` query string into [`RenderParams`].
+///
+/// An invalid or non-positive `dpi` silently falls back to the default, matching
+/// the historical behavior of the endpoint. Invalid values for any other
+/// parameter are rejected with `400 Bad Request`.
+pub fn parse_render_params(query: Option<&str>) -> Result<RenderParams, AppError> {
+    let mut params = RenderParams::default();
+    let Some(query) = query else {
+        return Ok(params);
+    };
+
+    // Iterate over query parameters using form_urlencoded.
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        match key.as_ref() {
+            DPI_QUERY_PARAM => {
+                // Use the parsed value only if it's positive.
+                if let Ok(dpi_val) = value.parse::<f32>() {
+                    if dpi_val > 0.0 {
+                        params.options.dpi = dpi_val;
+                    }
+                }
+                debug!(%value, "Parsed DPI from query string");
+            }
+            WIDTH_QUERY_PARAM => params.options.width = Some(parse_dimension(&key, &value)?),
+            HEIGHT_QUERY_PARAM => params.options.height = Some(parse_dimension(&key, &value)?),
+            FIT_QUERY_PARAM => {
+                params.options.fit = value.parse().map_err(AppError::InvalidParameter)?;
+            }
+            ALIGN_QUERY_PARAM => {
+                params.options.align = value.parse().map_err(AppError::InvalidParameter)?;
+            }
+            BACKGROUND_QUERY_PARAM => params.options.background = Some(parse_color(&key, &value)?),
+            FLATTEN_QUERY_PARAM => params.options.flatten = parse_bool(&key, &value)?,
+            FORMAT_QUERY_PARAM => {
+                params.format = Some(value.parse().map_err(AppError::InvalidParameter)?);
+            }
+            QUALITY_QUERY_PARAM => params.options.quality = Some(parse_quality(&key, &value)?),
+            LOSSLESS_QUERY_PARAM => params.options.lossless = parse_bool(&key, &value)?,
+            ID_QUERY_PARAM if value.is_empty() => {
+                return Err(AppError::InvalidParameter(format!("Invalid {}: must not be empty", key)));
+            }
+            ID_QUERY_PARAM => params.options.element = Some(value.into_owned()),
+            VIEWBOX_QUERY_PARAM => {
+                params.options.view_box = Some(sizing::parse_view_box(&value).map_err(AppError::InvalidParameter)?);
+            }
+            TRIM_QUERY_PARAM => params.options.trim = parse_bool(&key, &value)?,
+            CSS_QUERY_PARAM => params.options.style_sheet = Some(value.into_owned()),
+            COLOR_QUERY_PARAM => params.options.color = Some(parse_color(&key, &value)?),
+            PADDING_QUERY_PARAM => {
+                params.options.padding = value.parse().map_err(AppError::InvalidParameter)?;
+            }
+            _ => {}
+        }
+    }
+
+    let options = &params.options;
+    if [options.element.is_some(), options.view_box.is_some(), options.trim].iter().filter(|set| **set).count() > 1 {
+        return Err(AppError::InvalidParameter(format!(
+            "Invalid parameters: only one of {}, {} and {} can be given",
+            ID_QUERY_PARAM, VIEWBOX_QUERY_PARAM, TRIM_QUERY_PARAM
+        )));
+    }
+    Ok(params)
+}
+
+/// Parses the `/svg-to-icon` query string into [`IconOptions`].
+///
+/// Accepts the `/svg-to-png` placement and background parameters plus `sizes`
+/// and `name`. `width`, `height` and `format` are rejected since the package
+/// determines them.
+pub fn parse_icon_options(format: IconFormat, query: Option<&str>) -> Result<IconOptions, AppError> {
+    let params = parse_render_params(query)?;
+    let mut options = IconOptions { format, render: params.options, ..IconOptions::default() };
+    let Some(query) = query else {
+        return Ok(options);
+    };
+
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        match key.as_ref() {
+            SIZES_QUERY_PARAM => {
+                for size in value.split(',') {
+                    options.sizes.push(parse_dimension(&key, size.trim())?);
+                }
+            }
+            NAME_QUERY_PARAM => options.name = Some(value.into_owned()),
+            WIDTH_QUERY_PARAM | HEIGHT_QUERY_PARAM | FORMAT_QUERY_PARAM => {
+                return Err(AppError::InvalidParameter(format!(
+                    "Invalid {}: not supported for icons, use {} instead",
+                    key, SIZES_QUERY_PARAM
+                )));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(options)
+}
+
+/// Parses the optional `timeout_ms` query parameter.
+///
+/// The configured timeout is an upper bound: larger values are clamped to it,
+/// so clients can only shorten the deadline.
+pub fn parse_timeout(query: Option<&str>, max: Duration) -> Result<Duration, AppError> {
+    let Some(value) = query.and_then(|query| {
+        form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == TIMEOUT_QUERY_PARAM)
+            .map(|(_, value)| value)
+    }) else {
+        return Ok(max);
+    };
+    match value.parse::<u64>() {
+        Ok(ms) if ms > 0 => Ok(Duration::from_millis(ms).min(max)),
+        _ => Err(AppError::InvalidParameter(format!(
+            "Invalid {}: '{}' must be a positive integer",
+            TIMEOUT_QUERY_PARAM, value
+        ))),
+    }
+}
+
+/// Parses a fuzz percentage such as `5%` or `7.5` into a fraction of the channel range.
+pub fn parse_fuzz(key: &str, value: &str) -> Result<f32, AppError> {
+    match value.trim_end_matches('%').parse::<f32>() {
+        Ok(v) if (0.0..=100.0).contains(&v) => Ok(v / 100.0),
+        _ => Err(AppError::InvalidParameter(format!(
+            "Invalid {}: '{}' must be a percentage between 0 and 100",
+            key, value
+        ))),
+    }
+}
+
+/// Parses an edge feathering radius between 0 and [`transparency::MAX_FEATHER`] pixels.
+pub fn parse_feather(key: &str, value: &str) -> Result<u32, AppError> {
+    match value.parse::<u32>() {
+        Ok(v) if v <= transparency::MAX_FEATHER => Ok(v),
+        _ => Err(AppError::InvalidParameter(format!(
+            "Invalid {}: '{}' must be an integer between 0 and {}",
+            key,
+            value,
+            transparency::MAX_FEATHER
+        ))),
+    }
+}
+
+/// Parses the `/png-to-transparent` query string into [`KeyOptions`].
+///
+/// Invalid values are rejected with `400 Bad Request`.
+pub fn parse_key_options(query: Option<&str>) -> Result<KeyOptions, AppError> {
+    let mut options = KeyOptions::default();
+    let Some(query) = query else {
+        return Ok(options);
+    };
+
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        match key.as_ref() {
+            FUZZ_QUERY_PARAM => options.fuzz = parse_fuzz(&key, &value)?,
+            SEED_QUERY_PARAM => {
+                options.seeds.push(value.parse().map_err(AppError::InvalidParameter)?);
+            }
+            KEY_COLOR_QUERY_PARAM => {
+                let color = parse_color(&key, &value)?;
+                options.color = Some([color.red, color.green, color.blue, color.alpha]);
+            }
+            MODE_QUERY_PARAM => {
+                options.mode = value.parse().map_err(AppError::InvalidParameter)?;
+            }
+            FEATHER_QUERY_PARAM => options.feather = parse_feather(&key, &value)?,
+            _ => {}
+        }
+    }
+
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeout_can_only_be_lowered() {
+        let max = Duration::from_secs(30);
+        assert_eq!(parse_timeout(None, max), Ok(max));
+        assert_eq!(parse_timeout(Some("dpi=300&timeout_ms=250"), max), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_timeout(Some("timeout_ms=600000"), max), Ok(max));
+        assert_eq!(parse_timeout(Some("timeout_ms=soon"), max).unwrap_err().code(), "invalid_parameter");
+    }
+}
@@ -0,0 +1,206 @@
+//! # Render Pool
+//!
+//! Rendering and encoding are CPU-bound and can take hundreds of milliseconds
+//! for large images. Running them directly in an async handler blocks a Tokio
+//! worker thread, which stalls unrelated requests such as `/health`.
+//!
+//! [`RenderPool`] moves that work onto Tokio's blocking thread pool while
+//! bounding how many jobs run at once and how many may wait for a slot. When
+//! both are exhausted, new jobs are rejected immediately with
+//! [`AppError::Overloaded`] (`503 Service Unavailable` with `Retry-After`)
+//! instead of piling up.
+//!
+//! Every job also has a [`Deadline`]. When it passes, the request fails with
+//! [`RenderError::Timeout`](svg2png::RenderError::Timeout) (`504 Gateway Timeout`).
+//! resvg cannot be interrupted mid-render, so the abandoned job keeps running
+//! until it reaches its next [`Deadline::check`] and bails out. It keeps its
+//! slot until then, so timed-out jobs still count towards the concurrency
+//! limit.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use svg2png::Deadline;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, error, warn, Span};
+
+use super::config::PoolConfig;
+use super::error::AppError;
+
+/// Seconds clients are asked to wait before retrying when the pool is saturated.
+const RETRY_AFTER_SECS: u64 = 1;
+
+/// A bounded pool for blocking render jobs.
+///
+/// Cloning is cheap and clones share the same limits.
+#[derive(Debug, Clone)]
+pub struct RenderPool {
+    /// Permits for jobs that are running or waiting (`concurrency + queue_depth`).
+    admission: Arc<Semaphore>,
+    /// Permits for jobs that are running (`concurrency`).
+    workers: Arc<Semaphore>,
+    /// The configured (maximum) render timeout.
+    timeout: Duration,
+    /// The configured timeout for all renders of a batch.
+    batch_timeout: Duration,
+}
+
+impl RenderPool {
+    /// Creates a pool with the given sizing.
+    pub fn new(config: PoolConfig) -> Self {
+        RenderPool {
+            admission: Arc::new(Semaphore::new(config.concurrency + config.queue_depth)),
+            workers: Arc::new(Semaphore::new(config.concurrency)),
+            timeout: config.timeout,
+            batch_timeout: config.batch_timeout,
+        }
+    }
+
+    /// The configured render timeout. Requests may ask for a shorter one.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// The time all renders of a batch request may take together.
+    pub fn batch_timeout(&self) -> Duration {
+        self.batch_timeout
+    }
+
+    /// How many more jobs would be admitted right now, running or queued.
+    ///
+    /// `0` means the pool is saturated and [`run`](Self::run) rejects new jobs.
+    pub fn free_slots(&self) -> usize {
+        self.admission.available_permits()
+    }
+
+    /// Runs `job` on the blocking thread pool once a worker slot is free.
+    ///
+    /// The time spent waiting for a worker counts towards `timeout`.
+    ///
+    /// The job's permits are released when it returns, even if `timeout`
+    /// expired or the request was cancelled (e.g. the client disconnected)
+    /// first. A job that times out while still waiting for a worker never runs.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(T)` / `Err(AppError)` - The job's own result.
+    /// * `Err(AppError::Overloaded)` - If all workers are busy and the queue is full.
+    /// * `Err(AppError::Render(RenderError::Timeout))` - If the job did not finish within `timeout`.
+    /// * `Err(AppError::Internal)` - If the job could not be run to completion.
+    pub async fn run<F, T>(&self, timeout: Duration, job: F) -> Result<T, AppError>
+    where
+        F: FnOnce(&Deadline) -> Result<T, AppError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let admitted = self.admission.clone().try_acquire_owned().map_err(|_| {
+            warn!("Render pool saturated, rejecting request");
+            AppError::Overloaded { retry_after_secs: RETRY_AFTER_SECS }
+        })?;
+        let deadline = Deadline::after(timeout);
+
+        match tokio::time::timeout(timeout, self.run_admitted(admitted, deadline, job)).await {
+            Ok(result) => result,
+            Err(_) => Err(deadline.error().into()),
+        }
+    }
+
+    /// Waits for a worker and runs an admitted job, holding both permits until it returns.
+    async fn run_admitted<F, T>(&self, admitted: OwnedSemaphorePermit, deadline: Deadline, job: F) -> Result<T, AppError>
+    where
+        F: FnOnce(&Deadline) -> Result<T, AppError> + Send + 'static,
+        T: Send + 'static,
+    {
+        debug!(available = self.workers.available_permits(), "Waiting for a render worker");
+        let worker = self
+            .workers
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|e| AppError::Internal(format!("Render pool closed: {}", e)))?;
+
+        // Keep the request's span so logs from the job are attributed to it.
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let result = span.in_scope(|| job(&deadline));
+            drop((admitted, worker));
+            result
+        })
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Render task failed");
+            AppError::Internal(format!("Render task failed: {}", e))
+        })?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use svg2png::RenderError;
+
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    #[tokio::test]
+    async fn test_rejects_when_workers_and_queue_are_full() {
+        let pool = RenderPool::new(PoolConfig { concurrency: 1, queue_depth: 1, ..PoolConfig::default() });
+        let (release, blocked) = std::sync::mpsc::channel::<()>();
+
+        // Occupy the only worker, then the only queue slot.
+        let running = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(TIMEOUT, move |_| Ok(blocked.recv().is_ok())).await }
+        });
+        while pool.workers.available_permits() > 0 {
+            tokio::task::yield_now().await;
+        }
+        let queued = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(TIMEOUT, |_| Ok(true)).await }
+        });
+        while pool.admission.available_permits() > 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let err = pool.run(TIMEOUT, |_| Ok(true)).await.unwrap_err();
+        assert_eq!(err, AppError::Overloaded { retry_after_secs: RETRY_AFTER_SECS });
+
+        release.send(()).unwrap();
+        assert_eq!(running.await.unwrap(), Ok(true));
+        assert_eq!(queued.await.unwrap(), Ok(true));
+        assert_eq!(pool.run(TIMEOUT, |_| Ok(1)).await, Ok(1));
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_job_holds_slot_until_it_exits() {
+        let pool = RenderPool::new(PoolConfig { concurrency: 1, queue_depth: 0, ..PoolConfig::default() });
+        let (resume, paused) = std::sync::mpsc::channel::<()>();
+        let (checked, check_result) = std::sync::mpsc::channel();
+
+        let err = pool
+            .run(Duration::from_millis(20), move |deadline| {
+                paused.recv().unwrap();
+                checked.send(deadline.check("render")).unwrap();
+                Ok(())
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err, AppError::Render(RenderError::Timeout { timeout_ms: 20 }));
+        assert_eq!(err.status(), axum::http::StatusCode::GATEWAY_TIMEOUT);
+
+        // The abandoned job is still running, so it still occupies the only slot.
+        assert_eq!(pool.workers.available_permits(), 0);
+        assert_eq!(pool.admission.available_permits(), 0);
+        let err = pool.run(TIMEOUT, |_| Ok(1)).await.unwrap_err();
+        assert_eq!(err, AppError::Overloaded { retry_after_secs: RETRY_AFTER_SECS });
+
+        // It bails out at its next checkpoint and gives the slot back.
+        resume.send(()).unwrap();
+        let result = tokio::task::spawn_blocking(move || check_result.recv().unwrap()).await.unwrap();
+        assert_eq!(result, Err(RenderError::Timeout { timeout_ms: 20 }));
+        while pool.admission.available_permits() == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(pool.workers.available_permits(), 1);
+        assert_eq!(pool.run(TIMEOUT, |_| Ok(1)).await, Ok(1));
+    }
+}
@@ -0,0 +1,275 @@
+//! # Readiness
+//!
+//! `/ready` reports whether the service can do useful work, as opposed to
+//! `/health`, which only reports that the process is up. Each dependency of
+//! the renderer is checked on every probe and described in the response:
+//!
+//! ```json
+//! {
+//!   "status": "ready",
+//!   "checks": {
+//!     "fonts": { "status": "pass", "font_faces": 312 },
+//!     "convert": { "status": "skip", "detail": "Not required: ..." },
+//!     "temp_dir": { "status": "pass", "path": "/tmp" },
+//!     "render_pool": { "status": "pass", "free_slots": 72 },
+//!     "smoke_render": { "status": "pass", "duration_ms": 2 }
+//!   }
+//! }
+//! ```
+//!
+//! The service is ready when no check fails. ImageMagick's `convert` is
+//! listed but not probed: background removal is implemented natively, so a
+//! missing `convert` binary cannot break `/png-to-transparent`. The smoke
+//! render exercises both the SVG renderer and background removal. It runs on
+//! the [`RenderPool`] like any request, with a short timeout, so probes cannot
+//! add unbounded blocking work.
+//!
+//! A saturated pool does not make the service unready: render requests are
+//! already answered with `503` and `Retry-After` then, and if every busy
+//! replica left the load balancer together, a traffic spike would become an
+//! outage. The `render_pool` check always passes and only reports
+//! `free_slots`; while it is `0`, the smoke render is skipped.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use svg2png::transparency::KeyOptions;
+use svg2png::{Deadline, RenderOptions, Renderer};
+
+use super::error::AppError;
+use super::pool::RenderPool;
+use super::state::AppState;
+
+/// Check status for a passing check.
+const PASS: &str = "pass";
+/// Check status for a failing check.
+const FAIL: &str = "fail";
+/// Check status for a check that does not apply to this build.
+const SKIP: &str = "skip";
+
+/// Longest the smoke render may take before the check fails.
+const SMOKE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Side length in pixels of the smoke render.
+const SMOKE_SIZE: u32 = 16;
+/// The SVG rendered by the smoke test: a square on a white background.
+const SMOKE_SVG: &[u8] = br##"<svg xmlns="http://www.w3.org/2000/svg" width="16" height="16">
+  <rect width="16" height="16" fill="#fff"/>
+  <rect x="4" y="4" width="8" height="8" fill="#c00"/>
+</svg>"##;
+
+/// The `/ready` response body.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// `ready` or `not_ready`.
+    pub status: &'static str,
+    /// The outcome of each check.
+    pub checks: Checks,
+}
+
+impl Report {
+    /// Returns `true` if no check failed.
+    pub fn is_ready(&self) -> bool {
+        let Checks { fonts, convert, temp_dir, render_pool, smoke_render } = &self.checks;
+        [fonts, convert, temp_dir, render_pool, smoke_render].iter().all(|check| check.status != FAIL)
+    }
+}
+
+/// The checks performed by `/ready`.
+#[derive(Debug, Serialize)]
+pub struct Checks {
+    /// At least one font face is loaded.
+    pub fonts: Check,
+    /// ImageMagick's `convert`, which the service does not depend on.
+    pub convert: Check,
+    /// The temp directory is writable.
+    pub temp_dir: Check,
+    /// How many jobs the render pool would still admit. Never fails.
+    pub render_pool: Check,
+    /// A small SVG renders and has its background removed.
+    pub smoke_render: Check,
+}
+
+/// The outcome of a single check.
+#[derive(Debug, Default, Serialize)]
+pub struct Check {
+    /// `pass`, `fail` or `skip`.
+    pub status: &'static str,
+    /// Number of loaded font faces, for the font check.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub font_faces: Option<usize>,
+    /// The checked directory, for the temp directory check.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// Jobs the render pool would still admit, for the render pool check.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub free_slots: Option<usize>,
+    /// How long the check took, for the smoke render.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// Why the check failed or was skipped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Check {
+    /// Creates a passing or failing check from a result.
+    fn from_result(result: Result<(), String>) -> Self {
+        match result {
+            Ok(()) => Check { status: PASS, ..Check::default() },
+            Err(detail) => Check { status: FAIL, detail: Some(detail), ..Check::default() },
+        }
+    }
+}
+
+/// Runs every check.
+///
+/// # Arguments
+///
+/// * `state` - The shared application state whose renderer and pool are checked.
+/// * `temp_dir` - The directory checked for writability.
+///
+/// # Returns
+///
+/// * `Report` - The outcome of each check and the overall status.
+pub async fn run(state: &AppState, temp_dir: &Path) -> Report {
+    // Checked before the smoke render takes a slot of its own.
+    let render_pool = check_render_pool(state.pool());
+    let checks = Checks {
+        fonts: check_fonts(state.renderer()),
+        convert: Check {
+            status: SKIP,
+            detail: Some("Not required: background removal is native and does not use ImageMagick".to_string()),
+            ..Check::default()
+        },
+        temp_dir: check_temp_dir(temp_dir).await,
+        render_pool,
+        smoke_render: check_smoke_render(state).await,
+    };
+    let mut report = Report { status: "ready", checks };
+    if !report.is_ready() {
+        report.status = "not_ready";
+    }
+    report
+}
+
+/// Fails if the font database is empty, in which case text would not render.
+fn check_fonts(renderer: &Renderer) -> Check {
+    let faces = renderer.fontdb().len();
+    let result = if faces == 0 { Err("No font faces are loaded".to_string()) } else { Ok(()) };
+    Check { font_faces: Some(faces), ..Check::from_result(result) }
+}
+
+/// Creates, writes and removes a uniquely named file in `dir`.
+async fn check_temp_dir(dir: &Path) -> Check {
+    let file = dir.join(format!(".svg2png-ready-{}", uuid::Uuid::new_v4()));
+    let result = match tokio::fs::write(&file, b"ready").await {
+        Ok(()) => tokio::fs::remove_file(&file).await,
+        Err(e) => Err(e),
+    }
+    .map_err(|e| format!("Cannot write to {}: {}", dir.display(), e));
+    Check { path: Some(dir.to_path_buf()), ..Check::from_result(result) }
+}
+
+/// Reports the free slots of the render pool, noting when it is saturated.
+fn check_render_pool(pool: &RenderPool) -> Check {
+    let free_slots = pool.free_slots();
+    let detail = (free_slots == 0)
+        .then(|| "All render workers are busy and the queue is full; render requests get 503".to_string());
+    Check { status: PASS, free_slots: Some(free_slots), detail, ..Check::default() }
+}
+
+/// Renders [`SMOKE_SVG`] on the render pool and removes its white background.
+///
+/// Skipped if the pool has no room for it, without failing readiness.
+async fn check_smoke_render(state: &AppState) -> Check {
+    let start = Instant::now();
+    let renderer = state.renderer().clone();
+    let result = state.pool().run(SMOKE_TIMEOUT, move |deadline| smoke_render(&renderer, deadline)).await;
+    let duration_ms = start.elapsed().as_millis().try_into().unwrap_or(u64::MAX);
+    match result {
+        Err(AppError::Overloaded { .. }) => Check {
+            status: SKIP,
+            detail: Some("Skipped: the render pool is saturated".to_string()),
+            ..Check::default()
+        },
+        result => Check { duration_ms: Some(duration_ms), ..Check::from_result(result.map_err(|e| e.to_string())) },
+    }
+}
+
+/// The smoke render job: renders and keys [`SMOKE_SVG`], checking the size of the result.
+fn smoke_render(renderer: &Renderer, deadline: &Deadline) -> Result<(), AppError> {
+    let png = renderer.render_png(SMOKE_SVG, &RenderOptions { deadline: Some(*deadline), ..RenderOptions::default() })?;
+    let image = renderer.make_transparent(&png.data, &KeyOptions { deadline: Some(*deadline), ..KeyOptions::default() })?;
+    match (image.width, image.height) {
+        (SMOKE_SIZE, SMOKE_SIZE) => Ok(()),
+        (width, height) => Err(AppError::Internal(format!(
+            "Expected a {SMOKE_SIZE}x{SMOKE_SIZE} image, got {width}x{height}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use svg2png::config::{FontConfig, Limits};
+
+    use crate::server::cache::RenderCache;
+    use crate::server::config::{AdminConfig, PoolConfig};
+
+    fn state(font_config: FontConfig, pool_config: PoolConfig) -> AppState {
+        AppState::new(font_config, Limits::default(), pool_config, RenderCache::default(), AdminConfig::default())
+    }
+
+    #[tokio::test]
+    async fn test_fails_without_fonts_or_writable_temp_dir() {
+        let font_config = FontConfig { load_system_fonts: false, ..FontConfig::default() };
+        let state = state(font_config, PoolConfig::default());
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing");
+
+        let report = run(&state, &missing).await;
+        assert!(!report.is_ready());
+        assert_eq!(report.status, "not_ready");
+        assert_eq!(report.checks.fonts.status, FAIL);
+        assert_eq!(report.checks.fonts.font_faces, Some(0));
+        assert_eq!(report.checks.convert.status, SKIP);
+        assert_eq!(report.checks.temp_dir.status, FAIL);
+        assert!(report.checks.temp_dir.detail.as_deref().unwrap().starts_with("Cannot write to"));
+        assert_eq!(report.checks.render_pool.status, PASS);
+        // Rendering shapes does not need fonts.
+        assert_eq!(report.checks.smoke_render.status, PASS);
+
+        let report = run(&state, dir.path()).await;
+        assert_eq!(report.checks.temp_dir.status, PASS);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_saturated_pool_stays_ready_and_skips_smoke_render() {
+        let state = state(FontConfig::default(), PoolConfig { concurrency: 1, queue_depth: 0, ..PoolConfig::default() });
+        let (release, blocked) = mpsc::channel::<()>();
+        let job = tokio::spawn({
+            let state = state.clone();
+            async move { state.pool().run(Duration::from_secs(30), move |_| Ok(blocked.recv().ok())).await }
+        });
+        while state.pool().free_slots() > 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let report = run(&state, &std::env::temp_dir()).await;
+        assert_eq!(report.status, "ready");
+        assert_eq!(report.checks.render_pool.status, PASS);
+        assert_eq!(report.checks.render_pool.free_slots, Some(0));
+        assert!(report.checks.render_pool.detail.is_some());
+        assert_eq!(report.checks.smoke_render.status, SKIP);
+
+        release.send(()).unwrap();
+        job.await.unwrap().unwrap();
+        let report = run(&state, &std::env::temp_dir()).await;
+        assert_eq!(report.checks.render_pool.status, PASS);
+        assert_eq!(report.checks.render_pool.detail, None);
+        assert_eq!(report.checks.smoke_render.status, PASS);
+    }
+}
@@ -0,0 +1,74 @@
+//! # Request IDs
+//!
+//! Assigns every request an ID so failed conversions can be correlated with
+//! server logs. A caller (typically a gateway) may supply its own in the
+//! `X-Request-Id` header; otherwise a UUID is generated. The ID is echoed in
+//! the `X-Request-Id` response header and stored in a task-local for the
+//! duration of the request, which lets error responses and the request span
+//! include it without threading it through every handler.
+
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+
+/// The request and response header carrying the request ID.
+pub static HEADER: HeaderName = HeaderName::from_static("x-request-id");
+/// Maximum length of a caller-supplied request ID.
+const MAX_LEN: usize = 128;
+
+tokio::task_local! {
+    /// The ID of the request currently being handled on this task.
+    static REQUEST_ID: String;
+}
+
+/// Returns the ID of the request currently being handled, if any.
+///
+/// Only available within the async task handling the request (not inside
+/// `spawn_blocking` closures).
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Middleware that assigns a request ID, makes it available via [`current`]
+/// and echoes it in the response.
+pub async fn assign_request_id(request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(&HEADER)
+        .and_then(accepted_id)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let header = HeaderValue::from_str(&id).ok();
+    let mut response = REQUEST_ID.scope(id, next.run(request)).await;
+    if let Some(header) = header {
+        response.headers_mut().insert(HEADER.clone(), header);
+    }
+    response
+}
+
+/// Returns a caller-supplied request ID if it is safe to log and echo.
+///
+/// IDs must be 1 to 128 visible ASCII characters; anything else is replaced
+/// by a generated ID rather than rejected.
+fn accepted_id(value: &HeaderValue) -> Option<String> {
+    let id = value.to_str().ok()?;
+    let valid = !id.is_empty() && id.len() <= MAX_LEN && id.bytes().all(|b| b.is_ascii_graphic());
+    valid.then(|| id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accepted_id() {
+        let accept = |id: &str| accepted_id(&HeaderValue::from_str(id).unwrap());
+        assert_eq!(accept("gw-7f3a_01:2").as_deref(), Some("gw-7f3a_01:2"));
+        assert_eq!(accept(""), None);
+        assert_eq!(accept("two words"), None);
+        assert_eq!(accept(&"a".repeat(MAX_LEN)).map(|id| id.len()), Some(MAX_LEN));
+        assert_eq!(accept(&"a".repeat(MAX_LEN + 1)), None);
+    }
+}
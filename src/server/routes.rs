@@ -0,0 +1,1501 @@
+//! # Routes
+//!
+//! The HTTP handlers of the service and the [`router`] that wires them to
+//! their paths and middleware.
+
+use std::time::Instant;
+
+use axum::{
+    body::Bytes,
+    extract::{rejection::BytesRejection, DefaultBodyLimit, Path, State},
+    http::{header, HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use tracing::{debug, error, info, instrument, warn};
+
+use svg2png::config::Limits;
+use svg2png::encode::{self, OutputFormat};
+use svg2png::icon::{IconFormat, IconOptions};
+use svg2png::transparency::KeyOptions;
+use svg2png::{Deadline, RenderOptions};
+
+use super::admin;
+use super::batch;
+use super::cache;
+use super::error::AppError;
+use super::form;
+use super::metrics::{self, CacheResult};
+use super::params::{parse_icon_options, parse_key_options, parse_render_params, parse_timeout, FORMAT_QUERY_PARAM};
+use super::readiness;
+use super::request_id;
+use super::state::AppState;
+use super::telemetry;
+
+/// HTTP Content-Type value for PNG images.
+const PNG_CONTENT_TYPE: &str = "image/png";
+/// HTTP Content-Type value for ZIP archives.
+const ZIP_CONTENT_TYPE: &str = "application/zip";
+/// `Content-Disposition` of batch conversion responses.
+const BATCH_CONTENT_DISPOSITION: &str = "attachment; filename=\"svg2png-batch.zip\"";
+/// Seconds clients are asked to wait before retrying while fonts are being reloaded.
+const FONT_RELOAD_RETRY_AFTER_SECS: u64 = 5;
+
+// The `instrument` macro automatically adds logging for function entry/exit.
+#[instrument(skip(state, headers, body))]
+/// Converts an SVG image provided in the request body to a PNG image.
+///
+/// Accepts an optional `dpi` query parameter to control the output resolution.
+/// If `dpi` is not provided, invalid, or non-positive, it defaults to 96 DPI.
+/// The SVG is scaled according to the requested DPI relative to the default 96 DPI.
+///
+/// Alternatively, `width` and/or `height` request an exact output size in pixels.
+/// With only one of them the other is derived from the aspect ratio; with both,
+/// `fit` (`contain`, `cover`, `fill`, `scale-down`) and `align` (e.g. `center`,
+/// `top-left`) control how the SVG is placed inside the canvas.
+///
+/// The rendered region defaults to the whole canvas. At most one of these
+/// selects another one:
+///
+/// - `id` renders only the element with that ID (e.g. one symbol instance of a
+///   sprite sheet), cropped to its bounding box including stroke and filters.
+/// - `viewbox=x,y,w,h` overrides the SVG's `viewBox`, in the SVG's own user
+///   coordinates, to render any region of a large drawing.
+/// - `trim=true` crops to the bounding box of the drawn content.
+///
+/// `padding` adds space around the region, in canvas pixels: one value for all
+/// sides or up to four (`top,right,bottom,left`) as in CSS. The padded region
+/// then takes the place of the canvas for `dpi`, `width`, `height`, `fit` and
+/// `align`.
+///
+/// `css` injects a stylesheet while parsing, e.g. to render an icon in a dark
+/// theme. Like a user-agent stylesheet it overrides presentation attributes but
+/// not the SVG's own `<style>` or `style` rules. `color` sets the CSS `color` of
+/// the root element, which `fill="currentColor"` and `stroke="currentColor"`
+/// resolve to. Instead of a raw SVG, the body may be a `multipart/form-data`
+/// upload with an `svg` field and a `css` field (see [`super::form`]).
+///
+/// A `background` CSS color (e.g. `#fff`, `rgb(0,0,0)`, `white`) fills the canvas
+/// before rendering. With `flatten=true` the result is composited onto an opaque
+/// background (the given one, or white) and encoded as an RGB PNG without alpha.
+///
+/// `timeout_ms` lowers the render deadline below the configured
+/// `SVG2PNG_RENDER_TIMEOUT_MS`; larger values are clamped to it.
+///
+/// The resulting PNG image includes a `pHYs` chunk indicating the physical pixel
+/// dimensions based on the effective DPI (the applied scale relative to 96 DPI).
+///
+/// Rendered PNGs are cached by their SVG and options (see [`super::cache`]).
+/// Responses carry a strong `ETag` derived from both and a `Cache-Control`
+/// header; a request whose `If-None-Match` matches the `ETag` gets
+/// `304 Not Modified` without rendering.
+///
+/// # Arguments
+///
+/// * `state` - The shared application state holding the pre-built font database.
+/// * `uri` - The request URI, used to extract the optional query parameters.
+/// * `headers` - The request headers, used to detect form uploads and for `If-None-Match`.
+/// * `body` - The raw SVG, or a form upload of an SVG and a stylesheet.
+///
+/// # Returns
+///
+/// * `Ok(Response)` - On success, returns a response containing the PNG image
+///   data with a `Content-Type` header set to `image/png`, or `304 Not Modified`
+///   with an empty body.
+/// * `Err(AppError)` - On failure, returns a problem details response. Possible errors include:
+///     - `400 Bad Request`: If the request body is empty (`empty_body`), a form upload is
+///       malformed (`invalid_form`), the SVG data is
+///       invalid (`svg_parse_error`), a query parameter is invalid (`invalid_parameter`),
+///       the SVG has no element with the given `id` (`element_not_found`), or the SVG
+///       dimensions result in a zero-sized image after scaling (`zero_size`).
+///     - `500 Internal Server Error`: If there's an issue creating the internal
+///       pixmap (`pixmap_alloc_failed`), the renderer panics (`render_panic`), or
+///       encoding the PNG data fails (`encoder_error`).
+///     - `503 Service Unavailable`: If all render workers are busy and the queue is
+///       full (`overloaded`). A `Retry-After` header is included.
+///     - `504 Gateway Timeout`: If parsing and rendering do not finish before the
+///       deadline (`render_timeout`).
+///
+/// # Panics
+///
+/// `resvg::render` may panic on certain SVGs. Parsing and rendering run inside
+/// [`Renderer::render`](svg2png::Renderer::render), so such panics are logged with a backtrace and
+/// reported as `500 Internal Server Error` (`render_panic`) instead.
+async fn svg_to_png(
+    State(state): State<AppState>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Result<Bytes, BytesRejection>,
+) -> Result<Response, AppError> {
+    debug!(query = uri.query().unwrap_or(""), uri = %uri, "Processing svg_to_png request");
+    let body = read_body(body, state.limits())?;
+
+    let mut params = parse_render_params(uri.query())
+        .and_then(|params| match params.format {
+            Some(_) => Err(AppError::InvalidParameter(format!(
+                "Invalid {}: not supported by /svg-to-png, use /svg-to-image instead",
+                FORMAT_QUERY_PARAM
+            ))),
+            None => Ok(params),
+        })
+        .inspect_err(|e| {
+            error!(error = %e, "Invalid query parameters");
+        })?;
+    let body = unpack_svg_form(&headers, body, &mut params.options).await?;
+    let timeout = parse_timeout(uri.query(), state.pool().timeout())?;
+    debug!(?params, ?timeout, "Parsed render parameters");
+
+    let fonts = state.renderer().font_fingerprint();
+    let key = cache::key(&body, &params.options, &fonts);
+    let cache_headers = [
+        (header::ETAG, key.etag()),
+        (header::CACHE_CONTROL, state.cache().cache_control().to_string()),
+    ];
+    if cache::if_none_match(&headers, &key.etag()) {
+        debug!(%key, "Client copy is current");
+        state.metrics().record_cache(CacheResult::NotModified);
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+    }
+    if let Some((data, tier)) = state.cache().get(&key).await {
+        debug!(%key, ?tier, "Serving cached PNG");
+        state.metrics().record_cache(CacheResult::Hit(tier));
+        return Ok(([(header::CONTENT_TYPE, PNG_CONTENT_TYPE)], cache_headers, data).into_response());
+    }
+    state.metrics().record_cache(CacheResult::Miss);
+
+    // Rendering is CPU-bound, so it runs on the bounded render pool.
+    let renderer = state.renderer().clone();
+    let image = state
+        .pool()
+        .run(timeout, move |deadline| {
+            let options = RenderOptions { deadline: Some(*deadline), ..params.options };
+            Ok(renderer.render_png(&body, &options)?)
+        })
+        .await?;
+    state.metrics().record_render(&image);
+    let data = Bytes::from(image.data);
+    // If fonts were reloaded meanwhile, the image may have been rendered with
+    // the new ones, so it is only cached while the keyed fonts are current.
+    if state.renderer().font_fingerprint() == fonts {
+        state.cache().insert(key, data.clone()).await;
+    } else {
+        debug!(%key, "Fonts changed during the render, not caching the image");
+    }
+
+    // Note: Function exit logging is handled automatically by the `#[instrument]` macro.
+    Ok(([(header::CONTENT_TYPE, PNG_CONTENT_TYPE)], cache_headers, data).into_response())
+}
+
+// The `instrument` macro automatically adds logging for function entry/exit.
+#[instrument(skip(state, headers, body))]
+/// Converts an SVG image provided in the request body to PNG, JPEG, WebP or AVIF.
+///
+/// Accepts all `/svg-to-png` query parameters. The output format is taken from
+/// the `format` query parameter (`png`, `jpeg`, `webp`, `avif`) if present,
+/// otherwise negotiated from the `Accept` header, defaulting to PNG when the
+/// header is absent or only contains wildcards.
+///
+/// `quality` (1-100) controls lossy encoding. WebP is lossless unless a
+/// `quality` is given (`lossless=true` forces lossless). JPEG has no alpha
+/// channel, so it is always flattened onto `background` (or white).
+///
+/// Resolution metadata reflects the effective DPI: `pHYs` for PNG, JFIF density
+/// for JPEG and an EXIF chunk for WebP. AVIF carries no resolution metadata.
+///
+/// # Arguments
+///
+/// * `state` - The shared application state holding the pre-built font database.
+/// * `uri` - The request URI, used to extract the optional query parameters.
+/// * `headers` - The request headers, used for `Accept` negotiation and to detect form uploads.
+/// * `body` - The raw SVG, or a form upload of an SVG and a stylesheet.
+///
+/// # Returns
+///
+/// * `Ok(impl IntoResponse)` - The encoded image with a matching `Content-Type`.
+/// * `Err(AppError)` - As for `/svg-to-png`, plus `406 Not Acceptable`
+///   (`not_acceptable`) if no supported format satisfies the `Accept` header.
+async fn svg_to_image(
+    State(state): State<AppState>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Result<Bytes, BytesRejection>,
+) -> Result<impl IntoResponse, AppError> {
+    debug!(query = uri.query().unwrap_or(""), uri = %uri, "Processing svg_to_image request");
+    let body = read_body(body, state.limits())?;
+
+    let mut params = parse_render_params(uri.query()).inspect_err(|e| {
+        error!(error = %e, "Invalid query parameters");
+    })?;
+    let body = unpack_svg_form(&headers, body, &mut params.options).await?;
+    debug!(?params, "Parsed render parameters");
+
+    let accept = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok());
+    let format = match (params.format, accept) {
+        (Some(format), _) => format,
+        (None, None) => OutputFormat::default(),
+        (None, Some(accept)) => encode::negotiate(accept).ok_or_else(|| {
+            let err_msg = format!(
+                "None of the supported formats (image/png, image/jpeg, image/webp, image/avif) is acceptable: {}",
+                accept
+            );
+            error!(%err_msg);
+            AppError::NotAcceptable(err_msg)
+        })?,
+    };
+    debug!(?format, "Selected output format");
+
+    let timeout = parse_timeout(uri.query(), state.pool().timeout())?;
+    let renderer = state.renderer().clone();
+    let image = state
+        .pool()
+        .run(timeout, move |deadline| {
+            let options = RenderOptions { format, deadline: Some(*deadline), ..params.options };
+            Ok(renderer.render(&body, &options)?)
+        })
+        .await?;
+    state.metrics().record_render(&image);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type()),
+            (header::VARY, header::ACCEPT.as_str()),
+        ],
+        image.data,
+    ))
+}
+
+// The `instrument` macro automatically adds logging for function entry/exit.
+#[instrument(skip(state, headers, body))]
+/// Converts a batch of SVGs to PNG in a single request.
+///
+/// The body is either a ZIP archive of SVGs (any `Content-Type` other than
+/// multipart, typically `application/zip`) or a `multipart/form-data` body
+/// whose file fields are SVGs. All `/svg-to-png` query parameters apply to
+/// every file; `timeout_ms` applies to each file separately.
+///
+/// Files are converted one after another on the render pool, so a batch never
+/// occupies more than one worker. A failing SVG does not fail the batch. The
+/// whole batch is bounded by `SVG2PNG_BATCH_TIMEOUT_MS`: files not converted
+/// by then are reported as timed out instead of being rendered.
+///
+/// # Arguments
+///
+/// * `state` - The shared application state holding the renderer and render pool.
+/// * `uri` - The request URI, used to extract the optional query parameters.
+/// * `headers` - The request headers, used to detect multipart bodies.
+/// * `body` - The raw ZIP or multipart body.
+///
+/// # Returns
+///
+/// * `Ok(impl IntoResponse)` - A ZIP archive (`application/zip`) with one PNG per
+///   SVG at the same relative path and a `manifest.json` listing each file's
+///   status, dimensions or error code (see [`super::batch`]).
+/// * `Err(AppError)` - On failure, returns a problem details response. Possible errors include:
+///     - `400 Bad Request`: If the body is empty (`empty_body`), is not a valid ZIP or
+///       multipart body, contains no SVGs, has unsafe paths or two files with the same
+///       output path (`invalid_archive`), or a query parameter is invalid (`invalid_parameter`).
+///     - `413 Payload Too Large`: If the body exceeds the size limit (`payload_too_large`).
+///     - `422 Unprocessable Entity`: If the batch has too many files or its uncompressed
+///       size is too large (`limit_exceeded`).
+async fn batch_svg_to_png(
+    State(state): State<AppState>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Result<Bytes, BytesRejection>,
+) -> Result<impl IntoResponse, AppError> {
+    let body = read_body(body, state.limits())?;
+    debug!(body_len = body.len(), query = uri.query().unwrap_or(""), "Processing batch_svg_to_png request");
+
+    let params = parse_render_params(uri.query()).inspect_err(|e| {
+        error!(error = %e, "Invalid query parameters");
+    })?;
+    let timeout = parse_timeout(uri.query(), state.pool().timeout())?;
+
+    let limits = *state.limits();
+    let files = match headers.get(header::CONTENT_TYPE) {
+        Some(content_type) if form::is_multipart(content_type) => {
+            batch::read_multipart(content_type, body, &limits).await?
+        }
+        // Inflating the archive is CPU-bound, so keep it off the async workers.
+        _ => tokio::task::spawn_blocking(move || batch::read_zip(&body, &limits))
+            .await
+            .map_err(|e| AppError::Internal(format!("Archive extraction failed: {}", e)))??,
+    };
+    if files.is_empty() {
+        error!("Batch contains no SVG files");
+        return Err(AppError::InvalidArchive("The archive contains no SVG files".to_string()));
+    }
+    info!(files = files.len(), "Converting batch");
+
+    let batch_deadline = Deadline::after(state.pool().batch_timeout());
+    let mut results = Vec::with_capacity(files.len());
+    for file in files {
+        let remaining = batch_deadline.remaining();
+        let result = if remaining.is_zero() {
+            Err(batch_deadline.error().into())
+        } else {
+            let renderer = state.renderer().clone();
+            let data = file.data;
+            let options = params.options.clone();
+            state
+                .pool()
+                .run(timeout.min(remaining), move |deadline| {
+                    let options = RenderOptions { deadline: Some(*deadline), ..options };
+                    Ok(renderer.render_png(&data, &options)?)
+                })
+                .await
+        };
+        match &result {
+            Ok(image) => state.metrics().record_render(image),
+            Err(e) => warn!(path = file.path, error = %e, "Batch file failed"),
+        }
+        results.push((file.path, result));
+    }
+
+    let archive = tokio::task::spawn_blocking(move || batch::write_zip(results))
+        .await
+        .map_err(|e| AppError::Internal(format!("Archive creation failed: {}", e)))??;
+    Ok((
+        [
+            (header::CONTENT_TYPE, ZIP_CONTENT_TYPE),
+            (header::CONTENT_DISPOSITION, BATCH_CONTENT_DISPOSITION),
+        ],
+        archive,
+    ))
+}
+
+// The `instrument` macro automatically adds logging for function entry/exit.
+#[instrument(skip(state, headers, body))]
+/// Renders an SVG at several sizes and packages it as an icon.
+///
+/// The `format` path segment selects the package:
+///
+/// - `ico`: a multi-resolution favicon (`image/x-icon`). Sizes default to
+///   16, 24, 32, 48, 64, 128 and 256; at most 256.
+/// - `icns`: an Apple icon (`image/icns`). Sizes default to all of 16, 32, 64,
+///   128, 256, 512 and 1024, the only sizes it can hold.
+/// - `android`: a ZIP of `res/mipmap-*dpi/<name>.png` launcher icons plus the
+///   512x512 `<name>-playstore.png`. `name` defaults to `ic_launcher`.
+/// - `ios`: a ZIP of an `AppIcon.appiconset` with every iPhone, iPad and App
+///   Store size and its `Contents.json`. iOS icons are always flattened.
+///
+/// `sizes` (e.g. `16,32,48`) overrides the `ico` and `icns` sizes. The region,
+/// theming, `fit`, `align`, `background`, `flatten` and `timeout_ms` parameters
+/// and form uploads work as for `/svg-to-png`; the timeout covers the whole package.
+///
+/// # Arguments
+///
+/// * `state` - The shared application state holding the renderer and render pool.
+/// * `format` - The icon format from the path.
+/// * `uri` - The request URI, used to extract the optional query parameters.
+/// * `headers` - The request headers, used to detect form uploads.
+/// * `body` - The raw SVG, or a form upload of an SVG and a stylesheet.
+///
+/// # Returns
+///
+/// * `Ok(impl IntoResponse)` - The package with a matching `Content-Type` and a
+///   `Content-Disposition` attachment file name.
+/// * `Err(AppError)` - As for `/svg-to-png`, with `400 Bad Request`
+///   (`invalid_parameter`) for an unknown format, unsupported size or invalid name.
+async fn svg_to_icon(
+    State(state): State<AppState>,
+    Path(format): Path<String>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Result<Bytes, BytesRejection>,
+) -> Result<impl IntoResponse, AppError> {
+    debug!(%format, query = uri.query().unwrap_or(""), "Processing svg_to_icon request");
+    let format: IconFormat = format.parse().map_err(AppError::InvalidParameter)?;
+    let body = read_body(body, state.limits())?;
+
+    let mut options = parse_icon_options(format, uri.query()).inspect_err(|e| {
+        error!(error = %e, "Invalid query parameters");
+    })?;
+    let body = unpack_svg_form(&headers, body, &mut options.render).await?;
+    let timeout = parse_timeout(uri.query(), state.pool().timeout())?;
+    debug!(?options, ?timeout, "Parsed icon parameters");
+
+    let renderer = state.renderer().clone();
+    let (icon, elapsed) = state
+        .pool()
+        .run(timeout, move |deadline| {
+            let started = Instant::now();
+            let options = IconOptions {
+                render: RenderOptions { deadline: Some(*deadline), ..options.render },
+                ..options
+            };
+            let icon = renderer.render_icon(&body, &options)?;
+            Ok((icon, started.elapsed()))
+        })
+        .await?;
+    state.metrics().record_icon(elapsed, icon.len());
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", format.file_name())),
+        ],
+        icon,
+    ))
+}
+
+/// Unpacks a `multipart/form-data` upload of an SVG and a stylesheet (see
+/// [`super::form`]). Any other body is the SVG itself.
+///
+/// An uploaded stylesheet is appended to the one from the `css` query parameter.
+async fn unpack_svg_form(headers: &HeaderMap, body: Bytes, options: &mut RenderOptions) -> Result<Bytes, AppError> {
+    let Some(content_type) = headers.get(header::CONTENT_TYPE).filter(|v| form::is_multipart(v)) else {
+        return Ok(body);
+    };
+    let form = form::read_svg_form(content_type, body).await.inspect_err(|e| {
+        error!(error = %e, "Invalid form upload");
+    })?;
+    if let Some(css) = form.style_sheet {
+        options.style_sheet = Some(match options.style_sheet.take() {
+            Some(query) => format!("{}\n{}", query, css),
+            None => css,
+        });
+    }
+    Ok(form.svg)
+}
+
+/// Unwraps the request body, mapping an oversized body to `413 Payload Too Large`
+/// and an empty one to `400 Bad Request`.
+fn read_body(body: Result<Bytes, BytesRejection>, limits: &Limits) -> Result<Bytes, AppError> {
+    let body = body.map_err(|rejection| {
+        let err = AppError::from_body_rejection(rejection, limits.max_body_bytes);
+        error!(error = %err, "Failed to read request body");
+        err
+    })?;
+    if body.is_empty() {
+        error!("Received empty request body");
+        return Err(AppError::EmptyBody);
+    }
+    Ok(body)
+}
+
+// The `instrument` macro automatically adds logging for function entry/exit.
+#[instrument(skip(state))]
+/// Rescans the font directories and replaces the shared font database.
+///
+/// Allows fonts installed after startup to be picked up without restarting the
+/// service. In-flight requests keep using the database they started with. The
+/// render cache is cleared, since new fonts can change the output.
+///
+/// Only one reload runs at a time, and it runs on the render pool like any
+/// render, so repeated calls cannot add unbounded blocking work.
+///
+/// # Returns
+///
+/// * `Ok(impl IntoResponse)` - `200 OK` with the number of loaded font faces.
+/// * `Err(AppError)` - On failure, returns a problem details response. Possible errors include:
+///     - `503 Service Unavailable`: If another reload is in progress or the render pool is full (`overloaded`).
+///     - `504 Gateway Timeout`: If the reload takes longer than the render timeout (`render_timeout`).
+///       It still completes in the background.
+async fn reload_fonts(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let reloading = state.font_reload().clone().try_lock_owned().map_err(|_| {
+        warn!("Font reload already in progress, rejecting request");
+        AppError::Overloaded { retry_after_secs: FONT_RELOAD_RETRY_AFTER_SECS }
+    })?;
+    // Scanning font directories is blocking I/O, so keep it off the async workers.
+    let job_state = state.clone();
+    let faces = state
+        .pool()
+        .run(state.pool().timeout(), move |_| {
+            // Held until the scan finishes, even if the request times out first.
+            let _reloading = reloading;
+            let faces = job_state.renderer().reload_fonts();
+            job_state.cache().clear();
+            Ok(faces)
+        })
+        .await?;
+    Ok((StatusCode::OK, format!("Loaded {} font faces", faces)))
+}
+
+// The `instrument` macro automatically adds logging for function entry/exit.
+#[instrument]
+
+/// A simple health check endpoint.
+///
+/// Returns an HTTP `200 OK` status code if the service is running.
+///
+/// # Returns
+///
+/// * `StatusCode::OK` - Always returns a 200 OK status.
+async fn health_check() -> StatusCode {
+    StatusCode::OK
+}
+
+/// Reports whether the service can render (see [`super::readiness`]).
+///
+/// Unlike `/health`, this checks the font database, the temp directory, the
+/// render pool and a smoke render on every call, so it is suited to readiness
+/// probes.
+///
+/// # Returns
+///
+/// * `200 OK` if ready, otherwise `503 Service Unavailable`, with a JSON description of each check.
+async fn readiness_check(State(state): State<AppState>) -> impl IntoResponse {
+    let report = readiness::run(&state, &std::env::temp_dir()).await;
+    let status = if report.is_ready() {
+        StatusCode::OK
+    } else {
+        warn!(?report, "Service is not ready");
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
+/// Serves the Prometheus metrics (see [`super::metrics`]).
+///
+/// The font database size is read at scrape time, so it reflects font reloads.
+async fn export_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let font_faces = state.renderer().fontdb().len();
+    let text = state.metrics().render(font_faces, state.cache().usage());
+    ([(header::CONTENT_TYPE, metrics::CONTENT_TYPE)], text)
+}
+
+// The `instrument` macro automatically adds logging for function entry/exit.
+#[instrument(skip(state, body))]
+/// Makes the background of a PNG image transparent.
+///
+/// Takes a PNG image via POST request body. By default it samples the top-left
+/// pixel (0,0) and flood fills from there, making contiguous pixels of similar
+/// color (within a 5% fuzz factor) transparent. This matches the behavior of
+/// ImageMagick's `-fuzz 5% -fill none -draw "color 0,0 floodfill"`, implemented
+/// natively so no external tools are required.
+///
+/// Optional query parameters tune the removal:
+/// - `fuzz`: Color tolerance in percent (e.g. `10` or `10%`).
+/// - `seed`: Seed point `x,y`, or `corners` for all four corners. May be repeated.
+/// - `color`: A CSS color to key out instead of the color under each seed.
+/// - `mode`: `flood` (contiguous, default) or `global` (every matching pixel).
+/// - `feather`: Radius in pixels of edge refinement around the removed area.
+///   Pixels within it get partial alpha by un-mixing the background color, so
+///   anti-aliased edges composite cleanly onto other backgrounds.
+/// - `timeout_ms`: Lowers the processing deadline below the configured one.
+///
+/// # Arguments
+///
+/// * `state` - The shared application state holding the resource limits.
+/// * `uri` - The request URI, used to extract the optional query parameters.
+/// * `body` - The raw bytes of the input PNG image data.
+///
+/// # Returns
+///
+/// * `Ok(impl IntoResponse)` - On success, returns a response containing the modified PNG
+///   image data with a `Content-Type` header set to `image/png`.
+/// * `Err(AppError)` - On failure, returns a problem details response. Possible errors include:
+///     - `400 Bad Request`: If the request body is empty (`empty_body`), is not a valid
+///       PNG (`png_decode_error`), a query parameter is invalid, or a seed point lies
+///       outside the image (`invalid_parameter`).
+///     - `413 Payload Too Large`: If the body exceeds the size limit (`payload_too_large`).
+///     - `422 Unprocessable Entity`: If the image exceeds the pixel limits (`limit_exceeded`).
+///     - `500 Internal Server Error`: If encoding the resulting PNG fails (`encoder_error`).
+///     - `503 Service Unavailable`: If the render pool is saturated (`overloaded`).
+///     - `504 Gateway Timeout`: If processing does not finish before the deadline (`render_timeout`).
+async fn png_to_transparent(
+    State(state): State<AppState>,
+    uri: Uri,
+    body: Result<Bytes, BytesRejection>,
+) -> Result<impl IntoResponse, AppError> {
+    let body = read_body(body, state.limits())?;
+    debug!(body_len = body.len(), query = uri.query().unwrap_or(""), "Processing png_to_transparent request");
+
+    let options = parse_key_options(uri.query()).inspect_err(|e| {
+        error!(error = %e, "Invalid query parameters");
+    })?;
+    let timeout = parse_timeout(uri.query(), state.pool().timeout())?;
+    debug!(?options, ?timeout, "Parsed transparency options");
+
+    let renderer = state.renderer().clone();
+    let image = state
+        .pool()
+        .run(timeout, move |deadline| {
+            let options = KeyOptions { deadline: Some(*deadline), ..options };
+            Ok(renderer.make_transparent(&body, &options)?)
+        })
+        .await?;
+    state.metrics().record_transparency(&image);
+
+    // Return the PNG data.
+    Ok((
+        [(header::CONTENT_TYPE, PNG_CONTENT_TYPE)],
+        image.data,
+    ))
+}
+
+/// Builds the application router with all routes and the shared state.
+pub fn router(state: AppState) -> Router {
+    let body_limit = state.limits().max_body_bytes;
+    Router::new()
+        .route("/svg-to-png", post(svg_to_png))
+        .route("/svg-to-image", post(svg_to_image))
+        .route("/batch/svg-to-png", post(batch_svg_to_png))
+        .route("/svg-to-icon/{format}", post(svg_to_icon))
+        .route("/health", get(health_check))
+        .route("/ready", get(readiness_check))
+        .route("/metrics", get(export_metrics))
+        .route("/png-to-transparent", post(png_to_transparent))
+        .merge(
+            Router::new()
+                .route("/admin/fonts/reload", post(reload_fonts))
+                .route_layer(axum::middleware::from_fn_with_state(state.clone(), admin::require_token)),
+        )
+        .layer(DefaultBodyLimit::max(body_limit))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), metrics::track_requests))
+        .layer(axum::middleware::from_fn(telemetry::trace_request))
+        .layer(axum::middleware::from_fn(request_id::assign_request_id))
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*; // Import items from outer scope.
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt; // for `oneshot` and `ready`
+    use image::{ImageBuffer, Rgba};
+    use std::io::Cursor;
+    use std::time::Duration;
+    use svg2png::config::FontConfig;
+
+    use crate::server::cache::RenderCache;
+    use crate::server::config::{AdminConfig, PoolConfig};
+
+    // Shared state for tests, so the font database is only loaded once.
+    fn test_state() -> AppState {
+        static STATE: std::sync::OnceLock<AppState> = std::sync::OnceLock::new();
+        STATE.get_or_init(AppState::default).clone()
+    }
+
+    // Helper function to create the application router for testing.
+    fn app() -> Router {
+        router(test_state())
+    }
+
+    // Helper function to create a simple 2x2 red PNG.
+    fn create_test_png() -> Vec<u8> {
+        let mut img = ImageBuffer::<Rgba<u8>, Vec<u8>>::new(2, 2);
+        for pixel in img.pixels_mut() {
+            *pixel = Rgba([255, 0, 0, 255]); // Solid red
+        }
+        let mut buffer = Cursor::new(Vec::new());
+        img.write_to(&mut buffer, image::ImageFormat::Png).expect("Failed to write test PNG");
+        buffer.into_inner()
+    }
+
+    #[tokio::test]
+    async fn test_png_to_transparent_success() {
+        // Arrange: Create the app and a test PNG
+        // Arrange: Create the app with the actual route
+        let test_app = app();
+        let png_data = create_test_png();
+
+        // Act: Send a POST request to the (currently missing) endpoint
+        let request = Request::builder()
+            .method("POST")
+            .uri("/png-to-transparent")
+            .header(header::CONTENT_TYPE, PNG_CONTENT_TYPE)
+            .body(Body::from(png_data))
+            .unwrap();
+
+        // We expect this to fail initially (404 Not Found) because the route isn't added yet.
+        // Once the route and handler are added, we'll update the assertions.
+        let response = test_app.oneshot(request).await.unwrap();
+
+        // Assertions for success case:
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), PNG_CONTENT_TYPE);
+
+        let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        // Verify the output PNG
+        let img_result = image::load_from_memory_with_format(&body_bytes, image::ImageFormat::Png);
+        assert!(img_result.is_ok(), "Failed to decode response PNG: {:?}", img_result.err());
+        let img = img_result.unwrap().to_rgba8();
+
+        // Check the top-left pixel (0, 0) - it should now be transparent (alpha = 0)
+        // The flood fill starts from 0,0. Since our test image is solid red,
+        // the entire image should become transparent.
+        let top_left_pixel = img.get_pixel(0, 0);
+        assert_eq!(top_left_pixel[3], 0, "Top-left pixel alpha is not 0 (transparent)"); // Check alpha channel
+    }
+
+    #[tokio::test]
+    async fn test_png_to_transparent_empty_body() {
+        // Arrange
+        // Arrange: Create the app with the actual route
+        let test_app = app();
+
+        // Act: Send request with empty body
+        let request = Request::builder()
+            .method("POST")
+            .uri("/png-to-transparent")
+            .header(header::CONTENT_TYPE, PNG_CONTENT_TYPE)
+            .body(Body::empty())
+            .unwrap();
+
+        // Assertions for empty body case:
+        let response = test_app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_errors_are_problem_json_with_code_and_request_id() {
+        let response = post_svg("", "<not-svg").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), "application/problem+json");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["code"], "svg_parse_error");
+        assert_eq!(problem["status"], 400);
+        assert!(problem["detail"].as_str().unwrap().starts_with("Invalid SVG"));
+        assert!(!problem["request_id"].as_str().unwrap().is_empty());
+
+        let response = post_svg("?width=abc", TEST_SVG).await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["code"], "invalid_parameter");
+    }
+
+    #[tokio::test]
+    async fn test_request_id_is_accepted_and_echoed() {
+        // A caller-supplied ID is echoed in the header and the error body.
+        let request = Request::builder()
+            .method("POST")
+            .uri("/svg-to-png")
+            .header("x-request-id", "gateway-42")
+            .body(Body::from("<not-svg"))
+            .unwrap();
+        let response = app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()["x-request-id"], "gateway-42");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["request_id"], "gateway-42");
+
+        // Without one (or with an unusable one), a fresh ID is generated.
+        let request = Request::builder()
+            .method("POST")
+            .uri("/svg-to-png")
+            .header("x-request-id", "not a valid id")
+            .body(Body::from(TEST_SVG))
+            .unwrap();
+        let response = app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let id = response.headers()["x-request-id"].to_str().unwrap();
+        assert!(uuid::Uuid::parse_str(id).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_render_panic_is_isolated() {
+        let svg = include_str!("../../tests/fixtures/render_panic.svg");
+        let response = post_svg("", svg).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["code"], "render_panic");
+
+        // The service keeps working after the panic.
+        assert_eq!(post_svg("", TEST_SVG).await.status(), StatusCode::OK);
+    }
+
+    // A 100x50 solid blue rectangle used by the sizing tests.
+    const TEST_SVG: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="50"><rect width="100" height="50" fill="blue"/></svg>"#;
+
+    // Helper to POST an SVG to `/svg-to-png` with the given query string.
+    async fn post_svg(query: &str, svg: &str) -> axum::response::Response {
+        post_svg_to(&format!("/svg-to-png{}", query), svg).await
+    }
+
+    async fn post_svg_to(uri: &str, svg: &str) -> axum::response::Response {
+        let request = Request::builder()
+            .method("POST")
+            .uri(uri)
+            .body(Body::from(svg.to_string()))
+            .unwrap();
+        app().oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_svg_to_png_width_height_contain() {
+        let response = post_svg("?width=200&height=200&fit=contain&align=top", TEST_SVG).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let img = image::load_from_memory_with_format(&body_bytes, image::ImageFormat::Png)
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(img.dimensions(), (200, 200));
+        // Content is scaled 2x to 200x100 and aligned to the top; the bottom half is letterbox.
+        assert_eq!(img.get_pixel(100, 50)[3], 255);
+        assert_eq!(img.get_pixel(100, 150)[3], 0);
+    }
+
+    #[tokio::test]
+    async fn test_svg_to_png_single_dimension_keeps_aspect() {
+        let response = post_svg("?width=512", TEST_SVG).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let img = image::load_from_memory_with_format(&body_bytes, image::ImageFormat::Png).unwrap();
+        assert_eq!((img.width(), img.height()), (512, 256));
+    }
+
+    #[tokio::test]
+    async fn test_svg_to_png_invalid_fit() {
+        let response = post_svg("?width=10&height=10&fit=stretch", TEST_SVG).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_svg_to_png_background_fill() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"><rect width="5" height="10" fill="blue"/></svg>"#;
+        let response = post_svg("?background=%23ff0000", svg).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let img = image::load_from_memory_with_format(&body_bytes, image::ImageFormat::Png).unwrap();
+        assert_eq!(img.color(), image::ColorType::Rgba8);
+        let img = img.to_rgba8();
+        assert_eq!(img.get_pixel(2, 5), &Rgba([0, 0, 255, 255]));
+        assert_eq!(img.get_pixel(8, 5), &Rgba([255, 0, 0, 255]));
+    }
+
+    #[tokio::test]
+    async fn test_svg_to_png_flatten_emits_rgb() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"><rect width="5" height="10" fill="blue"/></svg>"#;
+        let response = post_svg("?flatten=true", svg).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let img = image::load_from_memory_with_format(&body_bytes, image::ImageFormat::Png).unwrap();
+        assert_eq!(img.color(), image::ColorType::Rgb8);
+        let img = img.to_rgb8();
+        assert_eq!(img.get_pixel(8, 5), &image::Rgb([255, 255, 255]));
+    }
+
+    #[tokio::test]
+    async fn test_svg_to_png_invalid_background() {
+        let response = post_svg("?background=notacolor", TEST_SVG).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_svg_to_png_rejects_format() {
+        let response = post_svg("?format=jpeg", TEST_SVG).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["code"], "invalid_parameter");
+        assert!(problem["detail"].as_str().unwrap().contains("/svg-to-image"));
+    }
+
+    // Helper to POST an SVG to `/svg-to-image` with the given query string and Accept header.
+    async fn post_svg_image(query: &str, accept: Option<&str>) -> axum::response::Response {
+        let mut builder = Request::builder()
+            .method("POST")
+            .uri(format!("/svg-to-image{}", query));
+        if let Some(accept) = accept {
+            builder = builder.header(header::ACCEPT, accept);
+        }
+        let request = builder.body(Body::from(TEST_SVG)).unwrap();
+        app().oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_svg_to_image_accept_negotiation() {
+        let response = post_svg_image("", Some("image/avif;q=0.5, image/webp")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), "image/webp");
+
+        let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let img = image::load_from_memory_with_format(&body_bytes, image::ImageFormat::WebP).unwrap();
+        assert_eq!((img.width(), img.height()), (100, 50));
+    }
+
+    #[tokio::test]
+    async fn test_svg_to_image_jpeg_density() {
+        let response = post_svg_image("?format=jpeg&dpi=192&quality=90", Some("image/png")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), "image/jpeg");
+
+        let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        // JFIF APP0 segment: density units (1 = DPI) followed by X/Y density.
+        assert_eq!(&body_bytes[6..11], b"JFIF\0");
+        assert_eq!(body_bytes[13], 1);
+        assert_eq!(u16::from_be_bytes([body_bytes[14], body_bytes[15]]), 192);
+        let img = image::load_from_memory_with_format(&body_bytes, image::ImageFormat::Jpeg).unwrap();
+        assert_eq!((img.width(), img.height()), (200, 100));
+    }
+
+    #[tokio::test]
+    async fn test_svg_to_image_not_acceptable() {
+        let response = post_svg_image("", Some("text/html")).await;
+        assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+    }
+
+    #[tokio::test]
+    async fn test_reload_fonts() {
+        let admin = AdminConfig { token: Some("s3cret".to_string()) };
+        let state = AppState::new(
+            FontConfig::default(),
+            Limits::default(),
+            PoolConfig::default(),
+            RenderCache::default(),
+            admin,
+        );
+        let before = state.renderer().options();
+        let reload = |state: &AppState, authorization: Option<&str>| {
+            let mut builder = Request::builder().method("POST").uri("/admin/fonts/reload");
+            if let Some(authorization) = authorization {
+                builder = builder.header(header::AUTHORIZATION, authorization);
+            }
+            router(state.clone()).oneshot(builder.body(Body::empty()).unwrap())
+        };
+
+        let response = reload(&state, Some("Bearer s3cret")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // A fresh database was swapped in; the old snapshot is still usable.
+        assert!(!std::sync::Arc::ptr_eq(&before, &state.renderer().options()));
+        assert_eq!(before.fontdb.len(), state.renderer().fontdb().len());
+
+        // Reloads require the token, and are disabled without one.
+        for (state, authorization) in [(&state, None), (&state, Some("Bearer guess")), (&AppState::default(), None)] {
+            let response = reload(state, authorization).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(problem["code"], "unauthorized");
+        }
+
+        // Reloads do not overlap.
+        let reloading = state.font_reload().clone().try_lock_owned().unwrap();
+        let response = reload(&state, Some("Bearer s3cret")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        drop(reloading);
+        assert_eq!(reload(&state, Some("Bearer s3cret")).await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_limits_rejected_before_allocation() {
+        let limits = Limits {
+            max_body_bytes: 8192,
+            max_width: 500,
+            max_height: 500,
+            max_pixels: 100_000,
+            max_dpi: 600.0,
+            ..Limits::default()
+        };
+        let state = AppState::new(
+            FontConfig { load_system_fonts: false, ..FontConfig::default() },
+            limits,
+            PoolConfig::default(),
+            RenderCache::default(),
+            AdminConfig::default(),
+        );
+        let post = |uri: &str, body: Vec<u8>| {
+            let request = Request::builder().method("POST").uri(uri).body(Body::from(body)).unwrap();
+            router(state.clone()).oneshot(request)
+        };
+        let code = |response: axum::response::Response| async move {
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            (status, problem["code"].as_str().unwrap().to_string())
+        };
+
+        let huge = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100000" height="10"/>"#;
+        let oversized = vec![b' '; 16_384];
+        let cases = [
+            ("/svg-to-png?dpi=9600", TEST_SVG.as_bytes().to_vec(), StatusCode::UNPROCESSABLE_ENTITY, "limit_exceeded"),
+            ("/svg-to-png", huge.as_bytes().to_vec(), StatusCode::UNPROCESSABLE_ENTITY, "limit_exceeded"),
+            ("/svg-to-image?width=400&height=400", TEST_SVG.as_bytes().to_vec(), StatusCode::UNPROCESSABLE_ENTITY, "limit_exceeded"),
+            ("/svg-to-png", oversized.clone(), StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large"),
+            ("/png-to-transparent", oversized, StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large"),
+        ];
+        for (uri, body, status, expected) in cases {
+            let response = post(uri, body).await.unwrap();
+            assert_eq!(code(response).await, (status, expected.to_string()), "uri: {}", uri);
+        }
+
+        // A small, highly compressed PNG whose header exceeds the pixel limit.
+        let mut png_data = Vec::new();
+        image::RgbaImage::new(400, 400)
+            .write_to(&mut Cursor::new(&mut png_data), image::ImageFormat::Png)
+            .unwrap();
+        assert!(png_data.len() < limits.max_body_bytes);
+        let response = post("/png-to-transparent", png_data).await.unwrap();
+        assert_eq!(code(response).await, (StatusCode::UNPROCESSABLE_ENTITY, "limit_exceeded".to_string()));
+
+        let response = post("/svg-to-png?dpi=300", TEST_SVG.as_bytes().to_vec()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_png_to_transparent_invalid_png() {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/png-to-transparent")
+            .header(header::CONTENT_TYPE, PNG_CONTENT_TYPE)
+            .body(Body::from("not a png"))
+            .unwrap();
+
+        let response = app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_png_to_transparent_corners_and_color() {
+        // 4x4 white image with a red pixel in the bottom-right corner.
+        let mut img = ImageBuffer::<Rgba<u8>, Vec<u8>>::from_pixel(4, 4, Rgba([255, 255, 255, 255]));
+        img.put_pixel(3, 3, Rgba([255, 0, 0, 255]));
+        let mut buffer = Cursor::new(Vec::new());
+        img.write_to(&mut buffer, image::ImageFormat::Png).unwrap();
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/png-to-transparent?seed=corners&color=red&fuzz=1%25")
+            .body(Body::from(buffer.into_inner()))
+            .unwrap();
+        let response = app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let img = image::load_from_memory_with_format(&body_bytes, image::ImageFormat::Png).unwrap().to_rgba8();
+        // Only the red corner matches the key color; the white corners are kept.
+        assert_eq!(img.get_pixel(3, 3)[3], 0);
+        assert_eq!(img.get_pixel(0, 0)[3], 255);
+    }
+
+    #[tokio::test]
+    async fn test_png_to_transparent_invalid_params() {
+        for query in ["fuzz=150", "seed=1", "mode=magic", "seed=9,9", "feather=-1", "timeout_ms=0"] {
+            let request = Request::builder()
+                .method("POST")
+                .uri(format!("/png-to-transparent?{}", query))
+                .body(Body::from(create_test_png()))
+                .unwrap();
+            let response = app().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "query: {}", query);
+        }
+    }
+
+    // TODO: Add more tests for:
+    // - Cases where the top-left pixel is already transparent?
+
+    fn read_zip_response(body: &[u8]) -> zip::ZipArchive<Cursor<Vec<u8>>> {
+        zip::ZipArchive::new(Cursor::new(body.to_vec())).expect("Response is not a ZIP archive")
+    }
+
+    fn zip_entry(archive: &mut zip::ZipArchive<Cursor<Vec<u8>>>, name: &str) -> Vec<u8> {
+        use std::io::Read;
+        let mut data = Vec::new();
+        archive.by_name(name).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn test_batch_zip_preserves_paths_and_reports_errors() {
+        use std::io::Write;
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in [
+            ("icons/a.svg", TEST_SVG),
+            ("icons/sub/b.svg", TEST_SVG),
+            ("broken.svg", "<not-svg"),
+            ("README.md", "ignored"),
+        ] {
+            writer.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+            writer.write_all(data.as_bytes()).unwrap();
+        }
+        let archive = writer.finish().unwrap().into_inner();
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/batch/svg-to-png?dpi=192")
+            .header(header::CONTENT_TYPE, ZIP_CONTENT_TYPE)
+            .body(Body::from(archive))
+            .unwrap();
+        let response = app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), ZIP_CONTENT_TYPE);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let mut archive = read_zip_response(&body);
+        let mut names: Vec<_> = archive.file_names().map(str::to_string).collect();
+        names.sort();
+        assert_eq!(names, ["icons/a.png", "icons/sub/b.png", "manifest.json"]);
+
+        let png = zip_entry(&mut archive, "icons/sub/b.png");
+        let img = image::load_from_memory_with_format(&png, image::ImageFormat::Png).unwrap();
+        assert_eq!((img.width(), img.height()), (200, 100));
+
+        let manifest: serde_json::Value = serde_json::from_slice(&zip_entry(&mut archive, "manifest.json")).unwrap();
+        assert_eq!((manifest["succeeded"].as_u64(), manifest["failed"].as_u64()), (Some(2), Some(1)));
+        let files = manifest["files"].as_array().unwrap();
+        assert_eq!(files[0]["path"], "icons/a.svg");
+        assert_eq!(files[0]["output"], "icons/a.png");
+        assert_eq!((files[0]["width"].as_u64(), files[0]["height"].as_u64()), (Some(200), Some(100)));
+        assert_eq!(files[2]["path"], "broken.svg");
+        assert_eq!(files[2]["status"], "error");
+        assert_eq!(files[2]["error"]["code"], "svg_parse_error");
+    }
+
+    #[tokio::test]
+    async fn test_batch_multipart_and_invalid_archives() {
+        let boundary = "svg2png-test-boundary";
+        let mut body = String::new();
+        for name in ["logo.svg", "nested/icon.svg"] {
+            body.push_str(&format!(
+                "--{boundary}\r\nContent-Disposition: form-data; name=\"files\"; filename=\"{name}\"\r\n\
+                 Content-Type: image/svg+xml\r\n\r\n{TEST_SVG}\r\n"
+            ));
+        }
+        body.push_str(&format!("--{boundary}--\r\n"));
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/batch/svg-to-png")
+            .header(header::CONTENT_TYPE, format!("multipart/form-data; boundary={}", boundary))
+            .body(Body::from(body))
+            .unwrap();
+        let response = app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let mut archive = read_zip_response(&body);
+        assert_eq!(zip_entry(&mut archive, "nested/icon.png")[..4], *b"\x89PNG");
+        assert!(archive.by_name("logo.png").is_ok());
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/batch/svg-to-png")
+            .header(header::CONTENT_TYPE, ZIP_CONTENT_TYPE)
+            .body(Body::from("not a zip"))
+            .unwrap();
+        let response = app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["code"], "invalid_archive");
+    }
+
+    #[tokio::test]
+    async fn test_batch_stops_converting_at_batch_deadline() {
+        use std::io::Write;
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for name in ["a.svg", "b.svg"] {
+            writer.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+            writer.write_all(TEST_SVG.as_bytes()).unwrap();
+        }
+        let archive = writer.finish().unwrap().into_inner();
+        let pool_config = PoolConfig { batch_timeout: Duration::from_nanos(1), ..PoolConfig::default() };
+        let state = AppState::new(
+            FontConfig::default(),
+            Limits::default(),
+            pool_config,
+            RenderCache::default(),
+            AdminConfig::default(),
+        );
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/batch/svg-to-png")
+            .header(header::CONTENT_TYPE, ZIP_CONTENT_TYPE)
+            .body(Body::from(archive))
+            .unwrap();
+        let response = router(state).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let mut archive = read_zip_response(&body);
+        let manifest: serde_json::Value = serde_json::from_slice(&zip_entry(&mut archive, "manifest.json")).unwrap();
+        assert_eq!(manifest["failed"], 2);
+        for file in manifest["files"].as_array().unwrap() {
+            assert_eq!(file["error"]["code"], "render_timeout");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_svg_to_icon_ico_and_android() {
+        let response = post_svg_to("/svg-to-icon/ico?sizes=16,32,256&align=top-left", TEST_SVG).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/x-icon");
+        assert_eq!(response.headers()[header::CONTENT_DISPOSITION], "attachment; filename=\"favicon.ico\"");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body[..6], [0, 0, 1, 0, 3, 0]);
+        // The last entry is 256x256 (stored as 0) and points at a PNG.
+        assert_eq!((body[38], body[39]), (0, 0));
+        let offset = u32::from_le_bytes(body[50..54].try_into().unwrap()) as usize;
+        let image = image::load_from_memory(&body[offset..]).unwrap();
+        assert_eq!((image.width(), image.height()), (256, 256));
+
+        let response = post_svg_to("/svg-to-icon/android?name=ic_brand", TEST_SVG).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let mut archive = read_zip_response(&body);
+        let icon = image::load_from_memory(&zip_entry(&mut archive, "res/mipmap-xxhdpi/ic_brand.png")).unwrap();
+        assert_eq!((icon.width(), icon.height()), (144, 144));
+        assert!(archive.by_name("ic_brand-playstore.png").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_svg_to_icon_invalid_parameters() {
+        for uri in [
+            "/svg-to-icon/bmp",
+            "/svg-to-icon/ico?sizes=16,512",
+            "/svg-to-icon/icns?sizes=48",
+            "/svg-to-icon/ios?sizes=16",
+            "/svg-to-icon/ico?width=32",
+        ] {
+            let response = post_svg_to(uri, TEST_SVG).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(problem["code"], "invalid_parameter", "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_svg_to_png_renders_element_by_id() {
+        let sprite = r##"<svg xmlns="http://www.w3.org/2000/svg" width="200" height="100">
+            <rect width="200" height="100" fill="#00ff00"/>
+            <g transform="translate(100,0)"><rect id="red" x="10" y="20" width="30" height="40" fill="#ff0000"/></g>
+            <g id="blue" transform="translate(150,50) scale(2)"><rect width="10" height="10" fill="#0000ff"/></g>
+        </svg>"##;
+        let render = |query: &'static str| async move {
+            let response = post_svg(query, sprite).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", query);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            image::load_from_memory(&body).unwrap().to_rgba8()
+        };
+
+        // Only the element is drawn, without the background, under its ancestors' transforms.
+        let red = render("?id=red").await;
+        assert_eq!(red.dimensions(), (30, 40));
+        assert!(red.pixels().all(|p| p.0 == [255, 0, 0, 255]));
+        let blue = render("?id=blue&padding=5").await;
+        assert_eq!(blue.dimensions(), (30, 30));
+        assert_eq!(blue.get_pixel(0, 0).0[3], 0);
+        assert_eq!(blue.get_pixel(15, 15).0, [0, 0, 255, 255]);
+        assert_eq!(render("?id=red&width=60").await.dimensions(), (60, 80));
+
+        let response = post_svg("?id=missing", sprite).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["code"], "element_not_found");
+        assert_eq!(post_svg("?id=red&padding=-1", sprite).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_svg_to_png_viewbox_trim_and_padding() {
+        // The viewBox doubles every user unit on the canvas.
+        let diagram = r##"<svg xmlns="http://www.w3.org/2000/svg" width="200" height="100" viewBox="0 0 100 50">
+            <rect x="10" y="10" width="20" height="10" fill="#ff0000"/>
+        </svg>"##;
+        let render = |query: &'static str| async move {
+            let response = post_svg(query, diagram).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", query);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            image::load_from_memory(&body).unwrap().to_rgba8()
+        };
+
+        let region = render("?viewbox=10,10,20,10").await;
+        assert_eq!(region.dimensions(), (40, 20));
+        assert!(region.pixels().all(|p| p.0 == [255, 0, 0, 255]));
+        assert_eq!(render("?trim=true").await, region);
+
+        let padded = render("?trim=true&padding=0,5").await;
+        assert_eq!(padded.dimensions(), (50, 20));
+        assert_eq!(padded.get_pixel(2, 10).0[3], 0);
+        assert_eq!(padded.get_pixel(25, 10).0, [255, 0, 0, 255]);
+        assert_eq!(render("?padding=1,2,3,4").await.dimensions(), (206, 104));
+
+        for query in ["?trim=true&viewbox=0,0,10,10", "?viewbox=0,0,0,10", "?padding=1,2,3,4,5"] {
+            assert_eq!(post_svg(query, diagram).await.status(), StatusCode::BAD_REQUEST, "{}", query);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_svg_to_png_theming_with_css_and_color() {
+        let icon = r##"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10">
+            <rect class="accent" width="10" height="10" fill="#000000"/>
+            <rect x="10" width="10" height="10" fill="currentColor"/>
+        </svg>"##;
+        let pixels = |body: &[u8]| {
+            let image = image::load_from_memory(body).unwrap().to_rgba8();
+            (image.get_pixel(5, 5).0, image.get_pixel(15, 5).0)
+        };
+
+        let response = post_svg("?css=.accent%7Bfill%3A%23ff0000%7D&color=%230000ff", icon).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(pixels(&body), ([255, 0, 0, 255], [0, 0, 255, 255]));
+
+        // The same stylesheet as a form field next to the SVG.
+        let boundary = "svg2png-test-boundary";
+        let form = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"svg\"; filename=\"icon.svg\"\r\n\r\n{icon}\r\n\
+             --{boundary}\r\nContent-Disposition: form-data; name=\"css\"\r\n\r\n.accent {{ fill: lime }} svg {{ color: white }}\r\n\
+             --{boundary}--\r\n"
+        );
+        let request = Request::builder()
+            .method("POST")
+            .uri("/svg-to-png")
+            .header(header::CONTENT_TYPE, format!("multipart/form-data; boundary={}", boundary))
+            .body(Body::from(form))
+            .unwrap();
+        let response = app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(pixels(&body), ([0, 255, 0, 255], [255, 255, 255, 255]));
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/svg-to-png")
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=missing")
+            .body(Body::from("not multipart"))
+            .unwrap();
+        let response = app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["code"], "invalid_form");
+    }
+
+    #[tokio::test]
+    async fn test_metrics_count_requests_and_renders() {
+        let state = AppState::default();
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10"/>"#;
+        for query in ["", "?fit=bogus"] {
+            let request = Request::builder()
+                .method("POST")
+                .uri(format!("/svg-to-png{}", query))
+                .body(Body::from(svg))
+                .unwrap();
+            router(state.clone()).oneshot(request).await.unwrap();
+        }
+        for format in ["bogus", "ico"] {
+            let uri = format!("/svg-to-icon/{}?sizes=16", format);
+            let request = Request::builder().method("POST").uri(uri).body(Body::from(svg)).unwrap();
+            router(state.clone()).oneshot(request).await.unwrap();
+        }
+        let request = Request::builder().uri("/nope").body(Body::empty()).unwrap();
+        router(state.clone()).oneshot(request).await.unwrap();
+
+        let request = Request::builder().uri("/metrics").body(Body::empty()).unwrap();
+        let response = router(state.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], metrics::CONTENT_TYPE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(text.contains("svg2png_http_requests_total{route=\"/svg-to-png\",status=\"200\"} 1\n"));
+        assert!(text.contains("svg2png_http_requests_total{route=\"/svg-to-png\",status=\"400\"} 1\n"));
+        // Path parameters are labeled by their template, not their value.
+        assert!(text.contains("svg2png_http_requests_total{route=\"/svg-to-icon/{format}\",status=\"400\"} 1\n"));
+        assert!(text.contains("svg2png_http_requests_total{route=\"unmatched\",status=\"404\"} 1\n"));
+        assert!(text.contains("svg2png_http_requests_total{route=\"/svg-to-icon/{format}\",status=\"200\"} 1\n"));
+        // Icons have their own histogram and leave the phase histograms to single images.
+        assert!(text.contains("svg2png_render_phase_seconds_count{phase=\"parse\"} 1\n"));
+        assert!(text.contains("svg2png_icon_seconds_count 1\n"));
+        assert!(text.contains("svg2png_output_bytes_count 2\n"));
+        assert!(text.contains("svg2png_output_pixels_sum 200\n"));
+        assert!(text.contains(&format!("svg2png_font_faces {}\n", state.renderer().fontdb().len())));
+    }
+
+    #[tokio::test]
+    async fn test_svg_to_png_cache_and_etag() {
+        let state = AppState::default();
+        let request = |if_none_match: Option<&str>| {
+            let mut builder = Request::builder().method("POST").uri("/svg-to-png?dpi=192");
+            if let Some(etag) = if_none_match {
+                builder = builder.header(header::IF_NONE_MATCH, etag);
+            }
+            builder.body(Body::from(TEST_SVG)).unwrap()
+        };
+
+        let first = router(state.clone()).oneshot(request(None)).await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        let etag = first.headers()[header::ETAG].to_str().unwrap().to_string();
+        assert!(etag.starts_with('"') && etag.len() == 66);
+        assert_eq!(first.headers()[header::CACHE_CONTROL], "public, max-age=86400");
+        let first_body = axum::body::to_bytes(first.into_body(), usize::MAX).await.unwrap();
+
+        // The same request is served from memory with the same ETag.
+        let second = router(state.clone()).oneshot(request(None)).await.unwrap();
+        assert_eq!(second.headers()[header::ETAG], etag.as_str());
+        assert_eq!(axum::body::to_bytes(second.into_body(), usize::MAX).await.unwrap(), first_body);
+
+        let not_modified = router(state.clone()).oneshot(request(Some(&etag))).await.unwrap();
+        assert_eq!(not_modified.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(not_modified.headers()[header::ETAG], etag.as_str());
+        assert!(axum::body::to_bytes(not_modified.into_body(), usize::MAX).await.unwrap().is_empty());
+
+        // Different options are a different entity.
+        let other = post_svg("?dpi=96", TEST_SVG).await;
+        assert_ne!(other.headers()[header::ETAG], etag.as_str());
+
+        let text = state.metrics().render(0, state.cache().usage());
+        assert!(text.contains("svg2png_cache_lookups_total{result=\"miss\"} 1\n"));
+        assert!(text.contains("svg2png_cache_lookups_total{result=\"memory_hit\"} 1\n"));
+        assert!(text.contains("svg2png_cache_lookups_total{result=\"not_modified\"} 1\n"));
+        assert!(text.contains(&format!("svg2png_cache_bytes{{tier=\"memory\"}} {}\n", first_body.len())));
+    }
+
+    #[tokio::test]
+    async fn test_if_none_match_wildcard_does_not_skip_render() {
+        for (svg, status) in [("<not-svg", StatusCode::BAD_REQUEST), (TEST_SVG, StatusCode::OK)] {
+            let request = Request::builder()
+                .method("POST")
+                .uri("/svg-to-png")
+                .header(header::IF_NONE_MATCH, "*")
+                .body(Body::from(svg))
+                .unwrap();
+            let response = router(AppState::default()).oneshot(request).await.unwrap();
+            assert_eq!(response.status(), status);
+            assert!(!axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ready_reports_each_check() {
+        let request = Request::builder().uri("/ready").body(Body::empty()).unwrap();
+        let response = app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report["status"], "ready");
+        assert!(report["checks"]["fonts"]["font_faces"].as_u64().unwrap() > 0);
+        assert_eq!(report["checks"]["convert"]["status"], "skip");
+        assert_eq!(report["checks"]["temp_dir"]["status"], "pass");
+        assert_eq!(report["checks"]["render_pool"]["status"], "pass");
+        assert_eq!(report["checks"]["smoke_render"]["status"], "pass");
+
+        // Without fonts the service is alive but not ready.
+        let font_config = FontConfig { load_system_fonts: false, ..FontConfig::default() };
+        let state = AppState::new(
+            font_config,
+            Limits::default(),
+            PoolConfig::default(),
+            RenderCache::default(),
+            AdminConfig::default(),
+        );
+        let request = Request::builder().uri("/ready").body(Body::empty()).unwrap();
+        let response = router(state.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report["status"], "not_ready");
+        assert_eq!(report["checks"]["fonts"]["status"], "fail");
+        let request = Request::builder().uri("/health").body(Body::empty()).unwrap();
+        assert_eq!(router(state).oneshot(request).await.unwrap().status(), StatusCode::OK);
+    }
+}
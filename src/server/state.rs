@@ -0,0 +1,109 @@
+//! # Shared Application State
+//!
+//! Holds the [`Renderer`] (and with it the font database), the render pool, the
+//! render cache, the metrics registry and the admin settings, all shared across
+//! requests.
+
+use std::sync::Arc;
+
+use svg2png::config::{FontConfig, Limits};
+use svg2png::Renderer;
+
+use super::cache::RenderCache;
+use super::config::{AdminConfig, PoolConfig};
+use super::metrics::Metrics;
+use super::pool::RenderPool;
+
+/// State shared by all handlers via Axum's `State` extractor.
+///
+/// Cloning is cheap: all fields are reference counted.
+#[derive(Clone)]
+pub struct AppState {
+    /// The renderer, including the font database and resource limits.
+    renderer: Arc<Renderer>,
+    /// The bounded pool that renders and encodes images.
+    pool: RenderPool,
+    /// Recently rendered PNGs, keyed by their input.
+    cache: Arc<RenderCache>,
+    /// Counters and histograms served by `GET /metrics`.
+    metrics: Arc<Metrics>,
+    /// Access to the `/admin/*` endpoints.
+    admin: Arc<AdminConfig>,
+    /// Held while the font database is being reloaded, so reloads never overlap.
+    font_reload: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl AppState {
+    /// Creates the application state, loading the font database.
+    ///
+    /// # Arguments
+    ///
+    /// * `font_config` - Which font directories to load and the fallback families.
+    /// * `limits` - Resource limits enforced on every request.
+    /// * `pool_config` - Sizing of the render pool.
+    /// * `cache` - The render cache (see [`RenderCache::open`]).
+    /// * `admin` - Access to the admin endpoints.
+    pub fn new(
+        font_config: FontConfig,
+        limits: Limits,
+        pool_config: PoolConfig,
+        cache: RenderCache,
+        admin: AdminConfig,
+    ) -> Self {
+        AppState {
+            renderer: Arc::new(Renderer::new(font_config, limits)),
+            pool: RenderPool::new(pool_config),
+            cache: Arc::new(cache),
+            metrics: Arc::new(Metrics::new()),
+            admin: Arc::new(admin),
+            font_reload: Arc::new(tokio::sync::Mutex::new(())),
+        }
+    }
+
+    /// Returns the shared renderer.
+    pub fn renderer(&self) -> &Arc<Renderer> {
+        &self.renderer
+    }
+
+    /// Returns the resource limits.
+    pub fn limits(&self) -> &Limits {
+        self.renderer.limits()
+    }
+
+    /// Returns the render pool.
+    pub fn pool(&self) -> &RenderPool {
+        &self.pool
+    }
+
+    /// Returns the render cache.
+    pub fn cache(&self) -> &RenderCache {
+        &self.cache
+    }
+
+    /// Returns the metrics registry.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Returns the admin settings.
+    pub fn admin(&self) -> &AdminConfig {
+        &self.admin
+    }
+
+    /// Returns the lock serializing font reloads.
+    pub fn font_reload(&self) -> &Arc<tokio::sync::Mutex<()>> {
+        &self.font_reload
+    }
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new(
+            FontConfig::default(),
+            Limits::default(),
+            PoolConfig::default(),
+            RenderCache::default(),
+            AdminConfig::default(),
+        )
+    }
+}
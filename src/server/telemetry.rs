@@ -0,0 +1,370 @@
+//! # Tracing
+//!
+//! Every request runs in a `request` span carrying its request ID (see
+//! [`request_id`]). The handler's span and the library's per-phase spans
+//! (`parse`, `render`, `encode`; see [`svg2png::renderer`]) nest below it,
+//! including on render pool threads, so every log line of a request can be
+//! traced back to it.
+//!
+//! Logs are human-readable text by default. Setting `SVG2PNG_LOG_FORMAT=json`
+//! switches to one JSON object per line, with the fields of all enclosing spans
+//! (see [`fmt_layer`]).
+//!
+//! Built with the `otel` feature, the spans can also be exported to an
+//! OpenTelemetry collector over OTLP/HTTP (protobuf). Export is enabled by
+//! setting the standard `OTEL_EXPORTER_OTLP_ENDPOINT` (the base URL, to which
+//! `/v1/traces` is appended) or `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` (the full
+//! URL) environment variable. The other `OTEL_EXPORTER_OTLP_*` variables and
+//! `OTEL_SERVICE_NAME` are honored as well.
+//!
+//! An incoming W3C `traceparent` (and `tracestate`) header makes the `request`
+//! span a child of the caller's span, so traces started by a gateway continue
+//! into the render.
+
+use std::str::FromStr;
+
+use anyhow::Context;
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use tracing::{info_span, Instrument, Subscriber};
+use tracing_subscriber::{fmt, fmt::writer::BoxMakeWriter, registry::LookupSpan, Layer};
+
+use super::request_id;
+
+/// Environment variable selecting the log format.
+const LOG_FORMAT_ENV_VAR: &str = "SVG2PNG_LOG_FORMAT";
+
+/// A type-erased tracing layer.
+pub type BoxedLayer<S> = Box<dyn Layer<S> + Send + Sync>;
+
+/// The format of log lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Human-readable lines with the enclosing spans as a prefix.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("Invalid log format '{}': expected text or json", other)),
+        }
+    }
+}
+
+impl LogFormat {
+    /// Reads the log format from `SVG2PNG_LOG_FORMAT`, defaulting to [`LogFormat::Text`].
+    ///
+    /// # Returns
+    ///
+    /// * `Err(anyhow::Error)` - If the variable is neither `text` nor `json`.
+    pub fn from_env() -> anyhow::Result<Self> {
+        match std::env::var(LOG_FORMAT_ENV_VAR) {
+            Ok(value) if !value.trim().is_empty() => Ok(value
+                .trim()
+                .parse()
+                .map_err(anyhow::Error::msg)
+                .with_context(|| format!("Invalid {} value", LOG_FORMAT_ENV_VAR))?),
+            _ => Ok(LogFormat::default()),
+        }
+    }
+}
+
+/// Builds the layer that writes log lines to `writer`.
+///
+/// JSON lines hold the event's `timestamp`, `level`, `target` and `fields`,
+/// plus `span` (the innermost span) and `spans` (all enclosing spans, outermost
+/// first) with their fields, so the `request` span's `request_id` is on every
+/// line logged while handling a request.
+pub fn fmt_layer<S>(format: LogFormat, writer: BoxMakeWriter) -> BoxedLayer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+{
+    match format {
+        LogFormat::Text => Box::new(fmt::layer().with_writer(writer)),
+        LogFormat::Json => Box::new(fmt::layer().json().with_current_span(true).with_span_list(true).with_writer(writer)),
+    }
+}
+
+/// Keeps span export running; flushes pending spans when dropped.
+#[must_use = "dropping the guard stops span export"]
+pub struct Guard {
+    /// The exporting tracer provider, if export is enabled.
+    #[cfg(feature = "otel")]
+    provider: Option<otlp::SdkTracerProvider>,
+}
+
+/// Builds the OTLP export layer.
+///
+/// # Returns
+///
+/// * `Ok((Some(layer), guard))` - If the `otel` feature is enabled and an OTLP endpoint is configured.
+/// * `Ok((None, guard))` - If span export is disabled.
+/// * `Err(anyhow::Error)` - If the exporter cannot be built, e.g. for an invalid endpoint URL.
+pub fn otlp_layer<S>() -> anyhow::Result<(Option<BoxedLayer<S>>, Guard)>
+where
+    S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+{
+    #[cfg(feature = "otel")]
+    {
+        if !otlp::endpoint_configured() {
+            return Ok((None, Guard { provider: None }));
+        }
+        let provider = otlp::tracer_provider(None)?;
+        Ok((Some(otlp::layer(&provider)), Guard { provider: Some(provider) }))
+    }
+    #[cfg(not(feature = "otel"))]
+    Ok((None, Guard {}))
+}
+
+#[cfg(feature = "otel")]
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take() {
+            // The subscriber may already be gone, so report on stderr.
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Failed to flush trace spans: {}", e);
+            }
+        }
+    }
+}
+
+/// Middleware that runs each request in a `request` span.
+///
+/// The span is named after the method and route template (e.g.
+/// `POST /svg-to-icon/{format}`) and records the request ID and the response
+/// status. It must run inside [`request_id::assign_request_id`].
+pub async fn trace_request(request: Request, next: Next) -> Response {
+    let method = request.method().clone();
+    let route = request.extensions().get::<MatchedPath>().map(|path| path.as_str().to_string());
+    let name = match &route {
+        Some(route) => format!("{} {}", method, route),
+        None => method.to_string(),
+    };
+    let span = info_span!(
+        "request",
+        otel.name = name,
+        otel.kind = "server",
+        http.request.method = %method,
+        http.route = route,
+        http.response.status_code = tracing::field::Empty,
+        request_id = request_id::current(),
+    );
+    #[cfg(feature = "otel")]
+    otlp::set_parent(&span, request.headers());
+
+    let response = next.run(request).instrument(span.clone()).await;
+    span.record("http.response.status_code", response.status().as_u16());
+    response
+}
+
+/// OTLP export and W3C trace context propagation.
+#[cfg(feature = "otel")]
+mod otlp {
+    use axum::http::HeaderMap;
+    use opentelemetry::propagation::{Extractor, TextMapPropagator};
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::{propagation::TraceContextPropagator, Resource};
+    use tracing::{Span, Subscriber};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::registry::LookupSpan;
+
+    pub use opentelemetry_sdk::trace::SdkTracerProvider;
+
+    use super::BoxedLayer;
+
+    /// Environment variable with the OTLP base URL for all signals.
+    const ENDPOINT_ENV_VAR: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+    /// Environment variable with the full OTLP URL for traces.
+    const TRACES_ENDPOINT_ENV_VAR: &str = "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT";
+    /// Environment variable overriding the reported service name.
+    const SERVICE_NAME_ENV_VAR: &str = "OTEL_SERVICE_NAME";
+
+    /// Whether an OTLP endpoint is configured in the environment.
+    pub fn endpoint_configured() -> bool {
+        [ENDPOINT_ENV_VAR, TRACES_ENDPOINT_ENV_VAR]
+            .iter()
+            .any(|var| std::env::var(var).is_ok_and(|v| !v.is_empty()))
+    }
+
+    /// Builds a tracer provider that batches spans to an OTLP/HTTP endpoint.
+    ///
+    /// Spans are exported from a background thread, never from request tasks.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The full traces URL, or `None` to read it from the environment.
+    pub fn tracer_provider(endpoint: Option<&str>) -> anyhow::Result<SdkTracerProvider> {
+        let mut exporter = SpanExporter::builder().with_http();
+        if let Some(endpoint) = endpoint {
+            exporter = exporter.with_endpoint(endpoint);
+        }
+        let exporter = exporter.build()?;
+
+        let mut resource = Resource::builder();
+        if std::env::var_os(SERVICE_NAME_ENV_VAR).is_none() {
+            resource = resource.with_service_name(env!("CARGO_PKG_NAME"));
+        }
+        Ok(SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(resource.build())
+            .build())
+    }
+
+    /// Builds the layer that turns tracing spans into OpenTelemetry spans.
+    pub fn layer<S>(provider: &SdkTracerProvider) -> BoxedLayer<S>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+    {
+        Box::new(tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_PKG_NAME"))))
+    }
+
+    /// Makes `span` a child of the remote span in the request's `traceparent` header.
+    ///
+    /// Missing or malformed headers leave `span` a root span.
+    pub fn set_parent(span: &Span, headers: &HeaderMap) {
+        let parent = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+        // Fails only when export is disabled, in which case there is nothing to link.
+        let _ = span.set_parent(parent);
+    }
+
+    /// Reads propagation headers from a request.
+    struct HeaderExtractor<'a>(&'a HeaderMap);
+
+    impl Extractor for HeaderExtractor<'_> {
+        fn get(&self, key: &str) -> Option<&str> {
+            self.0.get(key).and_then(|value| value.to_str().ok())
+        }
+
+        fn keys(&self) -> Vec<&str> {
+            self.0.keys().map(|name| name.as_str()).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Router};
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+    use tracing_subscriber::prelude::*;
+
+    #[tokio::test]
+    async fn test_json_log_lines_carry_request_id() {
+        // Collect log output in memory.
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let writer = {
+            let output = output.clone();
+            BoxMakeWriter::new(move || SharedWriter(output.clone()))
+        };
+        let _subscriber = tracing_subscriber::registry().with(fmt_layer(LogFormat::Json, writer)).set_default();
+
+        let app = Router::new()
+            .route("/log", get(|| async { tracing::info!("handled") }))
+            .layer(axum::middleware::from_fn(trace_request))
+            .layer(axum::middleware::from_fn(request_id::assign_request_id));
+        let request = axum::http::Request::builder()
+            .uri("/log")
+            .header("x-request-id", "abc-123")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap();
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let line: serde_json::Value = serde_json::from_str(output.lines().next().unwrap()).unwrap();
+        assert_eq!(line["fields"]["message"], "handled");
+        assert_eq!(line["span"]["name"], "request");
+        assert_eq!(line["span"]["request_id"], "abc-123");
+        assert_eq!(line["spans"][0]["otel.name"], "GET /log");
+    }
+
+    #[test]
+    fn test_log_format_parsing() {
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert_eq!("text".parse(), Ok(LogFormat::Text));
+        assert!("yaml".parse::<LogFormat>().is_err());
+    }
+
+    /// Appends log output to a shared buffer.
+    struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "otel")]
+    #[tokio::test]
+    async fn test_exports_phase_spans_under_remote_parent() {
+        use axum::{body::Bytes, http::StatusCode, routing::post};
+        use svg2png::{RenderOptions, Renderer};
+
+        // A stand-in collector that forwards every OTLP request body.
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let collector = Router::new().route(
+            "/v1/traces",
+            post(move |body: Bytes| {
+                let sender = sender.clone();
+                async move {
+                    let _ = sender.send(body);
+                    StatusCode::OK
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/v1/traces", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, collector).await.unwrap() });
+
+        let provider = otlp::tracer_provider(Some(&endpoint)).unwrap();
+        let _subscriber = tracing_subscriber::registry().with(otlp::layer(&provider)).set_default();
+
+        let renderer = std::sync::Arc::new(Renderer::default());
+        let app = Router::new()
+            .route(
+                "/render",
+                post(move |body: Bytes| {
+                    let renderer = renderer.clone();
+                    async move { renderer.render(&body, &RenderOptions::default()).unwrap().data }
+                }),
+            )
+            .layer(axum::middleware::from_fn(trace_request));
+        let request = axum::http::Request::builder()
+            .method("POST")
+            .uri("/render")
+            .header("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+            .body(axum::body::Body::from(r#"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="4"/>"#))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Flushing blocks until the collector has answered, so keep the runtime free.
+        tokio::task::spawn_blocking(move || provider.force_flush()).await.unwrap().unwrap();
+        let body = receiver.recv().await.unwrap();
+
+        let contains = |needle: &[u8]| body.windows(needle.len()).any(|window| window == needle);
+        for name in ["POST /render", "parse", "render", "encode"] {
+            assert!(contains(name.as_bytes()), "missing span {}", name);
+        }
+        // The remote trace ID and parent span ID, as raw protobuf bytes.
+        assert!(contains(&[0x4b, 0xf9, 0x2f, 0x35, 0x77, 0xb3, 0x4d, 0xa6, 0xa3, 0xce, 0x92, 0x9d, 0x0e, 0x0e, 0x47, 0x36]));
+        assert!(contains(&[0x00, 0xf0, 0x67, 0xaa, 0x0b, 0xa9, 0x02, 0xb7]));
+    }
+}
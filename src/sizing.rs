@@ -0,0 +1,374 @@
+//! # Output Sizing
+//!
+//! Computes the output canvas size and the rendering transform for an SVG,
+//! given its intrinsic size, the DPI-derived scale factor, and optional explicit
+//! `width`/`height` targets combined with a [`FitMode`] and an [`Alignment`].
+//!
+//! The semantics follow CSS `object-fit` / `object-position`: when both a target
+//! width and height are requested, the output canvas is exactly that size and the
+//! SVG content is scaled and positioned inside it according to the fit mode.
+//!
+//! The "intrinsic size" is that of the region being rendered: the whole canvas,
+//! a [`parse_view_box`] override, the drawn content or a single element, plus
+//! any [`Padding`].
+
+use std::str::FromStr;
+
+use resvg::tiny_skia::{NonZeroRect, Rect, Transform};
+use resvg::usvg::Size;
+use svgtypes::{Align, AspectRatio};
+
+/// How the SVG content is fitted into an explicit `width` x `height` box.
+///
+/// Only used when both a target width and height are requested. If only one
+/// dimension is given, the other is derived from the SVG's aspect ratio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FitMode {
+    /// Scale uniformly so the whole SVG fits inside the box (letterboxed).
+    #[default]
+    Contain,
+    /// Scale uniformly so the SVG covers the whole box (overflow is cropped).
+    Cover,
+    /// Stretch non-uniformly so the SVG exactly fills the box.
+    Fill,
+    /// Like `Contain`, but never scale above the size implied by the requested DPI.
+    ScaleDown,
+}
+
+impl FromStr for FitMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "contain" => Ok(FitMode::Contain),
+            "cover" => Ok(FitMode::Cover),
+            "fill" => Ok(FitMode::Fill),
+            "scale-down" => Ok(FitMode::ScaleDown),
+            other => Err(format!(
+                "Invalid fit mode '{}': expected one of contain, cover, fill, scale-down",
+                other
+            )),
+        }
+    }
+}
+
+/// Position of the scaled SVG content inside the output canvas.
+///
+/// Each axis is expressed as a fraction of the free space: `0.0` aligns to the
+/// start (left/top), `0.5` centers, and `1.0` aligns to the end (right/bottom).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Alignment {
+    /// Horizontal alignment fraction.
+    pub x: f32,
+    /// Vertical alignment fraction.
+    pub y: f32,
+}
+
+impl Default for Alignment {
+    fn default() -> Self {
+        Alignment { x: 0.5, y: 0.5 }
+    }
+}
+
+impl FromStr for Alignment {
+    type Err = String;
+
+    /// Parses alignment keywords such as `center`, `top`, `bottom-right` or `left`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (x, y) = match s {
+            "center" => (0.5, 0.5),
+            "top" => (0.5, 0.0),
+            "bottom" => (0.5, 1.0),
+            "left" => (0.0, 0.5),
+            "right" => (1.0, 0.5),
+            "top-left" => (0.0, 0.0),
+            "top-right" => (1.0, 0.0),
+            "bottom-left" => (0.0, 1.0),
+            "bottom-right" => (1.0, 1.0),
+            other => {
+                return Err(format!(
+                    "Invalid alignment '{}': expected center, top, bottom, left, right, \
+                     top-left, top-right, bottom-left or bottom-right",
+                    other
+                ))
+            }
+        };
+        Ok(Alignment { x, y })
+    }
+}
+
+/// Space added around the rendered region, in canvas pixels (output pixels at 96 DPI).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Padding {
+    /// Space above the region.
+    pub top: f32,
+    /// Space right of the region.
+    pub right: f32,
+    /// Space below the region.
+    pub bottom: f32,
+    /// Space left of the region.
+    pub left: f32,
+}
+
+impl Padding {
+    /// Equal padding on every side.
+    pub fn uniform(padding: f32) -> Self {
+        Padding { top: padding, right: padding, bottom: padding, left: padding }
+    }
+
+    /// Grows `rect` by the padding.
+    pub fn expand(&self, rect: Rect) -> Option<Rect> {
+        Rect::from_ltrb(
+            rect.left() - self.left,
+            rect.top() - self.top,
+            rect.right() + self.right,
+            rect.bottom() + self.bottom,
+        )
+    }
+}
+
+impl FromStr for Padding {
+    type Err = String;
+
+    /// Parses one to four comma-separated non-negative lengths, in the order of
+    /// the CSS `padding` shorthand (`all`, `vertical,horizontal`,
+    /// `top,horizontal,bottom` or `top,right,bottom,left`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|v| match v.trim().parse::<f32>() {
+                Ok(v) if v.is_finite() && v >= 0.0 => Ok(v),
+                _ => Err(()),
+            })
+            .collect::<Result<Vec<_>, _>>();
+        let (top, right, bottom, left) = match values.as_deref() {
+            Ok(&[all]) => (all, all, all, all),
+            Ok(&[vertical, horizontal]) => (vertical, horizontal, vertical, horizontal),
+            Ok(&[top, horizontal, bottom]) => (top, horizontal, bottom, horizontal),
+            Ok(&[top, right, bottom, left]) => (top, right, bottom, left),
+            _ => {
+                return Err(format!(
+                    "Invalid padding '{}': expected one to four comma-separated non-negative numbers",
+                    s
+                ))
+            }
+        };
+        Ok(Padding { top, right, bottom, left })
+    }
+}
+
+/// Parses a `viewBox`-style rectangle `x,y,width,height` (commas or spaces).
+pub fn parse_view_box(s: &str) -> Result<NonZeroRect, String> {
+    s.parse::<svgtypes::ViewBox>()
+        .ok()
+        .and_then(|vb| NonZeroRect::from_xywh(vb.x as f32, vb.y as f32, vb.w as f32, vb.h as f32))
+        .ok_or_else(|| format!("Invalid viewbox '{}': expected x,y,width,height with a positive width and height", s))
+}
+
+/// Computes the transform from an SVG's user coordinates to its canvas, as
+/// the root `viewBox` and `preserveAspectRatio` attributes define it.
+pub fn view_box_transform(view_box: NonZeroRect, aspect: AspectRatio, size: Size) -> Transform {
+    let sx = size.width() / view_box.width();
+    let sy = size.height() / view_box.height();
+    let (sx, sy) = match (aspect.align, aspect.slice) {
+        (Align::None, _) => (sx, sy),
+        (_, true) => (sx.max(sy), sx.max(sy)),
+        (_, false) => (sx.min(sy), sx.min(sy)),
+    };
+
+    // Free space along each axis, distributed according to the alignment.
+    let free_x = size.width() - view_box.width() * sx;
+    let free_y = size.height() - view_box.height() * sy;
+    let (align_x, align_y) = match aspect.align {
+        Align::None | Align::XMinYMin => (0.0, 0.0),
+        Align::XMidYMin => (0.5, 0.0),
+        Align::XMaxYMin => (1.0, 0.0),
+        Align::XMinYMid => (0.0, 0.5),
+        Align::XMidYMid => (0.5, 0.5),
+        Align::XMaxYMid => (1.0, 0.5),
+        Align::XMinYMax => (0.0, 1.0),
+        Align::XMidYMax => (0.5, 1.0),
+        Align::XMaxYMax => (1.0, 1.0),
+    };
+    Transform::from_row(
+        sx,
+        0.0,
+        0.0,
+        sy,
+        -view_box.x() * sx + free_x * align_x,
+        -view_box.y() * sy + free_y * align_y,
+    )
+}
+
+/// The computed output canvas size and rendering transform.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Layout {
+    /// Output pixmap width in pixels.
+    pub width: u32,
+    /// Output pixmap height in pixels.
+    pub height: u32,
+    /// Horizontal scale applied to SVG user units.
+    pub scale_x: f32,
+    /// Vertical scale applied to SVG user units.
+    pub scale_y: f32,
+    /// Full transform (scale and alignment offset) to render with.
+    pub transform: Transform,
+}
+
+/// Computes the output [`Layout`] for an SVG.
+///
+/// # Arguments
+///
+/// * `base` - The intrinsic SVG size in user units (CSS pixels at 96 DPI).
+/// * `dpi_scale` - The scale factor implied by the requested DPI (`dpi / 96`).
+/// * `width` - Optional explicit output width in pixels.
+/// * `height` - Optional explicit output height in pixels.
+/// * `fit` - How to fit the content when both `width` and `height` are given.
+/// * `align` - Where to position the content inside the canvas when it does not fill it.
+///
+/// # Returns
+///
+/// The canvas size (rounded up so nothing is clipped) and the transform to
+/// render with. Dimensions may be zero for degenerate inputs; callers must check.
+pub fn compute_layout(
+    base: Size,
+    dpi_scale: f32,
+    width: Option<u32>,
+    height: Option<u32>,
+    fit: FitMode,
+    align: Alignment,
+) -> Layout {
+    let base_width = base.width();
+    let base_height = base.height();
+
+    let (canvas_width, canvas_height, scale_x, scale_y) = match (width, height) {
+        (None, None) => (
+            (base_width * dpi_scale).ceil() as u32,
+            (base_height * dpi_scale).ceil() as u32,
+            dpi_scale,
+            dpi_scale,
+        ),
+        (Some(w), None) => {
+            let scale = w as f32 / base_width;
+            (w, (base_height * scale).ceil() as u32, scale, scale)
+        }
+        (None, Some(h)) => {
+            let scale = h as f32 / base_height;
+            ((base_width * scale).ceil() as u32, h, scale, scale)
+        }
+        (Some(w), Some(h)) => {
+            let fit_x = w as f32 / base_width;
+            let fit_y = h as f32 / base_height;
+            let (scale_x, scale_y) = match fit {
+                FitMode::Fill => (fit_x, fit_y),
+                FitMode::Contain => {
+                    let s = fit_x.min(fit_y);
+                    (s, s)
+                }
+                FitMode::Cover => {
+                    let s = fit_x.max(fit_y);
+                    (s, s)
+                }
+                FitMode::ScaleDown => {
+                    let s = fit_x.min(fit_y).min(dpi_scale);
+                    (s, s)
+                }
+            };
+            (w, h, scale_x, scale_y)
+        }
+    };
+
+    // Offset the content inside the canvas according to the alignment. For
+    // `cover` the free space is negative, which crops the overflowing side.
+    let free_x = canvas_width as f32 - base_width * scale_x;
+    let free_y = canvas_height as f32 - base_height * scale_y;
+    let (offset_x, offset_y) = if width.is_some() && height.is_some() {
+        (free_x * align.x, free_y * align.y)
+    } else {
+        (0.0, 0.0)
+    };
+
+    Layout {
+        width: canvas_width,
+        height: canvas_height,
+        scale_x,
+        scale_y,
+        transform: Transform::from_row(scale_x, 0.0, 0.0, scale_y, offset_x, offset_y),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(w: f32, h: f32) -> Size {
+        Size::from_wh(w, h).unwrap()
+    }
+
+    #[test]
+    fn test_dpi_only_scales_intrinsic_size() {
+        let layout = compute_layout(size(100.0, 50.0), 2.0, None, None, FitMode::Contain, Alignment::default());
+        assert_eq!((layout.width, layout.height), (200, 100));
+        assert_eq!((layout.scale_x, layout.scale_y), (2.0, 2.0));
+    }
+
+    #[test]
+    fn test_single_dimension_preserves_aspect_ratio() {
+        let layout = compute_layout(size(100.0, 50.0), 1.0, Some(512), None, FitMode::Contain, Alignment::default());
+        assert_eq!((layout.width, layout.height), (512, 256));
+    }
+
+    #[test]
+    fn test_contain_letterboxes_and_centers() {
+        let layout = compute_layout(size(100.0, 50.0), 1.0, Some(200), Some(200), FitMode::Contain, Alignment::default());
+        assert_eq!((layout.width, layout.height), (200, 200));
+        assert_eq!(layout.scale_x, 2.0);
+        assert_eq!(layout.transform.ty, 50.0);
+    }
+
+    #[test]
+    fn test_cover_fill_and_scale_down() {
+        let base = size(100.0, 50.0);
+        let cover = compute_layout(base, 1.0, Some(200), Some(200), FitMode::Cover, "top-left".parse().unwrap());
+        assert_eq!((cover.scale_x, cover.transform.tx), (4.0, 0.0));
+
+        let fill = compute_layout(base, 1.0, Some(200), Some(200), FitMode::Fill, Alignment::default());
+        assert_eq!((fill.scale_x, fill.scale_y), (2.0, 4.0));
+
+        let scale_down = compute_layout(base, 1.0, Some(400), Some(400), FitMode::ScaleDown, Alignment::default());
+        assert_eq!((scale_down.width, scale_down.scale_x), (400, 1.0));
+    }
+
+    #[test]
+    fn test_padding_shorthand() {
+        assert_eq!("4".parse::<Padding>().unwrap(), Padding::uniform(4.0));
+        let p: Padding = "1, 2".parse().unwrap();
+        assert_eq!((p.top, p.right, p.bottom, p.left), (1.0, 2.0, 1.0, 2.0));
+        let p: Padding = "1,2,3".parse().unwrap();
+        assert_eq!((p.top, p.right, p.bottom, p.left), (1.0, 2.0, 3.0, 2.0));
+        let p: Padding = "1,2,3,4.5".parse().unwrap();
+        assert_eq!((p.top, p.right, p.bottom, p.left), (1.0, 2.0, 3.0, 4.5));
+        for invalid in ["", "-1", "1,2,3,4,5", "a", "inf"] {
+            assert!(invalid.parse::<Padding>().is_err(), "{}", invalid);
+        }
+
+        let rect = p.expand(Rect::from_xywh(10.0, 10.0, 20.0, 20.0).unwrap()).unwrap();
+        assert_eq!((rect.x(), rect.y(), rect.width(), rect.height()), (5.5, 9.0, 26.5, 24.0));
+    }
+
+    #[test]
+    fn test_view_box_parsing_and_transform() {
+        let vb = parse_view_box("10 20,30,40").unwrap();
+        assert_eq!((vb.x(), vb.y(), vb.width(), vb.height()), (10.0, 20.0, 30.0, 40.0));
+        assert!(parse_view_box("0,0,0,10").is_err());
+        assert!(parse_view_box("0,0,10").is_err());
+
+        // A 0 0 50 50 viewBox on a 200x100 canvas is scaled by 2 and centered.
+        let vb = parse_view_box("0,0,50,50").unwrap();
+        let ts = view_box_transform(vb, AspectRatio::default(), size(200.0, 100.0));
+        assert_eq!((ts.sx, ts.sy, ts.tx, ts.ty), (2.0, 2.0, 50.0, 0.0));
+        let stretch = AspectRatio { align: Align::None, ..AspectRatio::default() };
+        let ts = view_box_transform(vb, stretch, size(200.0, 100.0));
+        assert_eq!((ts.sx, ts.sy, ts.tx, ts.ty), (4.0, 2.0, 0.0, 0.0));
+    }
+}
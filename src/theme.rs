@@ -0,0 +1,77 @@
+//! # Theming
+//!
+//! Builds the CSS injected while parsing from [`RenderOptions::style_sheet`]
+//! and [`RenderOptions::color`], so the same SVG can be rendered in several
+//! themes without rewriting its source.
+//!
+//! The injected rules behave like a user-agent stylesheet (the same as
+//! `rsvg-convert --stylesheet`): they override presentation attributes but not
+//! the SVG's own `<style>` elements or `style` attributes.
+
+use std::sync::Arc;
+
+use resvg::usvg::Options;
+
+use crate::renderer::RenderOptions;
+
+/// Combines the stylesheet and the `color` shortcut into one stylesheet.
+///
+/// The `color` rule comes last so it wins over a `color` set by the stylesheet.
+///
+/// # Returns
+///
+/// `None` if neither is set, so the shared usvg options can be used as-is.
+pub(crate) fn style_sheet(options: &RenderOptions) -> Option<String> {
+    let color = options.color.map(|c| {
+        // `currentColor` resolves to the inherited `color`, so setting it on the
+        // root element themes every `fill="currentColor"` below it.
+        if c.alpha == 255 {
+            format!("svg {{ color: #{:02x}{:02x}{:02x} }}", c.red, c.green, c.blue)
+        } else {
+            format!("svg {{ color: rgba({}, {}, {}, {}) }}", c.red, c.green, c.blue, c.alpha as f32 / 255.0)
+        }
+    });
+    match (options.style_sheet.as_deref(), color) {
+        (None, None) => None,
+        (Some(css), None) => Some(css.to_string()),
+        (None, Some(color)) => Some(color),
+        (Some(css), Some(color)) => Some(format!("{}\n{}", css, color)),
+    }
+}
+
+/// Copies the shared usvg options with a stylesheet injected.
+///
+/// `usvg::Options` is not `Clone`; the font database is shared, not copied.
+pub(crate) fn with_style_sheet(base: &Options<'static>, style_sheet: String) -> Options<'static> {
+    Options {
+        resources_dir: base.resources_dir.clone(),
+        dpi: base.dpi,
+        font_family: base.font_family.clone(),
+        font_size: base.font_size,
+        languages: base.languages.clone(),
+        shape_rendering: base.shape_rendering,
+        text_rendering: base.text_rendering,
+        image_rendering: base.image_rendering,
+        default_size: base.default_size,
+        fontdb: Arc::clone(&base.fontdb),
+        style_sheet: Some(style_sheet),
+        ..Options::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_style_sheet_combines_css_and_color() {
+        let mut options = RenderOptions::default();
+        assert_eq!(style_sheet(&options), None);
+
+        options.color = Some(svgtypes::Color::new_rgba(255, 0, 128, 255));
+        assert_eq!(style_sheet(&options).unwrap(), "svg { color: #ff0080 }");
+        options.color = Some(svgtypes::Color::new_rgba(0, 0, 0, 51));
+        options.style_sheet = Some(".a { fill: red }".to_string());
+        assert_eq!(style_sheet(&options).unwrap(), ".a { fill: red }\nsvg { color: rgba(0, 0, 0, 0.2) }");
+    }
+}
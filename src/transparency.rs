@@ -0,0 +1,678 @@
+//! # Background Removal
+//!
+//! Native replacement for ImageMagick's
+//! `convert in.png -fuzz 5% -fill none -draw "color 0,0 floodfill" out.png`.
+//!
+//! The PNG is decoded to 8-bit RGBA, a 4-connected flood fill starting at a
+//! seed pixel clears every contiguous pixel whose color is within the fuzz
+//! tolerance of the seed color, and the result is re-encoded as RGBA PNG.
+//! Color similarity follows ImageMagick's fuzzy color distance so the `fuzz`
+//! percentage behaves the same as `-fuzz`.
+//!
+//! Besides the default single top-left seed, [`KeyOptions`] allows multiple seed
+//! points (or all four corners), an explicit color to key out, and a global mode
+//! that clears every matching pixel regardless of connectivity.
+//!
+//! Keying alone leaves hard halos where the background was blended into
+//! anti-aliased edges. With a non-zero `feather` radius, pixels near the cleared
+//! region are refined by un-mixing the key color (color decontamination): each
+//! pixel is treated as `alpha * foreground + (1 - alpha) * background`, and the
+//! smallest alpha explaining its color is recovered together with the
+//! foreground color.
+
+use std::str::FromStr;
+
+use tracing::{debug, error};
+
+use crate::config::Limits;
+use crate::deadline::Deadline;
+use crate::error::RenderError;
+
+/// Default fuzz factor, as a fraction of the channel range (ImageMagick's `-fuzz 5%`).
+pub const DEFAULT_FUZZ: f32 = 0.05;
+/// Largest accepted edge feathering radius in pixels.
+pub const MAX_FEATHER: u32 = 64;
+/// Pixels visited between deadline checks inside the keying loops.
+const DEADLINE_CHECK_INTERVAL: usize = 1 << 16;
+
+/// How background pixels are selected for removal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyMode {
+    /// Clear pixels contiguous with a seed point (4-connected flood fill).
+    #[default]
+    Flood,
+    /// Clear every pixel matching the key color anywhere in the image.
+    Global,
+}
+
+impl FromStr for KeyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flood" => Ok(KeyMode::Flood),
+            "global" => Ok(KeyMode::Global),
+            other => Err(format!("Invalid mode '{}': expected flood or global", other)),
+        }
+    }
+}
+
+/// A seed point for background sampling and flood filling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Seed {
+    /// An explicit pixel coordinate.
+    Point(u32, u32),
+    /// All four corners of the image.
+    Corners,
+}
+
+impl FromStr for Seed {
+    type Err = String;
+
+    /// Parses `x,y` coordinates or the keyword `corners`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "corners" {
+            return Ok(Seed::Corners);
+        }
+        let invalid = || format!("Invalid seed '{}': expected x,y pixel coordinates or corners", s);
+        let (x, y) = s.split_once(',').ok_or_else(invalid)?;
+        let x = x.trim().parse().map_err(|_| invalid())?;
+        let y = y.trim().parse().map_err(|_| invalid())?;
+        Ok(Seed::Point(x, y))
+    }
+}
+
+/// Options controlling background removal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyOptions {
+    /// Color tolerance as a fraction of the channel range (`0.05` for `5%`).
+    pub fuzz: f32,
+    /// Seed points. Empty means the top-left pixel.
+    pub seeds: Vec<Seed>,
+    /// Explicit color to key out. If unset, the color under each seed is used.
+    pub color: Option<[u8; 4]>,
+    /// Contiguous flood fill or global color keying.
+    pub mode: KeyMode,
+    /// Radius in pixels around the cleared region to refine with partial alpha. `0` disables.
+    pub feather: u32,
+    /// Deadline checked between decoding, keying and encoding. Unbounded if unset.
+    pub deadline: Option<Deadline>,
+}
+
+impl Default for KeyOptions {
+    fn default() -> Self {
+        KeyOptions {
+            fuzz: DEFAULT_FUZZ,
+            seeds: Vec::new(),
+            color: None,
+            mode: KeyMode::default(),
+            feather: 0,
+            deadline: None,
+        }
+    }
+}
+
+/// An 8-bit RGBA image decoded from a PNG.
+#[derive(Debug, Clone)]
+pub struct Bitmap {
+    /// Width in pixels.
+    pub width: u32,
+    /// Height in pixels.
+    pub height: u32,
+    /// Pixel data, 4 bytes per pixel, straight (non-premultiplied) alpha.
+    pub data: Vec<u8>,
+    /// Physical pixel dimensions (`pHYs`) of the source image, preserved on output.
+    pub pixel_dims: Option<png::PixelDimensions>,
+}
+
+impl Bitmap {
+    /// Returns the RGBA value of the pixel at `(x, y)`.
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = self.index(x, y);
+        [self.data[i], self.data[i + 1], self.data[i + 2], self.data[i + 3]]
+    }
+
+    /// Byte offset of the pixel at `(x, y)`.
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y as usize * self.width as usize + x as usize) * 4
+    }
+}
+
+/// Decodes a PNG of any color type and bit depth into 8-bit RGBA.
+///
+/// # Returns
+///
+/// * `Ok(Bitmap)` - The decoded image.
+/// * `Err(RenderError::PngDecode)` - If the data is not a valid PNG.
+/// * `Err(RenderError::LimitExceeded)` - If the image exceeds the configured size limits.
+pub fn decode_png(data: &[u8], limits: &Limits) -> Result<Bitmap, RenderError> {
+    let invalid = |e: png::DecodingError| {
+        error!(error = %e, "Invalid PNG data received");
+        RenderError::PngDecode(e.to_string())
+    };
+
+    let mut decoder = png::Decoder::new(data);
+    // Expand palettes, low bit depths and tRNS; strip 16-bit samples to 8 bits.
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(invalid)?;
+    // Check the header dimensions before allocating, so a small, highly
+    // compressed PNG cannot expand into an enormous buffer.
+    let info = reader.info();
+    limits.check_dimensions(info.width, info.height)?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buffer).map_err(invalid)?;
+    buffer.truncate(frame.buffer_size());
+    let pixel_dims = reader.info().pixel_dims;
+
+    let pixel_count = frame.width as usize * frame.height as usize;
+    let data = match frame.color_type {
+        png::ColorType::Rgba => buffer,
+        png::ColorType::Rgb => buffer.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => buffer.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => buffer.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        // Palettes are expanded by `normalize_to_color8`.
+        png::ColorType::Indexed => {
+            return Err(RenderError::PngDecode("unexpanded palette".to_string()));
+        }
+    };
+    debug_assert_eq!(data.len(), pixel_count * 4);
+    debug!(width = frame.width, height = frame.height, color_type = ?frame.color_type, "Decoded PNG");
+
+    Ok(Bitmap {
+        width: frame.width,
+        height: frame.height,
+        data,
+        pixel_dims,
+    })
+}
+
+/// Encodes a bitmap as an 8-bit RGBA PNG, preserving its `pHYs` chunk.
+///
+/// # Returns
+///
+/// * `Ok(Vec<u8>)` - The encoded PNG.
+/// * `Err(RenderError::Encoder)` - If encoding fails.
+pub fn encode_png(bitmap: &Bitmap) -> Result<Vec<u8>, RenderError> {
+    let encode_error = |e: png::EncodingError| {
+        error!(error = %e, "Failed to encode PNG");
+        RenderError::Encoder(format!("Failed to encode PNG: {}", e))
+    };
+
+    let mut buffer = Vec::new();
+    let mut encoder = png::Encoder::new(&mut buffer, bitmap.width, bitmap.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_pixel_dims(bitmap.pixel_dims);
+    let mut writer = encoder.write_header().map_err(encode_error)?;
+    writer.write_image_data(&bitmap.data).map_err(encode_error)?;
+    // Finalize the PNG stream before the buffer is returned.
+    writer.finish().map_err(encode_error)?;
+    Ok(buffer)
+}
+
+/// Returns whether two RGBA colors are equivalent within `fuzz`.
+///
+/// Mirrors ImageMagick's `IsFuzzyEquivalencePixel`: the alpha difference must
+/// be within `fuzz`, and the color difference (scaled by both alphas, so fully
+/// transparent pixels match regardless of color) must be within `fuzz` per
+/// channel on average.
+///
+/// # Arguments
+///
+/// * `a`, `b` - The colors to compare.
+/// * `fuzz` - Tolerance as a fraction of the channel range (`0.05` for `5%`).
+pub fn is_fuzzy_equivalent(a: [u8; 4], b: [u8; 4], fuzz: f32) -> bool {
+    let channel = |c: u8| c as f32 / 255.0;
+    // ImageMagick never uses a fuzz below sqrt(1/2) quantum units, which keeps
+    // exact matches working; in normalized units that is negligible.
+    let fuzz = fuzz.max(0.5_f32.sqrt() / 65535.0);
+    let fuzz_squared = fuzz * fuzz;
+
+    let alpha_delta = channel(a[3]) - channel(b[3]);
+    let mut distance = alpha_delta * alpha_delta;
+    if distance > fuzz_squared {
+        return false;
+    }
+    let scale = channel(a[3]) * channel(b[3]);
+    if scale <= f32::EPSILON {
+        return true;
+    }
+
+    distance *= 3.0;
+    for i in 0..3 {
+        let delta = channel(a[i]) - channel(b[i]);
+        distance += scale * delta * delta;
+        if distance > 3.0 * fuzz_squared {
+            return false;
+        }
+    }
+    true
+}
+
+/// Removes the background of `bitmap` according to `options`.
+///
+/// # Returns
+///
+/// * `Ok(usize)` - The number of pixels made transparent.
+/// * `Err(RenderError::InvalidOption)` - If a seed point lies outside the image.
+/// * `Err(RenderError::Timeout)` - If `options.deadline` passes while keying or feathering.
+pub fn remove_background(bitmap: &mut Bitmap, options: &KeyOptions) -> Result<usize, RenderError> {
+    let (width, height) = (bitmap.width, bitmap.height);
+    if width == 0 || height == 0 {
+        return Ok(0);
+    }
+
+    let mut points = Vec::new();
+    let seeds = if options.seeds.is_empty() { &[Seed::Point(0, 0)][..] } else { &options.seeds };
+    for seed in seeds {
+        match *seed {
+            Seed::Point(x, y) if x < width && y < height => points.push((x, y)),
+            Seed::Point(x, y) => {
+                let err_msg = format!("Seed {},{} is outside the {}x{} image", x, y, width, height);
+                error!(%err_msg);
+                return Err(RenderError::InvalidOption(err_msg));
+            }
+            Seed::Corners => {
+                points.extend([(0, 0), (width - 1, 0), (0, height - 1), (width - 1, height - 1)]);
+            }
+        }
+    }
+
+    // Sample every seed color before modifying the image, so overlapping fills
+    // do not pick up already-cleared pixels as their target.
+    let targets: Vec<[u8; 4]> = points
+        .iter()
+        .map(|&(x, y)| options.color.unwrap_or_else(|| bitmap.pixel(x, y)))
+        .collect();
+
+    // Tracks which pixels were cleared, for edge feathering.
+    let mut mask = vec![false; width as usize * height as usize];
+    let deadline = options.deadline;
+    let cleared = match options.mode {
+        KeyMode::Flood => {
+            let mut cleared = 0;
+            for (&seed, &target) in points.iter().zip(&targets) {
+                cleared += flood_fill_transparent(bitmap, seed, target, options.fuzz, &mut mask, deadline)?;
+            }
+            cleared
+        }
+        KeyMode::Global => key_color_transparent(bitmap, &targets, options.fuzz, &mut mask, deadline)?,
+    };
+    debug!(cleared, ?points, mode = ?options.mode, fuzz = options.fuzz, "Background removal complete");
+
+    if options.feather > 0 && cleared > 0 {
+        let refined = feather_edges(bitmap, &mask, &targets, options.feather, deadline)?;
+        debug!(refined, feather = options.feather, "Edge feathering complete");
+    }
+    Ok(cleared)
+}
+
+/// Clears every pixel in the image within `fuzz` of any of the `targets`,
+/// marking cleared pixels in `mask`.
+///
+/// # Returns
+///
+/// The number of pixels made transparent, or `RenderError::Timeout` if the deadline passes.
+fn key_color_transparent(
+    bitmap: &mut Bitmap,
+    targets: &[[u8; 4]],
+    fuzz: f32,
+    mask: &mut [bool],
+    deadline: Option<Deadline>,
+) -> Result<usize, RenderError> {
+    let mut cleared = 0;
+    for (i, (pixel, masked)) in bitmap.data.chunks_exact_mut(4).zip(mask.iter_mut()).enumerate() {
+        check_deadline_every(deadline, i, "key")?;
+        let color = [pixel[0], pixel[1], pixel[2], pixel[3]];
+        if targets.iter().any(|&target| is_fuzzy_equivalent(color, target, fuzz)) {
+            pixel.copy_from_slice(&[0, 0, 0, 0]);
+            *masked = true;
+            cleared += 1;
+        }
+    }
+    Ok(cleared)
+}
+
+/// Refines pixels within `radius` (Chebyshev distance) of a cleared pixel by
+/// un-mixing the key color.
+///
+/// For each such pixel, every target color is tried as the background and the
+/// one yielding the lowest alpha wins. The pixel's alpha is multiplied by the
+/// recovered alpha and its color replaced by the recovered foreground color.
+///
+/// # Returns
+///
+/// The number of pixels whose alpha was reduced, or `RenderError::Timeout` if
+/// the deadline passes.
+fn feather_edges(
+    bitmap: &mut Bitmap,
+    mask: &[bool],
+    targets: &[[u8; 4]],
+    radius: u32,
+    deadline: Option<Deadline>,
+) -> Result<usize, RenderError> {
+    let (width, height) = (bitmap.width as usize, bitmap.height as usize);
+
+    // Multi-source BFS from the cleared pixels, 8-connected so the distance is
+    // the Chebyshev distance, stopping at `radius`.
+    let mut distance = vec![u32::MAX; width * height];
+    let mut frontier: Vec<usize> = (0..mask.len()).filter(|&i| mask[i]).collect();
+    for &i in &frontier {
+        distance[i] = 0;
+    }
+    let mut visits = 0;
+    for step in 1..=radius {
+        let mut next = Vec::new();
+        for &i in &frontier {
+            check_deadline_every(deadline, visits, "feather")?;
+            visits += 1;
+            let (x, y) = ((i % width) as isize, (i / width) as isize);
+            for (dx, dy) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || ny < 0 || nx >= width as isize || ny >= height as isize {
+                    continue;
+                }
+                let n = ny as usize * width + nx as usize;
+                if distance[n] == u32::MAX {
+                    distance[n] = step;
+                    next.push(n);
+                }
+            }
+        }
+        frontier = next;
+    }
+
+    let mut refined = 0;
+    for (i, pixel) in bitmap.data.chunks_exact_mut(4).enumerate() {
+        check_deadline_every(deadline, i, "feather")?;
+        if distance[i] == 0 || distance[i] == u32::MAX || pixel[3] == 0 {
+            continue;
+        }
+        let color = [pixel[0], pixel[1], pixel[2]];
+        let best = targets
+            .iter()
+            .map(|t| unmix(color, [t[0], t[1], t[2]]))
+            .min_by(|a, b| a.0.total_cmp(&b.0));
+        let Some((alpha, foreground)) = best else {
+            continue;
+        };
+        if alpha >= 1.0 {
+            continue;
+        }
+        pixel[..3].copy_from_slice(&foreground);
+        pixel[3] = (pixel[3] as f32 * alpha).round() as u8;
+        refined += 1;
+    }
+    Ok(refined)
+}
+
+/// Recovers the smallest alpha and the matching foreground color such that
+/// `alpha * foreground + (1 - alpha) * background == color`.
+///
+/// This is the "color to alpha" decomposition: per channel, the alpha needed
+/// is the distance from the background towards the channel's extreme (0 or 1)
+/// in the direction of the observed color; the overall alpha is the maximum.
+fn unmix(color: [u8; 3], background: [u8; 3]) -> (f32, [u8; 3]) {
+    let c = color.map(|v| v as f32 / 255.0);
+    let b = background.map(|v| v as f32 / 255.0);
+
+    let mut alpha: f32 = 0.0;
+    for i in 0..3 {
+        let needed = if c[i] > b[i] {
+            (c[i] - b[i]) / (1.0 - b[i])
+        } else if c[i] < b[i] {
+            (b[i] - c[i]) / b[i]
+        } else {
+            0.0
+        };
+        alpha = alpha.max(needed);
+    }
+    if alpha <= f32::EPSILON {
+        return (0.0, color);
+    }
+
+    let foreground = std::array::from_fn(|i| {
+        let f = (c[i] - b[i]) / alpha + b[i];
+        (f.clamp(0.0, 1.0) * 255.0).round() as u8
+    });
+    (alpha.min(1.0), foreground)
+}
+
+/// Clears every pixel 4-connected to `seed` whose color is within `fuzz` of
+/// `target`, setting it to fully transparent black and marking it in `mask`.
+/// Nothing is cleared if the seed pixel itself does not match.
+///
+/// Pixels already marked in `mask` (e.g. by an earlier seed) are treated as
+/// boundaries, so `mask` doubles as the visited set. The fill works on
+/// horizontal spans: each cleared span queues the row above and below it
+/// rather than every neighbouring pixel, so the queue stays small compared
+/// to the image.
+///
+/// # Returns
+///
+/// The number of pixels made transparent, zero if the seed is out of bounds,
+/// or `RenderError::Timeout` if the deadline passes.
+fn flood_fill_transparent(
+    bitmap: &mut Bitmap,
+    seed: (u32, u32),
+    target: [u8; 4],
+    fuzz: f32,
+    mask: &mut [bool],
+    deadline: Option<Deadline>,
+) -> Result<usize, RenderError> {
+    let (width, height) = (bitmap.width, bitmap.height);
+    if seed.0 >= width || seed.1 >= height {
+        return Ok(0);
+    }
+    let fillable = |bitmap: &Bitmap, mask: &[bool], x: u32, y: u32| {
+        !mask[y as usize * width as usize + x as usize] && is_fuzzy_equivalent(bitmap.pixel(x, y), target, fuzz)
+    };
+
+    // Each entry is a run of candidate pixels `x1..=x2` in row `y`.
+    let mut spans = vec![(seed.0, seed.0, seed.1)];
+    let mut cleared = 0;
+    let mut visits = 0;
+    while let Some((x1, x2, y)) = spans.pop() {
+        let mut x = x1;
+        while x <= x2 {
+            check_deadline_every(deadline, visits, "flood fill")?;
+            visits += 1;
+            if !fillable(bitmap, mask, x, y) {
+                x += 1;
+                continue;
+            }
+
+            // Extend the span as far as it matches in both directions.
+            let mut left = x;
+            while left > 0 && fillable(bitmap, mask, left - 1, y) {
+                left -= 1;
+            }
+            let mut right = x;
+            while right + 1 < width && fillable(bitmap, mask, right + 1, y) {
+                right += 1;
+            }
+            visits += (right - left) as usize;
+
+            let row = y as usize * width as usize;
+            for px in left..=right {
+                let i = bitmap.index(px, y);
+                bitmap.data[i..i + 4].copy_from_slice(&[0, 0, 0, 0]);
+                mask[row + px as usize] = true;
+            }
+            cleared += (right - left + 1) as usize;
+
+            if y > 0 {
+                spans.push((left, right, y - 1));
+            }
+            if y + 1 < height {
+                spans.push((left, right, y + 1));
+            }
+            // `right + 1` is a boundary, so the next candidate is after it.
+            x = right + 2;
+        }
+    }
+    debug!(cleared, ?seed, ?target, fuzz, "Flood fill complete");
+    Ok(cleared)
+}
+
+/// Checks `deadline` on every [`DEADLINE_CHECK_INTERVAL`]th iteration of a
+/// per-pixel loop, starting with the first.
+fn check_deadline_every(deadline: Option<Deadline>, iteration: usize, phase: &str) -> Result<(), RenderError> {
+    match deadline {
+        Some(deadline) if iteration.is_multiple_of(DEADLINE_CHECK_INTERVAL) => deadline.check(phase),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 5x5 near-white bitmap with a 3x3 red square in the middle.
+    fn framed_bitmap() -> Bitmap {
+        let mut data = Vec::new();
+        for y in 0..5 {
+            for x in 0..5 {
+                if (1..4).contains(&x) && (1..4).contains(&y) {
+                    data.extend_from_slice(&[255, 0, 0, 255]);
+                } else {
+                    // Slightly off-white so the fuzz tolerance is exercised.
+                    data.extend_from_slice(&[255 - (x as u8 % 2) * 5, 255, 255, 255]);
+                }
+            }
+        }
+        Bitmap { width: 5, height: 5, data, pixel_dims: None }
+    }
+
+    #[test]
+    fn test_fuzzy_equivalence_matches_imagemagick_scale() {
+        // 5% fuzz allows a per-channel RMS difference of ~12.75 levels.
+        assert!(is_fuzzy_equivalent([255, 255, 255, 255], [245, 245, 245, 255], 0.05));
+        assert!(!is_fuzzy_equivalent([255, 255, 255, 255], [235, 235, 235, 255], 0.05));
+        assert!(is_fuzzy_equivalent([255, 0, 0, 0], [0, 255, 0, 0], 0.05));
+        assert!(!is_fuzzy_equivalent([255, 255, 255, 255], [255, 255, 255, 0], 0.05));
+    }
+
+    #[test]
+    fn test_flood_fill_stops_at_boundary() {
+        let mut bitmap = framed_bitmap();
+        let cleared = remove_background(&mut bitmap, &KeyOptions::default()).unwrap();
+        assert_eq!(cleared, 16);
+        assert_eq!(bitmap.pixel(0, 0), [0, 0, 0, 0]);
+        assert_eq!(bitmap.pixel(2, 2), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_zero_fuzz_requires_exact_match() {
+        let mut bitmap = framed_bitmap();
+        let mut mask = vec![false; 25];
+        flood_fill_transparent(&mut bitmap, (0, 0), [255, 255, 255, 255], 0.0, &mut mask, None).unwrap();
+        // Only the exact-white pixels connected to (0, 0) are cleared.
+        assert_eq!(bitmap.pixel(0, 1)[3], 0);
+        assert_eq!(bitmap.pixel(1, 0)[3], 255);
+    }
+
+    #[test]
+    fn test_flood_fill_follows_winding_spans() {
+        // White with red walls; `.` is reachable from the top-left corner only
+        // by going down and around, and `o` is enclosed.
+        let rows = ["...#o#o", "...##o#", "...#.#.", "...#...", "......."];
+        let data = rows
+            .iter()
+            .flat_map(|row| row.chars())
+            .flat_map(|c| if c == '#' { [255, 0, 0, 255] } else { [255, 255, 255, 255] })
+            .collect();
+        let mut bitmap = Bitmap { width: 7, height: 5, data, pixel_dims: None };
+        let mut mask = vec![false; 35];
+        let white = [255, 255, 255, 255];
+
+        assert_eq!(flood_fill_transparent(&mut bitmap, (0, 0), white, 0.0, &mut mask, None), Ok(24));
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                assert_eq!(mask[y * 7 + x], c == '.', "pixel {},{}", x, y);
+            }
+        }
+        // A seed inside the cleared area adds nothing; one in an enclosed area only fills that.
+        assert_eq!(flood_fill_transparent(&mut bitmap, (6, 4), white, 0.0, &mut mask, None), Ok(0));
+        assert_eq!(flood_fill_transparent(&mut bitmap, (4, 0), white, 0.0, &mut mask, None), Ok(1));
+    }
+
+    #[test]
+    fn test_global_key_ignores_connectivity() {
+        let mut bitmap = framed_bitmap();
+        let options = KeyOptions {
+            color: Some([255, 0, 0, 255]),
+            mode: KeyMode::Global,
+            ..KeyOptions::default()
+        };
+        assert_eq!(remove_background(&mut bitmap, &options).unwrap(), 9);
+        assert_eq!(bitmap.pixel(2, 2)[3], 0);
+        assert_eq!(bitmap.pixel(0, 0)[3], 255);
+    }
+
+    #[test]
+    fn test_keying_loops_stop_at_deadline() {
+        let expired = Some(Deadline::after(std::time::Duration::ZERO));
+        let timeout = || Err(RenderError::Timeout { timeout_ms: 0 });
+        let white = [255, 255, 255, 255];
+        let mut bitmap = framed_bitmap();
+        let mut mask = vec![false; 25];
+        assert_eq!(flood_fill_transparent(&mut bitmap, (0, 0), white, 0.05, &mut mask, expired), timeout());
+        assert_eq!(key_color_transparent(&mut bitmap, &[white], 0.05, &mut mask, expired), timeout());
+        mask[0] = true;
+        assert_eq!(feather_edges(&mut bitmap, &mask, &[white], 1, expired), timeout());
+        assert_eq!(bitmap.data, framed_bitmap().data);
+    }
+
+    #[test]
+    fn test_seeds_parse_and_validate() {
+        assert_eq!("3, 4".parse::<Seed>(), Ok(Seed::Point(3, 4)));
+        assert_eq!("corners".parse::<Seed>(), Ok(Seed::Corners));
+        assert!("3".parse::<Seed>().is_err());
+
+        let mut bitmap = framed_bitmap();
+        let options = KeyOptions { seeds: vec![Seed::Point(5, 0)], ..KeyOptions::default() };
+        assert_eq!(remove_background(&mut bitmap, &options).unwrap_err().code(), "invalid_parameter");
+    }
+
+    #[test]
+    fn test_unmix_recovers_blended_edge() {
+        // 50% black over white.
+        let (alpha, foreground) = unmix([128, 128, 128], [255, 255, 255]);
+        assert!((alpha - 127.0 / 255.0).abs() < 1e-4, "alpha: {}", alpha);
+        assert_eq!(foreground, [0, 0, 0]);
+        // Pure background is fully transparent.
+        assert_eq!(unmix([255, 255, 255], [255, 255, 255]).0, 0.0);
+    }
+
+    #[test]
+    fn test_feather_softens_edge_pixels_only() {
+        // White background, a gray anti-aliased ring around a black 1x1 core.
+        let mut data = Vec::new();
+        for y in 0..5u32 {
+            for x in 0..5u32 {
+                let d = x.abs_diff(2).max(y.abs_diff(2));
+                let v = [0, 128, 255][d.min(2) as usize];
+                data.extend_from_slice(&[v, v, v, 255]);
+            }
+        }
+        let mut bitmap = Bitmap { width: 5, height: 5, data, pixel_dims: None };
+        let options = KeyOptions { feather: 1, ..KeyOptions::default() };
+        remove_background(&mut bitmap, &options).unwrap();
+
+        assert_eq!(bitmap.pixel(0, 0)[3], 0);
+        // The gray ring becomes half-transparent black; the core is untouched.
+        assert_eq!(bitmap.pixel(1, 1), [0, 0, 0, 127]);
+        assert_eq!(bitmap.pixel(2, 2), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_png_round_trip_preserves_density() {
+        let mut bitmap = framed_bitmap();
+        bitmap.pixel_dims = Some(png::PixelDimensions { xppu: 11811, yppu: 11811, unit: png::Unit::Meter });
+        let decoded = decode_png(&encode_png(&bitmap).unwrap(), &Limits::default()).unwrap();
+        assert_eq!(decoded.data, bitmap.data);
+        let dims = decoded.pixel_dims.unwrap();
+        assert_eq!((dims.xppu, dims.yppu, dims.unit), (11811, 11811, png::Unit::Meter));
+    }
+}